@@ -1,246 +1,9552 @@
 package main
 
 import (
+	"archive/zip"
+	"bufio"
+	"bytes"
+	"cmp"
 	"context"
+	"crypto/hmac"
+	"crypto/rand"
 	"crypto/sha1"
+	"crypto/sha256"
+	"embed"
 	"encoding/base64"
+	"encoding/binary"
+	"encoding/csv"
+	"encoding/hex"
 	"encoding/json"
+	"encoding/xml"
 	"errors"
+	"flag"
 	"fmt"
 	"github.com/patrickmn/go-cache"
+	"image"
+	"image/color"
+	"image/draw"
+	"image/jpeg"
+	_ "image/png"
+	"io"
+	"io/fs"
 	"log"
+	"math"
+	"mime"
+	"mime/multipart"
+	"net"
 	"net/http"
+	"net/smtp"
+	"net/url"
 	"os"
+	"os/exec"
+	"os/signal"
+	"path"
+	"path/filepath"
+	"reflect"
 	"regexp"
+	"runtime"
+	"slices"
+	"sort"
+	"strconv"
 	"strings"
+	"sync"
+	"sync/atomic"
+	"syscall"
 	"time"
+	"unicode"
+	"unicode/utf8"
 
 	"cloud.google.com/go/storage"
 	"github.com/eknkc/pug"
+	"golang.org/x/crypto/bcrypt"
+	"golang.org/x/text/collate"
+	"golang.org/x/text/language"
+	"google.golang.org/api/googleapi"
 	"google.golang.org/api/iterator"
+	"google.golang.org/api/monitoring/v3"
+	"google.golang.org/api/option"
 )
 
 type Category struct {
 	Name      string    `json:"name"`
 	Stub      string    `json:"stub"`
+	Hidden    bool      `json:"-"`
 	Galleries []Gallery `json:"galleries"`
+	// Index page anchor, gallery count and link to the whole category, set by the index layout
+	Anchor  string `json:"-"`
+	Count   int    `json:"-"`
+	ViewAll string `json:"-"`
 }
 
 type Gallery struct {
-	Name     string  `json:"name"`
-	Category string  `json:"category"`
-	Stub     string  `json:"-"`
-	Videos   []Video `json:"videos"`
+	assets
+	Name       string   `json:"name"`
+	Category   string   `json:"category"`
+	Stub       string   `json:"-"`
+	LegacyStub string   `json:"-"`
+	Cover      *string  `json:"cover,omitempty"`
+	Tags       []string `json:"tags,omitempty"`
+	Videos     []Video  `json:"videos"`
+	// Gallery page link sorted by views, keeping any signature
+	PopularLink string `json:"-"`
+	// Where the page continues loading videos from, empty when they all fit
+	NextCursor string `json:"-"`
+	// ZIP download link keeping any signature, empty when downloads are off
+	DownloadLink string `json:"-"`
+	// Absolute page url, preview image and description for link previews in chat apps
+	PreviewUrl         string `json:"-"`
+	PreviewImage       string `json:"-"`
+	PreviewDescription string `json:"-"`
 }
 
 type Video struct {
+	Id            string  `json:"id"`
+	Name          string  `json:"name"`
+	Category      string  `json:"-"`
+	Gallery       string  `json:"-"`
+	Url           string  `json:"url"`
+	VideoPath     string  `json:"-"`
+	Thumbnail     *string `json:"thumbnail,omitempty"`
+	ThumbnailPath string  `json:"-"`
+	MediaType     string  `json:"media_type"`
+	Playable      bool    `json:"playable"`
+	// For badges, the duration, resolution and frame rate are only known once ffprobe has read the video
+	Size            int64   `json:"size,omitempty"`
+	SizeDisplay     string  `json:"size_display,omitempty"`
+	Duration        float64 `json:"duration,omitempty"`
+	DurationDisplay string  `json:"duration_display,omitempty"`
+	Resolution      string  `json:"resolution,omitempty"`
+	Fps             float64 `json:"fps,omitempty"`
+	// Which of the video's renditions the url is for, when it has more than one
+	Rendition     string    `json:"rendition,omitempty"`
+	Chapters      []Chapter `json:"chapters,omitempty"`
+	Subtitles     *string   `json:"subtitles,omitempty"`
+	SubtitlesPath string    `json:"-"`
+	Preview       *string   `json:"preview,omitempty"`
+	PreviewPath   string    `json:"-"`
+	Views         int       `json:"views"`
+	Tags          []string  `json:"tags,omitempty"`
+	Created       time.Time `json:"-"`
+	Captured      time.Time `json:"-"`
+	Locked        bool      `json:"-"`
+	// Left out of public pages and feeds, but still shown to admins
+	Hidden bool `json:"-"`
+	// Resized thumbnail for grids, served by the thumbnail proxy
+	ThumbnailSmall   string `json:"-"`
+	thumbnailVersion int64
+	videoVersion     int64
+	// CRC32C of the thumbnail, for urls named by its content
+	thumbnailHash string
+	// Object names keyed by rendition, when the video was uploaded in more than one format
+	renditions map[string]string
+	// Place in the gallery's custom order, zero when it has none
+	position int
+}
+
+type Chapter struct {
+	Start float64 `json:"start"`
+	Title string  `json:"title"`
+	Url   string  `json:"-"`
+}
+
+func (v Video) IsImage() bool {
+	return v.MediaType == "image"
+}
+
+// NeedsTranscode flags videos in containers browsers can't play
+func (v Video) NeedsTranscode() bool {
+	return !v.IsImage() && !v.Playable
+}
+
+func (v *Video) setProbe(probe VideoProbe) {
+	v.Duration = probe.Duration
+	v.DurationDisplay = formatDuration(probe.Duration)
+	v.Resolution = getResolution(probe.Width, probe.Height)
+	v.Fps = probe.Fps
+}
+
+// TakenAt is the EXIF capture date of photos, or when the file was uploaded
+func (v Video) TakenAt() time.Time {
+	if !v.Captured.IsZero() {
+		return v.Captured
+	}
+	return v.Created
+}
+
+// Key identifies the video in the metadata store
+func (v Video) Key() string {
+	return v.Category + "/" + v.Gallery + "/" + v.Name
+}
+
+func (g Gallery) getViews() int {
+	views := 0
+	for _, video := range g.Videos {
+		views += video.Views
+	}
+	return views
+}
+
+type Tenant struct {
+	Host       string `json:"-"`
+	BucketName string `json:"bucket"`
+	SecretKey  string `json:"secret_key"`
+
+	videoCache   *cache.Cache
+	staleLibrary *Library
+	refreshCall  *refreshCall
+	refreshError error
+	refreshStats refreshStats
+	refreshMutex sync.Mutex
+
+	suggestions  *suggestIndex
+	suggestMutex sync.Mutex
+
+	metadata         *Metadata
+	metadataMutex    sync.Mutex
+	pendingViews     map[string]int
+	pendingClicks    map[string]int
+	pendingAnalytics map[string]VideoAnalytics
+	viewsMutex       sync.Mutex
+
+	// Set while generated covers are being drawn
+	collagesRunning atomic.Bool
+	// Set while new videos are being probed
+	probesRunning atomic.Bool
+
+	// Open /api/events streams waiting for library changes
+	subscribers      map[chan []LibraryEvent]struct{}
+	subscribersMutex sync.Mutex
+
+	// Previews and subtitles being generated, and those finished in the last hour
+	jobs      map[string]*Job
+	jobsMutex sync.Mutex
+	// Running transcodes by the object they write, so viewers of the same video share one
+	transcodes      map[string]*Job
+	transcodesMutex sync.Mutex
+}
+
+// refreshStats adds up how long listing the bucket has taken since the server started
+type refreshStats struct {
+	count    int
+	failures int
+	total    time.Duration
+	longest  time.Duration
+}
+
+// refreshCall is a bucket listing in progress that other requests can wait for
+type refreshCall struct {
+	done    chan struct{}
+	library Library
+	err     error
+}
+
+type Metadata struct {
+	// View counts keyed by video key
+	Views map[string]int `json:"views,omitempty"`
+	// EXIF capture dates of photos keyed by video key, zero when there is none
+	Captured map[string]time.Time `json:"captured,omitempty"`
+	// Tags keyed by video key, or by category and gallery name for a whole gallery
+	Tags map[string][]string `json:"tags,omitempty"`
+	// API tokens keyed by the sha256 hash of the token
+	ApiTokens map[string]ApiToken `json:"api_tokens,omitempty"`
+	// Salted password hashes keyed by video key, or by category and gallery name
+	Passwords map[string]string `json:"passwords,omitempty"`
+	// Short links keyed by their code
+	ShortLinks map[string]ShortLink `json:"short_links,omitempty"`
+	// Keys signing guest links keyed by category and gallery name
+	GalleryKeys map[string]string `json:"gallery_keys,omitempty"`
+	// Video keys in each collection, keyed by the collection name
+	Collections map[string][]string `json:"collections,omitempty"`
+	// When the last digest email was sent
+	DigestSent *time.Time `json:"digest_sent,omitempty"`
+	// Custom video order keyed by category and gallery name
+	VideoOrder map[string][]string `json:"video_order,omitempty"`
+	// Keys of videos hidden from the public
+	HiddenVideos []string `json:"hidden_videos,omitempty"`
+	// Content ratings keyed by category, or by category and gallery name
+	Ratings map[string]string `json:"ratings,omitempty"`
+	// Player events keyed by video key
+	Analytics map[string]VideoAnalytics `json:"analytics,omitempty"`
+	// Hash of the thumbnails each generated cover was made from, keyed by category and gallery name
+	Collages map[string]string `json:"collages,omitempty"`
+	// What ffprobe found in each video, keyed by video key
+	Probes map[string]VideoProbe `json:"probes,omitempty"`
+	// Set while the public pages are down for maintenance
+	Maintenance *Maintenance `json:"maintenance,omitempty"`
+	// When an instance took on sending each webhook event, keyed by the event and its video
+	WebhookClaims map[string]time.Time `json:"webhook_claims,omitempty"`
+}
+
+// Maintenance takes the public pages offline while the library is being reorganized
+type Maintenance struct {
+	Message string     `json:"message,omitempty"`
+	Since   time.Time  `json:"since"`
+	Until   *time.Time `json:"until,omitempty"`
+}
+
+// VideoProbe is what ffprobe found in a version of a video, all zero when it couldn't read it
+type VideoProbe struct {
+	Generation int64   `json:"generation"`
+	Duration   float64 `json:"duration,omitempty"`
+	Width      int     `json:"width,omitempty"`
+	Height     int     `json:"height,omitempty"`
+	Fps        float64 `json:"fps,omitempty"`
+}
+
+// VideoAnalytics counts what happened while a video was played in the gallery player
+type VideoAnalytics struct {
+	Plays     int `json:"plays,omitempty"`
+	Pauses    int `json:"pauses,omitempty"`
+	Completes int `json:"completes,omitempty"`
+	// Playback errors keyed by browser
+	Errors map[string]int `json:"errors,omitempty"`
+}
+
+func (a VideoAnalytics) add(other VideoAnalytics) VideoAnalytics {
+	a.Plays += other.Plays
+	a.Pauses += other.Pauses
+	a.Completes += other.Completes
+	for browser, count := range other.Errors {
+		if a.Errors == nil {
+			a.Errors = make(map[string]int)
+		}
+		a.Errors[browser] += count
+	}
+	return a
+}
+
+type Library struct {
+	Videos []Video
+	// Signed cover image urls keyed by category and gallery name
+	Covers map[string]string
+	// Galleries created through the API, keyed by category and gallery name
+	Placeholders []string
+	// Bytes stored per gallery, keyed by category and gallery name
+	GalleryBytes map[string]int64
+	// Signed contact sheet urls keyed by category and gallery name
+	ContactSheets map[string]string
+	// Signed generated cover urls keyed by category and gallery name
+	Collages        map[string]string
+	TotalBytes      int64
+	RefreshedAt     time.Time
+	RefreshDuration time.Duration
+	// What the refresh found out about each object, keyed by object name
+	objects map[string]listedObject
+}
+
+// listedObject keeps the work done for an object, so the next refresh can skip it while the generation is the same
+type listedObject struct {
+	generation int64
+	crc32c     uint32
+	size       int64
+	created    time.Time
+	url        string
+	signed     time.Time
+	// Chapters without urls, only for chapter sidecars
+	chapters []Chapter
+}
+
+type Stats struct {
+	Categories        int     `json:"categories"`
+	Galleries         int     `json:"galleries"`
+	Videos            int     `json:"videos"`
+	Images            int     `json:"images"`
+	TotalBytes        int64   `json:"total_bytes"`
+	MissingThumbnails int     `json:"missing_thumbnails"`
+	CacheAgeSecs      float64 `json:"cache_age_secs"`
+	LastRefreshSecs   float64 `json:"last_refresh_secs"`
+	Refreshes         int     `json:"refreshes"`
+	FailedRefreshes   int     `json:"failed_refreshes"`
+	AvgRefreshSecs    float64 `json:"avg_refresh_secs"`
+	MaxRefreshSecs    float64 `json:"max_refresh_secs"`
+}
+
+// Metrics are the numbers worth alerting on, for operators without Prometheus
+type Metrics struct {
+	UptimeSecs       float64 `json:"uptime_secs"`
+	Requests         int64   `json:"requests"`
+	InFlightRequests int64   `json:"in_flight_requests"`
+	ClientErrors     int64   `json:"client_errors"`
+	ServerErrors     int64   `json:"server_errors"`
+	OpenStreams      int     `json:"open_streams"`
+	RunningJobs      int     `json:"running_jobs"`
+	PendingViews     int     `json:"pending_views"`
+	CacheAgeSecs     float64 `json:"cache_age_secs"`
+	LastRefreshSecs  float64 `json:"last_refresh_secs"`
+	Refreshes        int     `json:"refreshes"`
+	FailedRefreshes  int     `json:"failed_refreshes"`
+	Degraded         bool    `json:"degraded"`
+}
+
+// Job is a task started from the admin API that runs in the background, keeping its events for anyone following it
+type Job struct {
+	ID       string     `json:"id"`
+	Kind     string     `json:"kind"`
+	Status   string     `json:"status"`
+	Started  time.Time  `json:"started"`
+	Finished *time.Time `json:"finished,omitempty"`
+	Error    string     `json:"error,omitempty"`
+	Progress *JobEvent  `json:"progress,omitempty"`
+
+	events  []JobEvent
+	changed chan struct{}
+	cancel  context.CancelFunc
+	mutex   sync.Mutex
+}
+
+type JobEvent struct {
+	Event string `json:"event"`
+	Data  any    `json:"data"`
+}
+
+const (
+	jobRunning   = "running"
+	jobDone      = "done"
+	jobFailed    = "failed"
+	jobCancelled = "cancelled"
+	// How long finished jobs can still be looked up
+	jobRetention = time.Hour
+	// Working directories older than this are left over from a process that stopped
+	staleJobDirAge = 24 * time.Hour
+)
+
+// AnalyticsRow is a video in the analytics report
+type AnalyticsRow struct {
+	Category  string         `json:"category"`
+	Gallery   string         `json:"gallery"`
+	Video     string         `json:"video"`
+	Plays     int            `json:"plays"`
+	Pauses    int            `json:"pauses"`
+	Completes int            `json:"completes"`
+	Errors    int            `json:"errors"`
+	Browsers  map[string]int `json:"browsers"`
+}
+
+// GalleryCoverage counts how much of a gallery has been filled in
+type GalleryCoverage struct {
+	Category      string `json:"category"`
+	Gallery       string `json:"gallery"`
+	Videos        int    `json:"videos"`
+	Photos        int    `json:"photos"`
+	Bytes         int64  `json:"bytes"`
+	Cover         bool   `json:"cover"`
+	WithThumbnail int    `json:"with_thumbnail"`
+	WithChapters  int    `json:"with_chapters"`
+	WithTags      int    `json:"with_tags"`
+	Playable      int    `json:"playable"`
+	ContactSheet  string `json:"contact_sheet,omitempty"`
+	// Display values for the report page
+	Size           string `json:"-"`
+	ThumbnailLevel string `json:"-"`
+	ChaptersLevel  string `json:"-"`
+	TagsLevel      string `json:"-"`
+	PlayableLevel  string `json:"-"`
+}
+
+type CoverageReport struct {
+	assets
+	Galleries []GalleryCoverage
+}
+
+type Index struct {
+	assets
+	Categories []Category
+	Stats      Stats
+	// Set while the bucket can't be listed and older videos are shown
+	Degraded bool
+	// Set on category pages, which leave out the search and library stats
+	Scoped bool
+	// Letters linking to the first category starting with them
+	JumpBar     []IndexLetter
+	PopularLink string
+}
+
+// DebugPage shows a template next to the context it was given and what it rendered
+type DebugPage struct {
+	assets
+	Template string
+	Context  string
+	Output   string
+	Error    string
+	Source   []DebugLine
+}
+
+type DebugLine struct {
+	Number int
+	Text   string
+}
+
+type IndexLetter struct {
+	Letter string
+	Anchor string
+}
+
+type JsonFeed struct {
+	Version     string         `json:"version"`
+	Title       string         `json:"title"`
+	HomePageUrl string         `json:"home_page_url,omitempty"`
+	FeedUrl     string         `json:"feed_url"`
+	Items       []JsonFeedItem `json:"items"`
+}
+
+type JsonFeedItem struct {
+	Id            string               `json:"id"`
+	Url           string               `json:"url"`
+	Title         string               `json:"title"`
+	ContentText   string               `json:"content_text"`
+	Image         *string              `json:"image,omitempty"`
+	DatePublished *time.Time           `json:"date_published,omitempty"`
+	Tags          []string             `json:"tags"`
+	Attachments   []JsonFeedAttachment `json:"attachments"`
+}
+
+type JsonFeedAttachment struct {
+	Url      string `json:"url"`
+	MimeType string `json:"mime_type"`
+}
+
+type AuditEntry struct {
+	Time       time.Time         `json:"time"`
+	Actor      string            `json:"actor"`
+	Action     string            `json:"action"`
+	Parameters map[string]string `json:"parameters"`
+}
+
+type AuditPage struct {
+	Entries []AuditEntry `json:"entries"`
+	Next    string       `json:"next,omitempty"`
+}
+
+type LibraryEvent struct {
+	Event    string    `json:"event"`
+	Time     time.Time `json:"time"`
+	Bucket   string    `json:"bucket"`
+	Category string    `json:"category"`
+	Gallery  string    `json:"gallery"`
+	Video    string    `json:"video,omitempty"`
+}
+
+// GalleryEvent is a library change as sent to gallery pages, without the bucket name
+type GalleryEvent struct {
+	Event    string `json:"event"`
+	Category string `json:"category"`
+	Gallery  string `json:"gallery"`
+	Video    string `json:"video,omitempty"`
+}
+
+type ApiToken struct {
+	Name    string    `json:"name"`
+	Scope   string    `json:"scope"`
+	Created time.Time `json:"created"`
+}
+
+type CreatedApiToken struct {
+	Token string `json:"token"`
+	ApiToken
+}
+
+type ShortLink struct {
+	// Category and gallery name
+	Gallery string     `json:"gallery"`
+	Video   string     `json:"video,omitempty"`
+	Expires *time.Time `json:"expires,omitempty"`
+	Clicks  int        `json:"clicks"`
+}
+
+type CreatedShortLink struct {
+	Code string `json:"code"`
+	Url  string `json:"url"`
+	ShortLink
+}
+
+type GalleryLink struct {
+	Url     string    `json:"url"`
+	Expires time.Time `json:"expires"`
+}
+
+type UploadSession struct {
+	Url     string            `json:"url"`
+	Method  string            `json:"method"`
+	Headers map[string]string `json:"headers"`
+	Expires time.Time         `json:"expires"`
+	// The version the upload replaces
+	Archived string `json:"archived,omitempty"`
+}
+
+// MetadataRow is a video's editable metadata, as exported and imported in bulk
+type MetadataRow struct {
+	Category  string   `json:"category"`
+	Gallery   string   `json:"gallery"`
+	Video     string   `json:"video"`
+	Path      string   `json:"path"`
+	MediaType string   `json:"media_type"`
+	Views     int      `json:"views"`
+	Tags      []string `json:"tags"`
+	Hidden    bool     `json:"hidden"`
+}
+
+// metadataImportRow leaves out the columns that aren't given, so they aren't changed
+type metadataImportRow struct {
+	Category string    `json:"category"`
+	Gallery  string    `json:"gallery"`
+	Video    string    `json:"video"`
+	Tags     *[]string `json:"tags"`
+	Hidden   *bool     `json:"hidden"`
+}
+
+type MetadataImport struct {
+	DryRun  bool               `json:"dry_run"`
+	Rows    int                `json:"rows"`
+	Changed []string           `json:"changed"`
+	Errors  []MetadataRowError `json:"errors,omitempty"`
+}
+
+type MetadataRowError struct {
+	Row   int    `json:"row"`
+	Error string `json:"error"`
+}
+
+type VideoVersion struct {
+	Version  string    `json:"version"`
+	Bytes    int64     `json:"bytes"`
+	Archived time.Time `json:"archived"`
+}
+
+type Suggestion struct {
+	Type     string `json:"type"`
+	Name     string `json:"name"`
+	Category string `json:"category"`
+	Gallery  string `json:"gallery"`
+	Href     string `json:"href"`
+}
+
+// suggestIndex holds every word ending of every name, sorted so prefixes can be binary searched
+type suggestIndex struct {
+	refreshedAt time.Time
+	keys        []string
+	suggestions []Suggestion
+}
+
+type NextVideo struct {
+	Id        string  `json:"id"`
 	Name      string  `json:"name"`
-	Category  string  `json:"-"`
-	Gallery   string  `json:"-"`
 	Url       string  `json:"url"`
 	Thumbnail *string `json:"thumbnail,omitempty"`
+	// Served by the service, so the player can load them without CORS
+	Subtitles string `json:"subtitles,omitempty"`
 }
 
-type Index struct {
-	Categories []Category
+// VideoPage is one page of a gallery for infinite scroll
+type VideoPage struct {
+	Videos     []PageVideo `json:"videos"`
+	NextCursor string      `json:"next_cursor,omitempty"`
+}
+
+type PageVideo struct {
+	Id        string  `json:"id"`
+	Name      string  `json:"name"`
+	Url       string  `json:"url"`
+	Thumbnail string  `json:"thumbnail,omitempty"`
+	MediaType string  `json:"media_type"`
+	Playable  bool    `json:"playable"`
+	Locked    bool    `json:"locked,omitempty"`
+	Preview   *string `json:"preview,omitempty"`
+	Rendition string  `json:"rendition,omitempty"`
+	// Badges, left out until the video has been probed
+	DurationDisplay string        `json:"duration_display,omitempty"`
+	Resolution      string        `json:"resolution,omitempty"`
+	Chapters        []PageChapter `json:"chapters,omitempty"`
+}
+
+type PageChapter struct {
+	Title string `json:"title"`
+	Url   string `json:"url"`
+}
+
+type SignedUrls struct {
+	Url       string  `json:"url"`
+	Thumbnail *string `json:"thumbnail,omitempty"`
+}
+
+type HealthCheck struct {
+	Status  string `json:"status"`
+	Message string `json:"message,omitempty"`
+}
+
+type Health struct {
+	Status   string                 `json:"status"`
+	Degraded bool                   `json:"degraded"`
+	Checks   map[string]HealthCheck `json:"checks"`
+}
+
+type ConfigSetting struct {
+	Name    string `json:"name"`
+	Value   string `json:"value,omitempty"`
+	Source  string `json:"source"`
+	Restart bool   `json:"restart,omitempty"`
+}
+
+type ConfigReload struct {
+	Changed  []string `json:"changed"`
+	Rejected []string `json:"rejected,omitempty"`
+	Problems []string `json:"problems,omitempty"`
+}
+
+type PasswordPage struct {
+	assets
+	Title   string
+	Message string
+}
+
+type GatePage struct {
+	assets
+	Title   string
+	Message string
+	Rating  string
+	// Without a PIN the gate only asks for a confirmation
+	NeedsPin bool
+}
+
+type MaintenancePage struct {
+	assets
+	Message string
+	Until   string
+}
+
+type ErrorPage struct {
+	assets
+	Status  int    `json:"status"`
+	Title   string `json:"error"`
+	Message string `json:"message"`
+}
+
+// AdminGallery adds the bucket object names to a gallery, for secret key routes only
+type AdminGallery struct {
+	Gallery
+	Videos []AdminVideo `json:"videos"`
+}
+
+// AdminVideo shows the object names that Video keeps out of public JSON
+type AdminVideo struct {
+	Video
+	VideoPath     string `json:"video_path"`
+	ThumbnailPath string `json:"thumbnail_path,omitempty"`
+	Hidden        bool   `json:"hidden,omitempty"`
+}
+
+func toAdminGallery(gallery Gallery) AdminGallery {
+	admin := AdminGallery{Gallery: gallery, Videos: []AdminVideo{}}
+	for _, video := range gallery.Videos {
+		admin.Videos = append(admin.Videos, AdminVideo{
+			Video:         video,
+			VideoPath:     video.VideoPath,
+			ThumbnailPath: video.ThumbnailPath,
+			Hidden:        video.Hidden,
+		})
+	}
+	return admin
+}
+
+type CatalogEntry struct {
+	Name  string  `json:"name"`
+	Href  string  `json:"href"`
+	Count int     `json:"count"`
+	Cover *string `json:"cover,omitempty"`
+}
+
+const (
+	stubLength = 12
+	// Length of an unpadded base64 sha1 hash
+	maxStubLength = 27
+)
+
+func (t *Tenant) getCategories() []Category {
+	return groupCategories(t.getGalleries())
+}
+
+// getPublicCategories leaves out hidden categories
+func (t *Tenant) getPublicCategories() []Category {
+	return groupCategories(t.getPublicGalleries())
+}
+
+func groupCategories(galleries []Gallery) []Category {
+	var categories []Category
+	for _, gallery := range galleries {
+		category := gallery.Category
+		// Check if category already exists
+		exists := false
+		for i, c := range categories {
+			if c.Name == category {
+				categories[i].Galleries = append(categories[i].Galleries, gallery)
+				exists = true
+				break
+			}
+		}
+		if !exists {
+			categories = append(categories, Category{
+				Name:      category,
+				Stub:      category,
+				Hidden:    isCategoryHidden(category),
+				Galleries: []Gallery{gallery},
+			})
+		}
+	}
+	return categories
+}
+
+func (t *Tenant) getGallery(stub string) (Gallery, error) {
+	// Get gallery
+	for _, gallery := range t.getGalleries() {
+		if gallery.Stub == stub {
+			return gallery, nil
+		}
+	}
+	return Gallery{}, fmt.Errorf("gallery not found")
+}
+
+// getVideoGallery finds the gallery a video is in
+func (t *Tenant) getVideoGallery(video Video) (Gallery, error) {
+	for _, gallery := range t.getGalleries() {
+		if gallery.Category == video.Category && gallery.Name == video.Gallery {
+			return gallery, nil
+		}
+	}
+	return Gallery{}, fmt.Errorf("gallery not found")
 }
 
-func getCategories() []Category {
-	var categories []Category
-	for _, gallery := range getGalleries() {
-		category := gallery.Category
-		// Check if category already exists
-		exists := false
-		for i, c := range categories {
-			if c.Name == category {
-				categories[i].Galleries = append(categories[i].Galleries, gallery)
-				exists = true
-				break
+func (t *Tenant) getLegacyGallery(stub string) (Gallery, error) {
+	// Only redirect legacy stubs that still point at a single gallery
+	var matches []Gallery
+	for _, gallery := range t.getGalleries() {
+		if gallery.LegacyStub == stub {
+			matches = append(matches, gallery)
+		}
+	}
+	if len(matches) != 1 {
+		return Gallery{}, fmt.Errorf("gallery not found")
+	}
+	return matches[0], nil
+}
+
+func getHash(value string, secretKey string, length int) string {
+	// Generate Hash
+	hash := sha1.New()
+	hash.Write([]byte(value + secretKey))
+	return base64.URLEncoding.EncodeToString(hash.Sum(nil))[0:length]
+}
+
+func getStub(value string, secretKey string, length int) string {
+	return "/gallery/" + getHash(value, secretKey, length)
+}
+
+func isCategoryHidden(category string) bool {
+	return slices.Contains(getList("HIDDEN_CATEGORIES"), category) || isCategoryAdminOnly(category)
+}
+
+func isCategoryAdminOnly(category string) bool {
+	return slices.Contains(getList("ADMIN_ONLY_CATEGORIES"), category)
+}
+
+// getCategoryKeys maps category names to their own secret keys from CATEGORY_KEYS
+func getCategoryKeys() map[string]string {
+	keys := map[string]string{}
+	if keysJson := os.Getenv("CATEGORY_KEYS"); keysJson != "" {
+		if err := json.Unmarshal([]byte(keysJson), &keys); err != nil {
+			panic("CATEGORY_KEYS is not valid JSON: " + err.Error())
+		}
+	}
+	return keys
+}
+
+func getCategoryRank(category string) int {
+	order := getList("CATEGORY_ORDER")
+	if rank := slices.Index(order, category); rank >= 0 {
+		return rank
+	}
+	return len(order)
+}
+
+func (t *Tenant) getPublicGalleries() []Gallery {
+	var galleries []Gallery
+	for _, gallery := range t.getGalleries() {
+		if !isCategoryHidden(gallery.Category) {
+			galleries = append(galleries, gallery)
+		}
+	}
+	return galleries
+}
+
+// withoutHidden leaves out videos hidden from the public
+func withoutHidden(videos []Video) []Video {
+	return slices.DeleteFunc(slices.Clone(videos), func(video Video) bool { return video.Hidden })
+}
+
+func withoutHiddenVideos(galleries []Gallery) []Gallery {
+	filtered := []Gallery{}
+	for _, gallery := range galleries {
+		gallery.Videos = withoutHidden(gallery.Videos)
+		filtered = append(filtered, gallery)
+	}
+	return filtered
+}
+
+func (t *Tenant) getGalleries() []Gallery {
+	library := t.getLibrary()
+	metadata := t.getMetadata()
+	secretKey := t.SecretKey
+
+	newGallery := func(category string, gallery string, videos []Video) Gallery {
+		var cover *string
+		if coverUrl, ok := library.Covers[category+"/"+gallery]; ok {
+			cover = &coverUrl
+		} else if collageUrl, ok := library.Collages[category+"/"+gallery]; ok {
+			cover = &collageUrl
+		}
+		return Gallery{
+			Name:       gallery,
+			Category:   category,
+			Stub:       getStub(category+"/"+gallery, secretKey, stubLength),
+			LegacyStub: getStub(gallery, secretKey, 4),
+			Cover:      cover,
+			Tags:       metadata.Tags[category+"/"+gallery],
+			Videos:     videos,
+		}
+	}
+
+	var galleries []Gallery
+	for _, video := range library.Videos {
+		category := video.Category
+		gallery := video.Gallery
+		video.Views = metadata.Views[video.Key()]
+		video.Captured = metadata.Captured[video.Key()]
+		video.Tags = mergeTags(metadata.Tags[category+"/"+gallery], metadata.Tags[video.Key()])
+		video.Hidden = slices.Contains(metadata.HiddenVideos, video.Key())
+		if probe, ok := metadata.Probes[video.Key()]; ok && probe.Generation == video.videoVersion && !video.IsImage() {
+			video.setProbe(probe)
+		}
+		// Check if gallery already exists
+		exists := false
+		for i, g := range galleries {
+			if g.Category == category && g.Name == gallery {
+				galleries[i].Videos = append(galleries[i].Videos, video)
+				exists = true
+				break
+			}
+		}
+		if !exists {
+			galleries = append(galleries, newGallery(category, gallery, []Video{video}))
+		}
+	}
+
+	// Galleries created through the API are listed before anything is uploaded
+	for _, key := range library.Placeholders {
+		category, gallery, _ := strings.Cut(key, "/")
+		if !slices.ContainsFunc(galleries, func(g Gallery) bool { return g.Category == category && g.Name == gallery }) {
+			galleries = append(galleries, newGallery(category, gallery, []Video{}))
+		}
+	}
+
+	// Lengthen colliding stubs until every gallery has its own
+	for length := stubLength + 1; length <= maxStubLength; length++ {
+		stubCounts := make(map[string]int)
+		for _, gallery := range galleries {
+			stubCounts[gallery.Stub]++
+		}
+		collision := false
+		for i, gallery := range galleries {
+			if stubCounts[gallery.Stub] > 1 {
+				log.Println("Gallery stub collision: " + gallery.Stub)
+				galleries[i].Stub = getStub(gallery.Category+"/"+gallery.Name, secretKey, length)
+				collision = true
+			}
+		}
+		if !collision {
+			break
+		}
+	}
+
+	// Pinned categories first, then by name
+	sort.SliceStable(galleries, func(i, j int) bool {
+		a, b := galleries[i], galleries[j]
+		if getCategoryRank(a.Category) != getCategoryRank(b.Category) {
+			return getCategoryRank(a.Category) < getCategoryRank(b.Category)
+		}
+		if a.Category != b.Category {
+			return compareNames(a.Category, b.Category) < 0
+		}
+		return compareNames(a.Name, b.Name) < 0
+	})
+
+	// Videos follow the custom order, or their names
+	for _, gallery := range galleries {
+		order := metadata.VideoOrder[gallery.Category+"/"+gallery.Name]
+		for i, video := range gallery.Videos {
+			if index := slices.Index(order, video.Name); index >= 0 {
+				gallery.Videos[i].position = index + 1
+			} else if len(order) > 0 {
+				gallery.Videos[i].position = len(order) + 1
+			}
+		}
+		sortVideos(gallery.Videos, "")
+	}
+	return galleries
+}
+
+func mergeTags(tags ...[]string) []string {
+	var merged []string
+	for _, list := range tags {
+		for _, tag := range list {
+			if !slices.Contains(merged, tag) {
+				merged = append(merged, tag)
+			}
+		}
+	}
+	return merged
+}
+
+func (t *Tenant) getTaggedVideos(tag string) []Video {
+	var videos []Video
+	for _, gallery := range t.getGalleries() {
+		for _, video := range gallery.Videos {
+			if slices.Contains(video.Tags, tag) {
+				videos = append(videos, video)
+			}
+		}
+	}
+	return videos
+}
+
+// Collections are listed as galleries of this category
+const collectionCategory = "Collections"
+
+func getCollectionStub(name string, secretKey string) string {
+	return "/collection/" + getHash("collection/"+name, secretKey, stubLength)
+}
+
+// getCollections builds a gallery for every collection, leaving out videos that are gone or hidden
+func (t *Tenant) getCollections() []Gallery {
+	videos := make(map[string]Video)
+	for _, gallery := range withoutHiddenVideos(t.getPublicGalleries()) {
+		for _, video := range gallery.Videos {
+			videos[video.Key()] = video
+		}
+	}
+
+	collections := []Gallery{}
+	for name, keys := range t.getMetadata().Collections {
+		collection := Gallery{
+			Name:     name,
+			Category: collectionCategory,
+			Stub:     getCollectionStub(name, t.SecretKey),
+			Videos:   []Video{},
+		}
+		for _, key := range keys {
+			if video, ok := videos[key]; ok {
+				collection.Videos = append(collection.Videos, video)
+			}
+		}
+		collections = append(collections, collection)
+	}
+	sort.Slice(collections, func(i, j int) bool {
+		return compareNames(collections[i].Name, collections[j].Name) < 0
+	})
+	return collections
+}
+
+func (t *Tenant) getSuggestIndex() *suggestIndex {
+	library := t.getLibrary()
+	t.suggestMutex.Lock()
+	defer t.suggestMutex.Unlock()
+	if t.suggestions != nil && t.suggestions.refreshedAt.Equal(library.RefreshedAt) {
+		return t.suggestions
+	}
+
+	// Rebuilt once per library refresh
+	type entry struct {
+		key        string
+		suggestion Suggestion
+	}
+	var entries []entry
+	add := func(suggestion Suggestion) {
+		name := strings.ToLower(suggestion.Name)
+		for i := range name {
+			if i == 0 || strings.ContainsRune(" -_.", rune(name[i-1])) {
+				entries = append(entries, entry{key: name[i:], suggestion: suggestion})
+			}
+		}
+	}
+	for _, gallery := range t.getGalleries() {
+		add(Suggestion{Type: "gallery", Name: gallery.Name, Category: gallery.Category, Gallery: gallery.Name, Href: gallery.Stub})
+		for _, video := range gallery.Videos {
+			add(Suggestion{Type: video.MediaType, Name: video.Name, Category: gallery.Category, Gallery: gallery.Name, Href: gallery.Stub})
+		}
+	}
+	sort.SliceStable(entries, func(i, j int) bool {
+		return entries[i].key < entries[j].key
+	})
+
+	index := &suggestIndex{refreshedAt: library.RefreshedAt}
+	for _, entry := range entries {
+		index.keys = append(index.keys, entry.key)
+		index.suggestions = append(index.suggestions, entry.suggestion)
+	}
+	t.suggestions = index
+	return index
+}
+
+// suggest finds names with a word starting with the query
+func (index *suggestIndex) suggest(query string, limit int) []Suggestion {
+	query = strings.ToLower(strings.TrimSpace(query))
+	suggestions := []Suggestion{}
+	if query == "" {
+		return suggestions
+	}
+	for i := sort.SearchStrings(index.keys, query); i < len(index.keys) && len(suggestions) < limit; i++ {
+		if !strings.HasPrefix(index.keys[i], query) {
+			break
+		}
+		if !slices.Contains(suggestions, index.suggestions[i]) {
+			suggestions = append(suggestions, index.suggestions[i])
+		}
+	}
+	return suggestions
+}
+
+func (t *Tenant) getVideo(id string) (Video, error) {
+	for _, video := range t.getLibrary().Videos {
+		if video.Id == id {
+			return video, nil
+		}
+	}
+	return Video{}, fmt.Errorf("video not found")
+}
+
+func (t *Tenant) getStats() Stats {
+	library := t.getLibrary()
+	stats := Stats{
+		Categories:      len(t.getCategories()),
+		Galleries:       len(t.getGalleries()),
+		TotalBytes:      library.TotalBytes,
+		CacheAgeSecs:    time.Since(library.RefreshedAt).Seconds(),
+		LastRefreshSecs: library.RefreshDuration.Seconds(),
+	}
+	t.refreshMutex.Lock()
+	stats.Refreshes = t.refreshStats.count
+	stats.FailedRefreshes = t.refreshStats.failures
+	if t.refreshStats.count > 0 {
+		stats.AvgRefreshSecs = t.refreshStats.total.Seconds() / float64(t.refreshStats.count)
+	}
+	stats.MaxRefreshSecs = t.refreshStats.longest.Seconds()
+	t.refreshMutex.Unlock()
+	for _, video := range library.Videos {
+		if video.IsImage() {
+			stats.Images++
+			continue
+		}
+		stats.Videos++
+		if video.Thumbnail == nil {
+			stats.MissingThumbnails++
+		}
+	}
+	return stats
+}
+
+func (t *Tenant) getCoverage() []GalleryCoverage {
+	library := t.getLibrary()
+	var coverage []GalleryCoverage
+	for _, gallery := range t.getGalleries() {
+		row := GalleryCoverage{
+			Category:     gallery.Category,
+			Gallery:      gallery.Name,
+			Bytes:        library.GalleryBytes[gallery.Category+"/"+gallery.Name],
+			Cover:        gallery.Cover != nil,
+			ContactSheet: library.ContactSheets[gallery.Category+"/"+gallery.Name],
+		}
+		for _, video := range gallery.Videos {
+			if video.IsImage() {
+				row.Photos++
+				continue
+			}
+			row.Videos++
+			if video.Thumbnail != nil {
+				row.WithThumbnail++
+			}
+			if len(video.Chapters) > 0 {
+				row.WithChapters++
+			}
+			if len(video.Tags) > 0 {
+				row.WithTags++
+			}
+			if video.Playable {
+				row.Playable++
+			}
+		}
+		row.Size = formatBytes(row.Bytes)
+		row.ThumbnailLevel = coverageLevel(row.WithThumbnail, row.Videos)
+		row.ChaptersLevel = coverageLevel(row.WithChapters, row.Videos)
+		row.TagsLevel = coverageLevel(row.WithTags, row.Videos)
+		row.PlayableLevel = coverageLevel(row.Playable, row.Videos)
+		coverage = append(coverage, row)
+	}
+	return coverage
+}
+
+// coverageLevel picks the report cell colour, from green when complete to red when mostly missing
+func coverageLevel(count int, total int) string {
+	switch {
+	case total == 0 || count == total:
+		return "has-background-success-light"
+	case count*2 >= total:
+		return "has-background-warning-light"
+	default:
+		return "has-background-danger-light"
+	}
+}
+
+// formatDuration shows seconds like a player does, as 3:05 or 1:23:45
+func formatDuration(seconds float64) string {
+	if seconds <= 0 {
+		return ""
+	}
+	total := int(math.Round(seconds))
+	if total >= 3600 {
+		return fmt.Sprintf("%d:%02d:%02d", total/3600, total/60%60, total%60)
+	}
+	return fmt.Sprintf("%d:%02d", total/60, total%60)
+}
+
+// getResolution names a video's resolution by its shorter side, so portrait videos get the same badge
+func getResolution(width int, height int) string {
+	side := min(width, height)
+	switch {
+	case side <= 0:
+		return ""
+	case side >= 4320:
+		return "8K"
+	case side >= 2160:
+		return "4K"
+	case side >= 1440:
+		return "1440p"
+	case side >= 1080:
+		return "1080p"
+	case side >= 720:
+		return "720p"
+	case side >= 480:
+		return "480p"
+	}
+	return "SD"
+}
+
+func formatBytes(bytes int64) string {
+	const unit = 1024
+	if bytes < unit {
+		return fmt.Sprintf("%d B", bytes)
+	}
+	div, exp := int64(unit), 0
+	for n := bytes / unit; n >= unit; n /= unit {
+		div *= unit
+		exp++
+	}
+	return fmt.Sprintf("%.1f %cB", float64(bytes)/float64(div), "KMGTPE"[exp])
+}
+
+func sortGalleries(galleries []Gallery, order string) {
+	if order == "popular" {
+		sort.SliceStable(galleries, func(i, j int) bool {
+			return galleries[i].getViews() > galleries[j].getViews()
+		})
+	}
+}
+
+// videoCursor is a video's place in a sort order, so paging can continue after it even once it's gone
+type videoCursor struct {
+	Position int       `json:"p,omitempty"`
+	Views    int       `json:"v"`
+	Taken    time.Time `json:"t"`
+	Name     string    `json:"n"`
+}
+
+func getVideoCursor(video Video) videoCursor {
+	return videoCursor{Position: video.position, Views: video.Views, Taken: video.TakenAt(), Name: video.Name}
+}
+
+// compareVideos orders by the requested sort, then by name so every order is complete
+func compareVideos(order string, a videoCursor, b videoCursor) int {
+	switch order {
+	case "popular":
+		if a.Views != b.Views {
+			return b.Views - a.Views
+		}
+	case "taken":
+		if !a.Taken.Equal(b.Taken) {
+			return a.Taken.Compare(b.Taken)
+		}
+	case "":
+		if a.Position != b.Position {
+			return a.Position - b.Position
+		}
+	}
+	return compareNames(a.Name, b.Name)
+}
+
+func validateLocale(name string, value string) error {
+	if _, err := language.Parse(value); err != nil {
+		return fmt.Errorf("%s must be a language tag like en or de-CH", name)
+	}
+	return nil
+}
+
+// nameCollator orders names the way people read them, set up on first use since collators aren't safe to share
+var nameCollator struct {
+	sync.Mutex
+	once     sync.Once
+	collator *collate.Collator
+}
+
+// compareNames sorts names by the rules of SORT_LOCALE, with numbers by value so "Part 2" comes before "Part 10"
+func compareNames(a string, b string) int {
+	nameCollator.once.Do(func() {
+		locale, err := language.Parse(os.Getenv("SORT_LOCALE"))
+		if err != nil {
+			locale = language.Und
+		}
+		nameCollator.collator = collate.New(locale, collate.Numeric)
+	})
+	nameCollator.Lock()
+	order := nameCollator.collator.CompareString(a, b)
+	nameCollator.Unlock()
+	if order != 0 {
+		return order
+	}
+	// Names the collator sees as equal still need a fixed order
+	return strings.Compare(a, b)
+}
+
+func sortVideos(videos []Video, order string) {
+	slices.SortStableFunc(videos, func(a, b Video) int {
+		return compareVideos(order, getVideoCursor(a), getVideoCursor(b))
+	})
+}
+
+func encodeCursor(video Video) string {
+	cursor, err := json.Marshal(getVideoCursor(video))
+	if err != nil {
+		panic(err)
+	}
+	return base64.RawURLEncoding.EncodeToString(cursor)
+}
+
+func decodeCursor(value string) (videoCursor, error) {
+	var cursor videoCursor
+	content, err := base64.RawURLEncoding.DecodeString(value)
+	if err != nil {
+		return cursor, err
+	}
+	err = json.Unmarshal(content, &cursor)
+	return cursor, err
+}
+
+func validatePositive(name string, value string) error {
+	if number, err := strconv.Atoi(value); err != nil || number <= 0 {
+		return fmt.Errorf("%s must be a positive number", name)
+	}
+	return nil
+}
+
+func validateBool(name string, value string) error {
+	if value != "true" && value != "false" {
+		return fmt.Errorf("%s must be true or false", name)
+	}
+	return nil
+}
+
+func validateJson(name string, value string) error {
+	if !json.Valid([]byte(value)) {
+		return fmt.Errorf("%s is not valid JSON", name)
+	}
+	return nil
+}
+
+func validateExecutable(name string, value string) error {
+	if _, err := exec.LookPath(value); err != nil {
+		return fmt.Errorf("%s must be an executable: %w", name, err)
+	}
+	return nil
+}
+
+func validateHwaccel(name string, value string) error {
+	if _, ok := hwaccelOptions[value]; !ok || value == "" {
+		return fmt.Errorf("%s must be vaapi, nvenc or videotoolbox", name)
+	}
+	return nil
+}
+
+func validateStorageAuth(name string, value string) error {
+	if value != "default" && value != "anonymous" {
+		return fmt.Errorf("%s must be default or anonymous", name)
+	}
+	return nil
+}
+
+// settingDefinition describes a setting, restart marks those only read at startup
+type settingDefinition struct {
+	name     string
+	restart  bool
+	secret   bool
+	validate func(name string, value string) error
+}
+
+// configSettings lists every setting, read from the environment or CONFIG_FILE
+var configSettings = []settingDefinition{
+	{name: "BUCKET_NAME", restart: true},
+	{name: "SECRET_KEY", restart: true, secret: true},
+	{name: "TENANTS", restart: true, secret: true, validate: validateJson},
+	{name: "CATEGORY_ORDER"},
+	{name: "SORT_LOCALE", restart: true, validate: validateLocale},
+	{name: "HIDDEN_CATEGORIES"},
+	{name: "ADMIN_ONLY_CATEGORIES"},
+	{name: "INDEX_LAYOUT", validate: validateIndexLayout},
+	{name: "INDEX_PREVIEW_COUNT", validate: validatePositive},
+	{name: "INDEX_JUMP_BAR", validate: validateBool},
+	{name: "CATEGORY_KEYS", restart: true, secret: true, validate: validateJson},
+	{name: "VIDEO_EXTENSIONS"},
+	{name: "IMAGE_EXTENSIONS"},
+	{name: "PLAYABLE_EXTENSIONS"},
+	{name: "LIST_CONCURRENCY", validate: validatePositive},
+	{name: "SIGN_CONCURRENCY", validate: validatePositive},
+	{name: "THUMBNAIL_CONCURRENCY", restart: true, validate: validatePositive},
+	{name: "THUMBNAIL_QUALITY", validate: validateQuality},
+	{name: "THUMBNAIL_MAX_WIDTH", restart: true, validate: validatePositive},
+	{name: "CACHE_TTL_SECS", validate: validatePositive},
+	{name: "CACHE_STALE_WHILE_REVALIDATE", validate: validateBool},
+	{name: "CACHE_REFRESH_AHEAD", restart: true, validate: validateBool},
+	{name: "MANIFEST_CACHE", validate: validateBool},
+	{name: "COLLAGE_COVERS", validate: validateBool},
+	{name: "PROBE_VIDEOS", validate: validateBool},
+	{name: "HASHED_THUMBNAILS", validate: validateBool},
+	{name: "FFMPEG_PATH", restart: true, validate: validateExecutable},
+	{name: "FFPROBE_PATH", restart: true, validate: validateExecutable},
+	{name: "FFMPEG_HWACCEL", restart: true, validate: validateHwaccel},
+	{name: "TRANSCODE_QUOTA_GB", validate: validatePositive},
+	{name: "SIGNED_GALLERY_LINKS", validate: validateBool},
+	{name: "READ_ONLY", restart: true, validate: validateBool},
+	{name: "UPLOAD_MAX_BYTES", validate: validatePositive},
+	{name: "CLAMD_ADDRESS"},
+	{name: "DOWNLOADABLE_GALLERIES"},
+	{name: "RATING_GATES", secret: true, validate: validateJson},
+	{name: "STORAGE_AUTH", restart: true, validate: validateStorageAuth},
+	{name: "STORAGE_CREDENTIALS_FILE", restart: true},
+	{name: "WEBHOOK_URLS"},
+	{name: "WEBHOOK_SECRET", secret: true},
+	{name: "VIEWS_DIR", restart: true},
+	{name: "DEBUG_TEMPLATES", validate: validateBool},
+	{name: "PUBLIC_URL"},
+	{name: "SMTP_HOST"},
+	{name: "SMTP_PORT", validate: validatePositive},
+	{name: "SMTP_USERNAME"},
+	{name: "SMTP_PASSWORD", secret: true},
+	{name: "SMTP_FROM"},
+	{name: "DIGEST_RECIPIENTS"},
+	{name: "DIGEST_INTERVAL_HOURS", validate: validatePositive},
+	{name: "WHISPER_URL"},
+	{name: "WHISPER_MODEL"},
+	{name: "WHISPER_API_KEY", secret: true},
+	{name: "MONITORING_PROJECT", restart: true},
+	{name: "MONITORING_INTERVAL_SECS", restart: true, validate: validatePositive},
+	{name: "TRUSTED_PROXY_HOPS", validate: validatePositive},
+	{name: "PORT", restart: true, validate: validatePositive},
+}
+
+var (
+	// Settings taken from CONFIG_FILE, environment variables win over them
+	configFileSettings []string
+	configFileUnknown  []string
+)
+
+// Keeps reloads from interleaving
+var configMutex sync.Mutex
+
+// readConfigFile reads the settings in the file as environment variable values, along with any unknown names
+func readConfigFile(name string) (map[string]string, []string, error) {
+	content, err := os.ReadFile(name)
+	if err != nil {
+		return nil, nil, err
+	}
+	var values map[string]json.RawMessage
+	if err := json.Unmarshal(content, &values); err != nil {
+		return nil, nil, err
+	}
+
+	settings := make(map[string]string)
+	var unknown []string
+	for key, raw := range values {
+		if !slices.ContainsFunc(configSettings, func(setting settingDefinition) bool { return setting.name == key }) {
+			unknown = append(unknown, key)
+			continue
+		}
+
+		// Lists may be arrays and numbers or objects are used as written
+		var value string
+		var list []string
+		if err := json.Unmarshal(raw, &value); err != nil {
+			if err := json.Unmarshal(raw, &list); err == nil {
+				value = strings.Join(list, ",")
+			} else {
+				value = string(raw)
+			}
+		}
+		settings[key] = value
+	}
+	return settings, unknown, nil
+}
+
+// loadConfigFile copies the settings in CONFIG_FILE into the environment unless they're already set
+func loadConfigFile() error {
+	name := os.Getenv("CONFIG_FILE")
+	if name == "" {
+		return nil
+	}
+	values, unknown, err := readConfigFile(name)
+	if err != nil {
+		return err
+	}
+	configFileUnknown = unknown
+
+	for key, value := range values {
+		if _, found := os.LookupEnv(key); found {
+			continue
+		}
+		if err := os.Setenv(key, value); err != nil {
+			return err
+		}
+		configFileSettings = append(configFileSettings, key)
+	}
+	return nil
+}
+
+// reloadConfig applies the changes made to CONFIG_FILE since it was read, unless one is invalid or needs a restart
+func reloadConfig() (ConfigReload, error) {
+	configMutex.Lock()
+	defer configMutex.Unlock()
+
+	name := os.Getenv("CONFIG_FILE")
+	if name == "" {
+		return ConfigReload{}, fmt.Errorf("CONFIG_FILE isn't set")
+	}
+	values, unknown, err := readConfigFile(name)
+	if err != nil {
+		return ConfigReload{}, err
+	}
+	reload := ConfigReload{Changed: []string{}}
+	for _, key := range unknown {
+		reload.Problems = append(reload.Problems, "CONFIG_FILE has an unknown setting "+key)
+	}
+
+	// Environment variables still win, so only settings from the file can change
+	changes := make(map[string]string)
+	for _, setting := range configSettings {
+		value, inFile := values[setting.name]
+		fromFile := slices.Contains(configFileSettings, setting.name)
+		if _, found := os.LookupEnv(setting.name); found && !fromFile {
+			continue
+		}
+		if (!inFile && !fromFile) || value == os.Getenv(setting.name) {
+			continue
+		}
+		if value != "" && setting.validate != nil {
+			if err := setting.validate(setting.name, value); err != nil {
+				reload.Problems = append(reload.Problems, err.Error())
+				continue
+			}
+		}
+		if setting.restart {
+			reload.Rejected = append(reload.Rejected, setting.name)
+			continue
+		}
+		changes[setting.name] = value
+	}
+	if len(reload.Problems) > 0 || len(reload.Rejected) > 0 {
+		return reload, nil
+	}
+
+	// Settings removed from the file go back to their defaults
+	for _, setting := range configSettings {
+		value, changed := changes[setting.name]
+		if !changed {
+			continue
+		}
+		configFileSettings = slices.DeleteFunc(configFileSettings, func(key string) bool { return key == setting.name })
+		if value == "" {
+			err = os.Unsetenv(setting.name)
+		} else {
+			err = os.Setenv(setting.name, value)
+			configFileSettings = append(configFileSettings, setting.name)
+		}
+		if err != nil {
+			return reload, err
+		}
+		reload.Changed = append(reload.Changed, setting.name)
+	}
+	return reload, nil
+}
+
+// validateConfig reports every invalid setting at once
+func validateConfig() []error {
+	var problems []error
+	for _, key := range configFileUnknown {
+		problems = append(problems, fmt.Errorf("CONFIG_FILE has an unknown setting %s", key))
+	}
+	for _, setting := range configSettings {
+		value := os.Getenv(setting.name)
+		if value == "" || setting.validate == nil {
+			continue
+		}
+		if err := setting.validate(setting.name, value); err != nil {
+			problems = append(problems, err)
+		}
+	}
+	if os.Getenv("SECRET_KEY") == "" && os.Getenv("TENANTS") == "" {
+		problems = append(problems, fmt.Errorf("SECRET_KEY or TENANTS must be set"))
+	}
+	return problems
+}
+
+func getConfigSettings() []ConfigSetting {
+	var settings []ConfigSetting
+	for _, setting := range configSettings {
+		value := os.Getenv(setting.name)
+		source := "environment"
+		if value == "" {
+			source = "default"
+		} else if slices.Contains(configFileSettings, setting.name) {
+			source = "file"
+		}
+		if setting.secret && value != "" {
+			value = "********"
+		}
+		settings = append(settings, ConfigSetting{Name: setting.name, Value: value, Source: source, Restart: setting.restart})
+	}
+	return settings
+}
+
+func getTenants() []*Tenant {
+	var tenants []*Tenant
+
+	// Default tenant
+	secretKey := os.Getenv("SECRET_KEY")
+	if secretKey != "" {
+		tenants = append(tenants, &Tenant{
+			BucketName: os.Getenv("BUCKET_NAME"),
+			SecretKey:  secretKey,
+		})
+	}
+
+	// Additional tenants keyed by hostname
+	if tenantsJson := os.Getenv("TENANTS"); tenantsJson != "" {
+		hostTenants := make(map[string]*Tenant)
+		if err := json.Unmarshal([]byte(tenantsJson), &hostTenants); err != nil {
+			panic("TENANTS is not valid JSON: " + err.Error())
+		}
+		for host, tenant := range hostTenants {
+			if tenant.BucketName == "" || tenant.SecretKey == "" {
+				panic("TENANTS entry " + host + " needs a bucket and secret_key")
+			}
+			tenant.Host = host
+			tenants = append(tenants, tenant)
+		}
+	}
+
+	if len(tenants) == 0 {
+		panic("SECRET_KEY not set")
+	}
+	for _, tenant := range tenants {
+		tenant.videoCache = cache.New(getCacheTTL(), 2*getCacheTTL())
+		tenant.pendingViews = make(map[string]int)
+		tenant.pendingClicks = make(map[string]int)
+		tenant.pendingAnalytics = make(map[string]VideoAnalytics)
+	}
+	return tenants
+}
+
+func getCacheTTL() time.Duration {
+	ttl := os.Getenv("CACHE_TTL_SECS")
+	if ttl == "" {
+		return 5 * time.Minute
+	}
+	seconds, err := strconv.Atoi(ttl)
+	if err != nil || seconds <= 0 {
+		panic("CACHE_TTL_SECS must be a positive number of seconds")
+	}
+	return time.Duration(seconds) * time.Second
+}
+
+func isStaleWhileRevalidate() bool {
+	return os.Getenv("CACHE_STALE_WHILE_REVALIDATE") == "true"
+}
+
+func (t *Tenant) getLibrary() Library {
+	// Check if Videos are cached
+	if cachedLibrary, found := t.videoCache.Get("videos"); found {
+		log.Println("Using Cached Videos")
+		return cachedLibrary.(Library)
+	}
+
+	// Serve the previous videos while refreshing in the background
+	if isStaleWhileRevalidate() {
+		t.refreshMutex.Lock()
+		library := t.staleLibrary
+		startRefresh := library != nil && t.refreshCall == nil
+		t.refreshMutex.Unlock()
+
+		if library != nil {
+			if startRefresh {
+				go func() {
+					_, _ = t.refreshOnce()
+				}()
+			}
+			log.Println("Using Stale Videos")
+			return *library
+		}
+	}
+
+	library, err := t.refreshOnce()
+	if err != nil {
+		// Keep showing the last videos we found instead of an empty site
+		t.refreshMutex.Lock()
+		lastLibrary := t.staleLibrary
+		t.refreshMutex.Unlock()
+		if lastLibrary == nil {
+			return Library{}
+		}
+		log.Println("Using Last Good Videos")
+		t.videoCache.Set("videos", *lastLibrary, refreshRetryInterval)
+		return *lastLibrary
+	}
+	return library
+}
+
+// refreshOnce lists the bucket unless a listing is already running, in which case it waits for that one
+func (t *Tenant) refreshOnce() (Library, error) {
+	t.refreshMutex.Lock()
+	if call := t.refreshCall; call != nil {
+		t.refreshMutex.Unlock()
+		log.Println("Waiting For Videos")
+		<-call.done
+		return call.library, call.err
+	}
+	call := &refreshCall{
+		done: make(chan struct{}),
+		err:  fmt.Errorf("refresh failed"),
+	}
+	t.refreshCall = call
+	t.refreshMutex.Unlock()
+
+	// Release the waiting requests even if the refresh panics
+	defer func() {
+		t.refreshMutex.Lock()
+		t.refreshCall = nil
+		t.refreshMutex.Unlock()
+		close(call.done)
+	}()
+	call.library, call.err = t.refreshLibrary()
+	return call.library, call.err
+}
+
+// How long the last good videos are served before listing the bucket again
+const refreshRetryInterval = 30 * time.Second
+
+func isRefreshAhead() bool {
+	return os.Getenv("CACHE_REFRESH_AHEAD") == "true"
+}
+
+// randomDuration picks a duration below limit
+func randomDuration(limit time.Duration) time.Duration {
+	var random [8]byte
+	if _, err := rand.Read(random[:]); err != nil {
+		panic(err)
+	}
+	return time.Duration(binary.BigEndian.Uint64(random[:]) % uint64(limit))
+}
+
+// refreshAhead lists the bucket shortly before the cache expires, so visitors don't wait for it
+func (t *Tenant) refreshAhead() {
+	ttl := getCacheTTL()
+	for {
+		// Somewhere in the last tenth of the TTL, so instances don't all list at once
+		wait := ttl - ttl/10 + randomDuration(ttl/20)
+		t.refreshMutex.Lock()
+		library := t.staleLibrary
+		failed := t.refreshError != nil
+		t.refreshMutex.Unlock()
+		switch {
+		case failed:
+			wait = refreshRetryInterval
+		case library == nil:
+			wait = 0
+		default:
+			wait -= time.Since(library.RefreshedAt)
+		}
+
+		if wait > 0 {
+			time.Sleep(wait)
+
+			// A request may have refreshed it in the meantime
+			t.refreshMutex.Lock()
+			refreshed := t.staleLibrary != library
+			t.refreshMutex.Unlock()
+			if refreshed {
+				continue
+			}
+		}
+		log.Println("Refreshing Videos Ahead Of Expiry")
+		_, _ = t.refreshOnce()
+	}
+}
+
+// getRefreshError is the reason the last refresh failed, or nil when it succeeded
+func (t *Tenant) getRefreshError() error {
+	t.refreshMutex.Lock()
+	defer t.refreshMutex.Unlock()
+	return t.refreshError
+}
+
+var (
+	storageClient     *storage.Client
+	storageClientOnce sync.Once
+)
+
+func (t *Tenant) getBucket() *storage.BucketHandle {
+	bucketName := t.BucketName
+	if bucketName == "" {
+		panic("BUCKET_NAME not set")
+	}
+
+	// Initialize Cloud Storage
+	storageClientOnce.Do(func() {
+		options, err := getStorageOptions()
+		if err != nil {
+			log.Fatal("Invalid storage credentials: " + err.Error())
+		}
+		client, err := storage.NewClient(context.Background(), options...)
+		if err != nil {
+			log.Fatal("Failed to create storage client: " + err.Error())
+		}
+		storageClient = client
+	})
+	return storageClient.Bucket(bucketName)
+}
+
+func isStorageAnonymous() bool {
+	return os.Getenv("STORAGE_AUTH") == "anonymous"
+}
+
+func getStorageOptions() ([]option.ClientOption, error) {
+	credentialsFile := os.Getenv("STORAGE_CREDENTIALS_FILE")
+	switch os.Getenv("STORAGE_AUTH") {
+	case "", "default":
+		// Application default credentials, which covers workload identity
+		if credentialsFile == "" {
+			return nil, nil
+		}
+		content, err := os.ReadFile(credentialsFile)
+		if err != nil {
+			return nil, fmt.Errorf("STORAGE_CREDENTIALS_FILE can't be read: %w", err)
+		}
+		var credentials struct {
+			Type string `json:"type"`
+		}
+		if err := json.Unmarshal(content, &credentials); err != nil || credentials.Type == "" {
+			return nil, fmt.Errorf("STORAGE_CREDENTIALS_FILE %s is not a credentials JSON file", credentialsFile)
+		}
+		return []option.ClientOption{option.WithCredentialsJSON(content)}, nil
+	case "anonymous":
+		if credentialsFile != "" {
+			return nil, fmt.Errorf("STORAGE_CREDENTIALS_FILE can't be used with anonymous STORAGE_AUTH")
+		}
+		return []option.ClientOption{option.WithoutAuthentication()}, nil
+	default:
+		return nil, fmt.Errorf("unknown STORAGE_AUTH %q, use default or anonymous", os.Getenv("STORAGE_AUTH"))
+	}
+}
+
+// validateStorage fails fast on bad credentials and explains buckets that can't be listed
+func validateStorage(tenants []*Tenant) {
+	if _, err := getStorageOptions(); err != nil {
+		log.Fatal("Invalid storage credentials: " + err.Error())
+	}
+	for _, tenant := range tenants {
+		if tenant.BucketName == "" {
+			continue
+		}
+		check := tenant.checkStorage(context.Background())
+		if check.Status != "ok" {
+			log.Println("Cannot list bucket " + tenant.BucketName + ", check that it exists and the credentials can read it: " + check.Message)
+		}
+	}
+}
+
+const metadataPath = "_metadata.json"
+
+func (t *Tenant) getMetadata() Metadata {
+	t.metadataMutex.Lock()
+	defer t.metadataMutex.Unlock()
+
+	if t.metadata == nil {
+		metadata, _, err := t.readMetadata(context.Background())
+		if err != nil {
+			panic(err)
+		}
+		t.metadata = &metadata
+	}
+	return *t.metadata
+}
+
+func (t *Tenant) readMetadata(ctx context.Context) (Metadata, int64, error) {
+	metadata := Metadata{}
+	reader, err := t.getBucket().Object(metadataPath).NewReader(ctx)
+	if errors.Is(err, storage.ErrObjectNotExist) {
+		return metadata, 0, nil
+	}
+	if err != nil {
+		return metadata, 0, err
+	}
+	defer reader.Close()
+
+	if err := json.NewDecoder(reader).Decode(&metadata); err != nil {
+		return metadata, 0, err
+	}
+	return metadata, reader.Attrs.Generation, nil
+}
+
+func (t *Tenant) updateMetadata(ctx context.Context, update func(metadata *Metadata)) error {
+	for attempt := 0; attempt < 5; attempt++ {
+		metadata, generation, err := t.readMetadata(ctx)
+		if err != nil {
+			return err
+		}
+		update(&metadata)
+
+		// Only write if nobody else has written in the meantime
+		object := t.getBucket().Object(metadataPath)
+		if generation == 0 {
+			object = object.If(storage.Conditions{DoesNotExist: true})
+		} else {
+			object = object.If(storage.Conditions{GenerationMatch: generation})
+		}
+		// Conditional writes are the storage call retried here, the client already retries the others
+		err = retry(ctx, "writing metadata", func() error {
+			writer := object.NewWriter(ctx)
+			writer.ContentType = "application/json"
+			if err := json.NewEncoder(writer).Encode(metadata); err != nil {
+				_ = writer.Close()
+				return err
+			}
+			return writer.Close()
+		})
+		var apiErr *googleapi.Error
+		if errors.As(err, &apiErr) && apiErr.Code == http.StatusPreconditionFailed {
+			continue
+		}
+		if err != nil {
+			return err
+		}
+
+		t.metadataMutex.Lock()
+		t.metadata = &metadata
+		t.metadataMutex.Unlock()
+		return nil
+	}
+	return fmt.Errorf("metadata was modified concurrently")
+}
+
+const auditPrefix = "_audit/"
+
+// getClientIp is the address the outermost of the TRUSTED_PROXY_HOPS proxies got the request from, since clients can send any X-Forwarded-For
+func getClientIp(r *http.Request) string {
+	hops, _ := strconv.Atoi(os.Getenv("TRUSTED_PROXY_HOPS"))
+	if forwardedFor := r.Header.Values("X-Forwarded-For"); hops > 0 && len(forwardedFor) > 0 {
+		// Each proxy appends the address it got the request from
+		addresses := strings.Split(strings.Join(forwardedFor, ","), ",")
+		return strings.TrimSpace(addresses[max(len(addresses)-hops, 0)])
+	}
+	host, _, err := net.SplitHostPort(r.RemoteAddr)
+	if err != nil {
+		return r.RemoteAddr
+	}
+	return host
+}
+
+type apiTokenContextKey struct{}
+
+func isTokenRequest(r *http.Request) bool {
+	_, ok := r.Context().Value(apiTokenContextKey{}).(string)
+	return ok
+}
+
+// getActor names who made a request in the audit log
+func getActor(r *http.Request) string {
+	if name, ok := r.Context().Value(apiTokenContextKey{}).(string); ok {
+		return "token:" + name
+	}
+	return getClientIp(r)
+}
+
+func (t *Tenant) audit(actor string, action string, parameters map[string]string) {
+	entry := AuditEntry{
+		Time:       time.Now().UTC(),
+		Actor:      actor,
+		Action:     action,
+		Parameters: parameters,
+	}
+	log.Println("Audit: " + action + " by " + entry.Actor)
+
+	// One object per entry, named so listing returns them in order
+	name := auditPrefix + entry.Time.Format("20060102T150405.000000000Z") + "-" + getHash(entry.Actor+action, t.SecretKey, 6) + ".json"
+	writer := t.getBucket().Object(name).If(storage.Conditions{DoesNotExist: true}).NewWriter(context.Background())
+	writer.ContentType = "application/json"
+	if err := json.NewEncoder(writer).Encode(entry); err != nil {
+		_ = writer.Close()
+		log.Println("Failed to write audit entry: " + err.Error())
+		return
+	}
+	if err := writer.Close(); err != nil {
+		log.Println("Failed to write audit entry: " + err.Error())
+	}
+}
+
+func (t *Tenant) getAuditPage(ctx context.Context, after string, limit int) (AuditPage, error) {
+	page := AuditPage{Entries: []AuditEntry{}}
+	bucket := t.getBucket()
+	query := &storage.Query{Prefix: auditPrefix}
+	if after != "" {
+		query.StartOffset = after + "\x00"
+	}
+	objects := bucket.Objects(ctx, query)
+	for len(page.Entries) < limit {
+		object, err := objects.Next()
+		if errors.Is(err, iterator.Done) {
+			return page, nil
+		}
+		if err != nil {
+			return page, err
+		}
+
+		reader, err := bucket.Object(object.Name).NewReader(ctx)
+		if err != nil {
+			return page, err
+		}
+		var entry AuditEntry
+		err = json.NewDecoder(reader).Decode(&entry)
+		_ = reader.Close()
+		if err != nil {
+			return page, err
+		}
+		page.Entries = append(page.Entries, entry)
+		page.Next = object.Name
+	}
+	return page, nil
+}
+
+func (t *Tenant) flushViews(interval time.Duration) {
+	for range time.Tick(interval) {
+		t.saveViews(context.Background())
+	}
+}
+
+func (t *Tenant) saveViews(ctx context.Context) {
+	t.viewsMutex.Lock()
+	views := t.pendingViews
+	clicks := t.pendingClicks
+	analytics := t.pendingAnalytics
+	t.pendingViews = make(map[string]int)
+	t.pendingClicks = make(map[string]int)
+	t.pendingAnalytics = make(map[string]VideoAnalytics)
+	t.viewsMutex.Unlock()
+	if len(views) == 0 && len(clicks) == 0 && len(analytics) == 0 {
+		return
+	}
+
+	log.Println("Saving View Counts")
+	err := t.updateMetadata(ctx, func(metadata *Metadata) {
+		if metadata.Views == nil {
+			metadata.Views = make(map[string]int)
+		}
+		for key, count := range views {
+			metadata.Views[key] += count
+		}
+		for code, count := range clicks {
+			if link, ok := metadata.ShortLinks[code]; ok {
+				link.Clicks += count
+				metadata.ShortLinks[code] = link
+			}
+		}
+		if len(analytics) > 0 && metadata.Analytics == nil {
+			metadata.Analytics = make(map[string]VideoAnalytics)
+		}
+		for key, counts := range analytics {
+			metadata.Analytics[key] = metadata.Analytics[key].add(counts)
+		}
+	})
+	if err != nil {
+		log.Println("Failed to save view counts: " + err.Error())
+	}
+}
+
+func getConcurrency(name string, defaultValue int) int {
+	concurrency := os.Getenv(name)
+	if concurrency == "" {
+		return defaultValue
+	}
+	// A bad value falls back to the default, a panic in a request or a zero sized semaphore would stop the service
+	value, err := strconv.Atoi(concurrency)
+	if err != nil || value <= 0 {
+		log.Printf("%s must be a positive number, using %d", name, defaultValue)
+		return defaultValue
+	}
+	return value
+}
+
+const (
+	retryAttempts  = 4
+	retryBaseDelay = 200 * time.Millisecond
+	retryMaxDelay  = 5 * time.Second
+)
+
+// retryBudget allows a retry for every ten calls, so retries can't pile onto an outage
+var retryBudget = struct {
+	sync.Mutex
+	tokens float64
+}{tokens: 10}
+
+func spendRetry() bool {
+	retryBudget.Lock()
+	defer retryBudget.Unlock()
+	if retryBudget.tokens < 1 {
+		return false
+	}
+	retryBudget.tokens--
+	return true
+}
+
+func earnRetry() {
+	retryBudget.Lock()
+	defer retryBudget.Unlock()
+	retryBudget.tokens = min(retryBudget.tokens+0.1, 10)
+}
+
+// isRetryable tells transient failures, like throttling or a dropped connection, from ones that will happen again
+func isRetryable(err error) bool {
+	if errors.Is(err, context.Canceled) || errors.Is(err, context.DeadlineExceeded) {
+		return false
+	}
+	var apiErr *googleapi.Error
+	if errors.As(err, &apiErr) {
+		return apiErr.Code == http.StatusRequestTimeout || apiErr.Code == http.StatusTooManyRequests || apiErr.Code >= 500
+	}
+	var netErr net.Error
+	if errors.As(err, &netErr) {
+		return true
+	}
+	return errors.Is(err, io.ErrUnexpectedEOF) || errors.Is(err, syscall.ECONNRESET)
+}
+
+// retry calls the storage API again after transient failures, backing off exponentially with full jitter.
+// Only for calls the client doesn't retry itself, since it already retries listing and reading objects.
+func retry(ctx context.Context, operation string, call func() error) error {
+	earnRetry()
+	delay := retryBaseDelay
+	for attempt := 1; ; attempt++ {
+		err := call()
+		if err == nil || attempt == retryAttempts || !isRetryable(err) || !spendRetry() {
+			return err
+		}
+		log.Println("Retrying " + operation + ": " + err.Error())
+		select {
+		case <-ctx.Done():
+			return err
+		case <-time.After(randomDuration(delay)):
+		}
+		delay = min(delay*2, retryMaxDelay)
+	}
+}
+
+// listedAttrs are the attributes a library refresh reads, with the CRC32C naming hashed thumbnail urls
+var listedAttrs = []string{"Name", "Size", "Created", "Generation", "CRC32C"}
+
+func (t *Tenant) listObjects(ctx context.Context) ([]*storage.ObjectAttrs, error) {
+	bucket := t.getBucket()
+
+	// List the category prefixes
+	var prefixes []string
+	categories := bucket.Objects(ctx, &storage.Query{Delimiter: "/"})
+	for {
+		category, err := categories.Next()
+		if errors.Is(err, iterator.Done) {
+			break
+		}
+		if err != nil {
+			return nil, err
+		}
+		if category.Prefix != "" {
+			prefixes = append(prefixes, category.Prefix)
+		}
+	}
+
+	// Old versions and transcodes of videos aren't part of the library
+	prefixes = slices.DeleteFunc(prefixes, func(prefix string) bool { return prefix == versionsPrefix || prefix == transcodesPrefix })
+
+	// List each category in parallel
+	shards := make([][]*storage.ObjectAttrs, len(prefixes))
+	shardErrors := make([]error, len(prefixes))
+	semaphore := make(chan struct{}, getConcurrency("LIST_CONCURRENCY", 4))
+	var wg sync.WaitGroup
+	for i, prefix := range prefixes {
+		wg.Add(1)
+		go func(i int, prefix string) {
+			defer wg.Done()
+			semaphore <- struct{}{}
+			defer func() { <-semaphore }()
+
+			query := &storage.Query{Prefix: prefix}
+			if err := query.SetAttrSelection(listedAttrs); err != nil {
+				shardErrors[i] = err
+				return
+			}
+			files := bucket.Objects(ctx, query)
+			for {
+				file, err := files.Next()
+				if errors.Is(err, iterator.Done) {
+					return
+				}
+				if err != nil {
+					shardErrors[i] = err
+					return
+				}
+				shards[i] = append(shards[i], file)
+			}
+		}(i, prefix)
+	}
+	wg.Wait()
+
+	// A partial listing would look like deleted videos
+	if err := errors.Join(shardErrors...); err != nil {
+		return nil, err
+	}
+
+	var files []*storage.ObjectAttrs
+	for _, shard := range shards {
+		files = append(files, shard...)
+	}
+	return files, nil
+}
+
+const signedUrlTTL = 24 * time.Hour
+
+// signUrls signs in parallel, since signing without a private key is a call to the IAM API
+func (t *Tenant) signUrls(names []string) (map[string]string, error) {
+	urls := make([]string, len(names))
+	signErrors := make([]error, len(names))
+	semaphore := make(chan struct{}, getConcurrency("SIGN_CONCURRENCY", 16))
+	var wg sync.WaitGroup
+	for i, name := range names {
+		wg.Add(1)
+		go func(i int, name string) {
+			defer wg.Done()
+			semaphore <- struct{}{}
+			defer func() { <-semaphore }()
+
+			urls[i], signErrors[i] = t.signUrl(name)
+		}(i, name)
+	}
+	wg.Wait()
+
+	signedUrls := make(map[string]string)
+	for i, name := range names {
+		if signErrors[i] != nil {
+			return nil, signErrors[i]
+		}
+		signedUrls[name] = urls[i]
+	}
+	return signedUrls, nil
+}
+
+func (t *Tenant) signUrl(name string) (string, error) {
+	// Public buckets can't sign urls without credentials
+	if isStorageAnonymous() {
+		return "https://storage.googleapis.com/" + t.BucketName + (&url.URL{Path: "/" + name}).EscapedPath(), nil
+	}
+	return t.getBucket().SignedURL(name, &storage.SignedURLOptions{
+		Expires: time.Now().Add(signedUrlTTL),
+		Method:  "GET",
+	})
+}
+
+const guestLinkTTL = 7 * 24 * time.Hour
+
+func isSignedGalleryLinks() bool {
+	return os.Getenv("SIGNED_GALLERY_LINKS") == "true"
+}
+
+func isReadOnly() bool {
+	return os.Getenv("READ_ONLY") == "true"
+}
+
+func (t *Tenant) getGallerySignature(gallery Gallery, expires int64) string {
+	// Rotating the gallery key invalidates every link shared for it
+	key := t.getMetadata().GalleryKeys[gallery.Category+"/"+gallery.Name]
+	mac := hmac.New(sha256.New, []byte(t.SecretKey+key))
+	mac.Write([]byte(gallery.Stub + "\n" + strconv.FormatInt(expires, 10)))
+	return base64.RawURLEncoding.EncodeToString(mac.Sum(nil))
+}
+
+func (t *Tenant) signGalleryLink(gallery Gallery, expires time.Time) string {
+	return gallery.Stub + "?" + t.signGalleryQuery(gallery, expires)
+}
+
+// signGalleryQuery is the exp and sig of a guest link, which /video links of the gallery's videos accept too
+func (t *Tenant) signGalleryQuery(gallery Gallery, expires time.Time) string {
+	query := url.Values{
+		"exp": {strconv.FormatInt(expires.Unix(), 10)},
+		"sig": {t.getGallerySignature(gallery, expires.Unix())},
+	}
+	return query.Encode()
+}
+
+// guestLinkQuery passes the exp and sig of the guest link a page was opened with on to its /video links
+func guestLinkQuery(r *http.Request) string {
+	query := r.URL.Query()
+	if query.Get("sig") == "" {
+		return ""
+	}
+	return "?" + url.Values{"exp": {query.Get("exp")}, "sig": {query.Get("sig")}}.Encode()
+}
+
+// verifyGallerySignature checks the exp and sig parameters of a guest link when they're required
+func (t *Tenant) verifyGallerySignature(gallery Gallery, query url.Values) error {
+	if !isSignedGalleryLinks() {
+		return nil
+	}
+	expires, err := strconv.ParseInt(query.Get("exp"), 10, 64)
+	if err != nil || time.Now().Unix() > expires {
+		return fmt.Errorf("This link has expired")
+	}
+	signature := t.getGallerySignature(gallery, expires)
+	if !hmac.Equal([]byte(query.Get("sig")), []byte(signature)) {
+		return fmt.Errorf("This link is not valid")
+	}
+	return nil
+}
+
+// Retry-After for maintenance without an expected end
+const maintenanceRetrySecs = 300
+
+// unlessMaintenance answers with the maintenance page while maintenance mode is on
+func (t *Tenant) unlessMaintenance(next http.HandlerFunc) http.HandlerFunc {
+	return func(w http.ResponseWriter, r *http.Request) {
+		maintenance := t.getMetadata().Maintenance
+		if maintenance == nil {
+			next(w, r)
+			return
+		}
+
+		retryAfter := maintenanceRetrySecs
+		page := MaintenancePage{Message: maintenance.Message}
+		if maintenance.Until != nil {
+			retryAfter = max(60, int(time.Until(*maintenance.Until).Seconds()))
+			page.Until = maintenance.Until.Format("Monday 2 January 15:04 MST")
+		}
+		if page.Message == "" {
+			page.Message = "The galleries are being reorganized and will be back soon."
+		}
+		w.Header().Set("Retry-After", strconv.Itoa(retryAfter))
+		w.Header().Set("Cache-Control", "no-store")
+		if strings.Contains(r.Header.Get("Accept"), "application/json") {
+			errorHandler(w, r, http.StatusServiceUnavailable, page.Message)
+			return
+		}
+
+		template, err := pug.CompileFile(viewsDir+"/maintenance.pug", pug.Options{})
+		if err != nil {
+			log.Println(err)
+			http.Error(w, page.Message, http.StatusServiceUnavailable)
+			return
+		}
+		w.Header().Set("Content-Type", "text/html; charset=utf-8")
+		w.WriteHeader(http.StatusServiceUnavailable)
+		err = template.Execute(w, page)
+		if err != nil {
+			log.Println(err)
+		}
+	}
+}
+
+// maintenanceHandler turns maintenance mode on with an optional message and expected end, or off again
+func (t *Tenant) maintenanceHandler(w http.ResponseWriter, r *http.Request) {
+	switch r.Method {
+	case http.MethodGet:
+	case http.MethodPost:
+		maintenance := &Maintenance{Message: r.FormValue("message"), Since: time.Now()}
+		if value := r.FormValue("until"); value != "" {
+			until, err := time.Parse(time.RFC3339, value)
+			if err != nil {
+				errorHandler(w, r, http.StatusBadRequest, "until must be a time like 2006-01-02T15:04:05Z")
+				return
+			}
+			maintenance.Until = &until
+		}
+		log.Println("Starting Maintenance")
+		err := t.updateMetadata(r.Context(), func(metadata *Metadata) {
+			metadata.Maintenance = maintenance
+		})
+		if err != nil {
+			panic(err)
+		}
+		t.audit(getActor(r), "start_maintenance", map[string]string{
+			"message": maintenance.Message,
+			"until":   r.FormValue("until"),
+		})
+	case http.MethodDelete:
+		log.Println("Ending Maintenance")
+		err := t.updateMetadata(r.Context(), func(metadata *Metadata) {
+			metadata.Maintenance = nil
+		})
+		if err != nil {
+			panic(err)
+		}
+		t.audit(getActor(r), "end_maintenance", map[string]string{})
+	default:
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to start maintenance or DELETE to end it")
+		return
+	}
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(t.getMetadata().Maintenance)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+// requireGallerySignature only lets guests open gallery pages with a valid, unexpired link
+func (t *Tenant) requireGallerySignature(next http.HandlerFunc) http.HandlerFunc {
+	return func(w http.ResponseWriter, r *http.Request) {
+		gallery, err := t.getGallery(r.URL.Path)
+		if err != nil {
+			next(w, r)
+			return
+		}
+		if err := t.verifyGallerySignature(gallery, r.URL.Query()); err != nil {
+			log.Println("Gallery link rejected: " + r.URL.Path)
+			errorHandler(w, r, http.StatusForbidden, err.Error())
+			return
+		}
+		next(w, r)
+	}
+}
+
+func getList(name string) []string {
+	var values []string
+	for _, value := range strings.Split(os.Getenv(name), ",") {
+		value = strings.TrimSpace(value)
+		if value != "" {
+			values = append(values, value)
+		}
+	}
+	return values
+}
+
+func getExtensions(name string, defaults []string) []string {
+	values := getList(name)
+	if len(values) == 0 {
+		return defaults
+	}
+	var extensions []string
+	for _, extension := range values {
+		extension = strings.ToLower(extension)
+		if !strings.HasPrefix(extension, ".") {
+			extension = "." + extension
+		}
+		extensions = append(extensions, extension)
+	}
+	return extensions
+}
+
+func (t *Tenant) loadChapters(videos []Video, chapterFiles map[string]string, objects map[string]listedObject) {
+	read := make([][]Chapter, len(videos))
+	semaphore := make(chan struct{}, getConcurrency("LIST_CONCURRENCY", 4))
+	var wg sync.WaitGroup
+	for i := range videos {
+		chapterFile, ok := chapterFiles[videos[i].Key()]
+		if !ok {
+			continue
+		}
+
+		// Unchanged chapter files were read by an earlier refresh
+		if chapters := objects[chapterFile].chapters; chapters != nil {
+			videos[i].Chapters = withChapterUrls(chapters, videos[i].Url)
+			continue
+		}
+		wg.Add(1)
+		go func(i int, chapterFile string) {
+			defer wg.Done()
+			semaphore <- struct{}{}
+			defer func() { <-semaphore }()
+
+			chapters, err := t.readChapters(chapterFile)
+			if err != nil {
+				log.Println("Failed to read chapters " + chapterFile + ": " + err.Error())
+				return
+			}
+			read[i] = chapters
+		}(i, chapterFile)
+	}
+	wg.Wait()
+
+	for i, chapters := range read {
+		if chapters == nil {
+			continue
+		}
+		chapterFile := chapterFiles[videos[i].Key()]
+		object := objects[chapterFile]
+		object.chapters = chapters
+		objects[chapterFile] = object
+		videos[i].Chapters = withChapterUrls(chapters, videos[i].Url)
+	}
+}
+
+// withChapterUrls copies the chapters, pointing each one at its start in the video
+func withChapterUrls(chapters []Chapter, videoUrl string) []Chapter {
+	withUrls := slices.Clone(chapters)
+	for i := range withUrls {
+		withUrls[i].Url = videoUrl + "#t=" + strconv.FormatFloat(withUrls[i].Start, 'f', -1, 64)
+	}
+	return withUrls
+}
+
+// loadCaptureDates reads the EXIF date of JPEG photos that haven't been read before
+func (t *Tenant) loadCaptureDates(videos []Video) error {
+	if isStorageAnonymous() {
+		return nil
+	}
+	// Refreshes run in the background, where a panic would take the server down
+	metadata, _, err := t.readMetadata(context.Background())
+	if err != nil {
+		return err
+	}
+	captured := metadata.Captured
+	var photos []Video
+	for _, video := range videos {
+		extension := strings.ToLower(path.Ext(video.VideoPath))
+		if _, found := captured[video.Key()]; !found && video.IsImage() && (extension == ".jpg" || extension == ".jpeg") {
+			photos = append(photos, video)
+		}
+	}
+	if len(photos) == 0 {
+		return nil
+	}
+	log.Printf("Reading Capture Dates of %d Photos", len(photos))
+
+	dates := make([]*time.Time, len(photos))
+	semaphore := make(chan struct{}, getConcurrency("LIST_CONCURRENCY", 4))
+	var wg sync.WaitGroup
+	for i, photo := range photos {
+		wg.Add(1)
+		go func(i int, photo Video) {
+			defer wg.Done()
+			semaphore <- struct{}{}
+			defer func() { <-semaphore }()
+
+			// The EXIF block sits at the start of the file
+			reader, err := t.getBucket().Object(photo.VideoPath).NewRangeReader(context.Background(), 0, 128*1024)
+			if err != nil {
+				log.Println("Failed to read photo " + photo.VideoPath + ": " + err.Error())
+				return
+			}
+			defer reader.Close()
+			data, err := io.ReadAll(reader)
+			if err != nil {
+				log.Println("Failed to read photo " + photo.VideoPath + ": " + err.Error())
+				return
+			}
+			date, _ := readExifDate(data)
+			dates[i] = &date
+		}(i, photo)
+	}
+	wg.Wait()
+
+	err = t.updateMetadata(context.Background(), func(metadata *Metadata) {
+		if metadata.Captured == nil {
+			metadata.Captured = make(map[string]time.Time)
+		}
+		for i, photo := range photos {
+			if dates[i] != nil {
+				metadata.Captured[photo.Key()] = *dates[i]
+			}
+		}
+	})
+	if err != nil {
+		log.Println("Failed to save capture dates: " + err.Error())
+	}
+	return nil
+}
+
+// readExifDate finds the DateTimeOriginal tag in the EXIF block of a JPEG
+func readExifDate(data []byte) (time.Time, error) {
+	if len(data) < 4 || data[0] != 0xFF || data[1] != 0xD8 {
+		return time.Time{}, fmt.Errorf("not a JPEG")
+	}
+	offset := 2
+	for offset+4 <= len(data) && data[offset] == 0xFF {
+		marker := data[offset+1]
+		length := int(binary.BigEndian.Uint16(data[offset+2:]))
+		// Image data follows the start of scan marker
+		if marker == 0xDA || length < 2 {
+			break
+		}
+		segment := data[offset+4 : min(offset+2+length, len(data))]
+		if marker == 0xE1 && bytes.HasPrefix(segment, []byte("Exif\x00\x00")) {
+			return parseExifDate(segment[6:])
+		}
+		offset += 2 + length
+	}
+	return time.Time{}, fmt.Errorf("no EXIF data")
+}
+
+func parseExifDate(tiff []byte) (time.Time, error) {
+	if len(tiff) < 8 {
+		return time.Time{}, fmt.Errorf("EXIF data too short")
+	}
+	var order binary.ByteOrder
+	switch string(tiff[:2]) {
+	case "II":
+		order = binary.LittleEndian
+	case "MM":
+		order = binary.BigEndian
+	default:
+		return time.Time{}, fmt.Errorf("unknown EXIF byte order")
+	}
+
+	// Each directory entry is a tag, type, count and value or offset
+	findTag := func(directory uint32, tag uint16) (uint32, uint32, bool) {
+		// Offsets are compared as uint64, so large ones can't wrap around
+		if uint64(directory)+2 > uint64(len(tiff)) {
+			return 0, 0, false
+		}
+		entries := int(order.Uint16(tiff[directory:]))
+		for i := 0; i < entries; i++ {
+			entry := int(directory) + 2 + i*12
+			if entry+12 > len(tiff) {
+				break
+			}
+			if order.Uint16(tiff[entry:]) == tag {
+				return order.Uint32(tiff[entry+4:]), order.Uint32(tiff[entry+8:]), true
+			}
+		}
+		return 0, 0, false
+	}
+
+	_, exifDirectory, found := findTag(order.Uint32(tiff[4:]), 0x8769)
+	if !found {
+		return time.Time{}, fmt.Errorf("no EXIF directory")
+	}
+	count, value, found := findTag(exifDirectory, 0x9003)
+	if !found || count < 19 || uint64(value)+19 > uint64(len(tiff)) {
+		return time.Time{}, fmt.Errorf("no capture date")
+	}
+	return time.Parse("2006:01:02 15:04:05", string(tiff[value:value+19]))
+}
+
+func (t *Tenant) readChapters(name string) ([]Chapter, error) {
+	reader, err := t.getBucket().Object(name).NewReader(context.Background())
+	if err != nil {
+		return nil, err
+	}
+	defer reader.Close()
+	content, err := io.ReadAll(io.LimitReader(reader, 64*1024))
+	if err != nil {
+		return nil, err
+	}
+	return parseChapters(string(content)), nil
+}
+
+// parseChapters reads WebVTT cues or "00:00:00 Title" lines, in the order they start
+func parseChapters(content string) []Chapter {
+	var chapters []Chapter
+	lines := strings.Split(strings.ReplaceAll(content, "\r\n", "\n"), "\n")
+	for i, line := range lines {
+		line = strings.TrimSpace(line)
+		if strings.Contains(line, "-->") {
+			// WebVTT cue, the title is on the next line
+			start, err := parseTimestamp(strings.TrimSpace(strings.Split(line, "-->")[0]))
+			if err == nil && i+1 < len(lines) && strings.TrimSpace(lines[i+1]) != "" {
+				chapters = append(chapters, Chapter{Start: start, Title: strings.TrimSpace(lines[i+1])})
+			}
+			continue
+		}
+		timestamp, title, found := strings.Cut(line, " ")
+		if !found {
+			continue
+		}
+		if start, err := parseTimestamp(timestamp); err == nil {
+			chapters = append(chapters, Chapter{Start: start, Title: strings.TrimSpace(title)})
+		}
+	}
+	slices.SortStableFunc(chapters, func(a, b Chapter) int { return cmp.Compare(a.Start, b.Start) })
+	return chapters
+}
+
+func parseTimestamp(timestamp string) (float64, error) {
+	parts := strings.Split(timestamp, ":")
+	if len(parts) < 2 || len(parts) > 3 {
+		return 0, fmt.Errorf("invalid timestamp: %s", timestamp)
+	}
+	seconds := 0.0
+	for _, part := range parts {
+		value, err := strconv.ParseFloat(part, 64)
+		if err != nil {
+			return 0, err
+		}
+		// ParseFloat also takes signs, NaN and Inf
+		if value < 0 || math.IsNaN(value) || math.IsInf(value, 0) {
+			return 0, fmt.Errorf("invalid timestamp: %s", timestamp)
+		}
+		seconds = seconds*60 + value
+	}
+	return seconds, nil
+}
+
+func (t *Tenant) refreshLibrary() (Library, error) {
+	start := time.Now()
+	library, err := t.listLibrary()
+	duration := time.Since(start)
+	t.refreshMutex.Lock()
+	t.refreshError = err
+	t.refreshStats.count++
+	t.refreshStats.total += duration
+	t.refreshStats.longest = max(t.refreshStats.longest, duration)
+	if err != nil {
+		t.refreshStats.failures++
+	}
+	t.refreshMutex.Unlock()
+	log.Printf("Listed %s in %.1fs\n", t.BucketName, duration.Seconds())
+	if err != nil {
+		log.Println("Failed to get videos: " + err.Error())
+		return library, err
+	}
+
+	// Cache Videos
+	// Read each time, so a reloaded CACHE_TTL_SECS applies from the next refresh
+	t.videoCache.Set("videos", library, getCacheTTL())
+	t.refreshMutex.Lock()
+	previous := t.staleLibrary
+	t.staleLibrary = &library
+	t.refreshMutex.Unlock()
+	if isCollageCovers() {
+		go t.updateCollages(context.Background())
+	}
+	if isProbeVideos() && isFfprobeInstalled() {
+		go t.updateProbes(context.Background())
+	}
+	if isManifestCache() {
+		go t.saveManifest(library)
+	}
+
+	// Announce what changed since the last refresh
+	if previous != nil {
+		events := t.diffLibraries(*previous, library)
+		go func() {
+			t.sendWebhooks(t.claimWebhookEvents(events))
+		}()
+		t.publishEvents(events)
+	}
+	return library, nil
+}
+
+func (t *Tenant) listLibrary() (Library, error) {
+	log.Println("Getting Videos")
+	start := time.Now()
+	files, err := t.listObjects(context.Background())
+	if err != nil {
+		return Library{}, err
+	}
+
+	// Objects that haven't changed since the last refresh keep their urls while they're fresh enough
+	t.refreshMutex.Lock()
+	var previousObjects map[string]listedObject
+	if t.staleLibrary != nil {
+		previousObjects = t.staleLibrary.objects
+	}
+	t.refreshMutex.Unlock()
+	library, err := t.buildLibrary(files, previousObjects)
+	library.RefreshDuration = time.Since(start)
+	return library, err
+}
+
+// buildLibrary sorts the objects into videos, photos and their sidecars
+func (t *Tenant) buildLibrary(files []*storage.ObjectAttrs, previousObjects map[string]listedObject) (Library, error) {
+	var totalBytes int64
+
+	// Pick up metadata written by other instances
+	t.metadataMutex.Lock()
+	t.metadata = nil
+	t.metadataMutex.Unlock()
+
+	videosMap := make(map[string]Video)
+	covers := make(map[string]string)
+	var placeholders []string
+	galleryBytes := make(map[string]int64)
+	contactSheets := make(map[string]string)
+	collages := make(map[string]string)
+	chapterFiles := make(map[string]string)
+	subtitleFiles := make(map[string]string)
+	previewFiles := make(map[string]string)
+	renditionFiles := make(map[string]map[string]string)
+	addRendition := func(key string, kind string, name string) {
+		if renditionFiles[key] == nil {
+			renditionFiles[key] = make(map[string]string)
+		}
+		renditionFiles[key][kind] = name
+	}
+
+	// Allowed Extensions
+	videoExtensions := getExtensions("VIDEO_EXTENSIONS", []string{".mp4", ".m4v", ".webm", ".mov", ".avi", ".mkv", ".ts"})
+	imageExtensions := getExtensions("IMAGE_EXTENSIONS", []string{".jpg", ".jpeg", ".png", ".heic", ".webp", ".avif"})
+	playableExtensions := getExtensions("PLAYABLE_EXTENSIONS", []string{".mp4", ".m4v", ".webm", ".mov"})
+	// A cover set through the API wins over a poster, whatever order they're listed in
+	coverNames := []string{"_cover", "_poster"}
+	coverRanks := make(map[string]int)
+	extensionRegex, _ := regexp.Compile(`\.[a-zA-Z0-9]+$`)
+
+	// Unchanged objects keep their urls while they're fresh enough
+	objects := make(map[string]listedObject)
+	var names []string
+	for _, file := range files {
+		if parts := strings.Split(file.Name, "/"); len(parts) == 3 && parts[2] != "" {
+			previous, ok := previousObjects[file.Name]
+			if ok && previous.generation == file.Generation && time.Since(previous.signed) < signedUrlTTL/2 {
+				objects[file.Name] = previous
+				continue
+			}
+			names = append(names, file.Name)
+		}
+	}
+
+	// Create Signed 24 Hour URLs
+	log.Printf("Signing %d Urls, Reusing %d\n", len(names), len(objects))
+	signed := time.Now()
+	signedUrls, err := t.signUrls(names)
+	if err != nil {
+		return Library{}, err
+	}
+	for _, file := range files {
+		if signedUrl, ok := signedUrls[file.Name]; ok {
+			objects[file.Name] = listedObject{
+				generation: file.Generation,
+				crc32c:     file.CRC32C,
+				size:       file.Size,
+				created:    file.Created,
+				url:        signedUrl,
+				signed:     signed,
+			}
+		}
+	}
+
+	// Iterate through videos
+	for _, file := range files {
+		parts := strings.Split(file.Name, "/")
+		if len(parts) == 3 && parts[2] != "" {
+			totalBytes += file.Size
+			category := parts[0]
+			gallery := parts[1]
+			filename := parts[2]
+			signedUrl := objects[file.Name].url
+			galleryBytes[category+"/"+gallery] += file.Size
+			// Remove extension from filename
+			fileBase := extensionRegex.ReplaceAll([]byte(filename), []byte(""))
+
+			// Chapter sidecars belong to the video with the same name
+			if strings.HasSuffix(string(fileBase), ".chapters") {
+				chapterFiles[category+"/"+gallery+"/"+strings.TrimSuffix(string(fileBase), ".chapters")] = file.Name
+				continue
+			}
+
+			// Other WebVTT sidecars are subtitles
+			if strings.EqualFold(path.Ext(filename), ".vtt") {
+				subtitleFiles[category+"/"+gallery+"/"+string(fileBase)] = file.Name
+				continue
+			}
+
+			// Short preview clips belong to the video with the same name
+			if strings.HasSuffix(string(fileBase), previewSuffix) && slices.Contains(videoExtensions, strings.ToLower(path.Ext(filename))) {
+				previewFiles[category+"/"+gallery+"/"+strings.TrimSuffix(string(fileBase), previewSuffix)] = file.Name
+				continue
+			}
+
+			// HLS playlists are renditions of the video with the same name, with their segments in a folder below
+			if strings.EqualFold(path.Ext(filename), ".m3u8") {
+				addRendition(category+"/"+gallery+"/"+string(fileBase), renditionHls, file.Name)
+				continue
+			}
+
+			// Gallery cover images aren't videos
+			if rank := slices.Index(coverNames, string(fileBase)); rank >= 0 {
+				if current, found := coverRanks[category+"/"+gallery]; !found || rank < current {
+					covers[category+"/"+gallery] = signedUrl
+					coverRanks[category+"/"+gallery] = rank
+				}
+				continue
+			}
+
+			// Contact sheets are made from the gallery, not part of it
+			if string(fileBase) == contactSheetName {
+				contactSheets[category+"/"+gallery] = signedUrl
+				continue
+			}
+
+			// Generated covers are only used when there is no cover
+			if string(fileBase) == collageName {
+				collages[category+"/"+gallery] = signedUrl
+				continue
+			}
+
+			// Marks a gallery that was created empty
+			if string(fileBase) == placeholderName {
+				placeholders = append(placeholders, category+"/"+gallery)
+				continue
+			}
+
+			// Key videos by their path so equal names in different galleries don't collide
+			videoKey := category + "/" + gallery + "/" + string(fileBase)
+
+			// If Video doesn't exist
+			if _, ok := videosMap[videoKey]; !ok {
+				videosMap[videoKey] = Video{
+					Name:     string(fileBase),
+					Category: category,
+					Gallery:  gallery,
+					Created:  file.Created,
+				}
+			}
+
+			// Check if video already exists
+			if video, ok := videosMap[videoKey]; ok {
+				lowerFilename := strings.ToLower(filename)
+				for _, extension := range videoExtensions {
+					if strings.HasSuffix(lowerFilename, extension) {
+						addRendition(videoKey, getRenditionKind(extension), file.Name)
+						videosMap[videoKey] = Video{
+							Name:             video.Name,
+							Category:         video.Category,
+							Gallery:          video.Gallery,
+							Url:              signedUrl,
+							VideoPath:        file.Name,
+							Thumbnail:        video.Thumbnail,
+							ThumbnailPath:    video.ThumbnailPath,
+							thumbnailVersion: video.thumbnailVersion,
+							thumbnailHash:    video.thumbnailHash,
+							Playable:         slices.Contains(playableExtensions, extension),
+							Created:          file.Created,
+						}
+					}
+				}
+				for _, extension := range imageExtensions {
+					if strings.HasSuffix(lowerFilename, extension) {
+						videosMap[videoKey] = Video{
+							Name:             video.Name,
+							Category:         video.Category,
+							Gallery:          video.Gallery,
+							Url:              video.Url,
+							VideoPath:        video.VideoPath,
+							Thumbnail:        &signedUrl,
+							ThumbnailPath:    file.Name,
+							thumbnailVersion: file.Generation,
+							thumbnailHash:    getThumbnailHash(file),
+							Playable:         video.Playable,
+							Created:          video.Created,
+						}
+					}
+				}
+			}
+		}
+	}
+	// Convert Map to Array
+	var videos []Video
+	for key, video := range videosMap {
+		video.Id = getHash(key, t.SecretKey, stubLength)
+
+		// Images without a matching video are photos
+		if video.Url == "" && video.Thumbnail != nil {
+			video.MediaType = "image"
+			video.Url = *video.Thumbnail
+			video.VideoPath = video.ThumbnailPath
+		} else {
+			video.MediaType = "video"
+		}
+		if video.ThumbnailPath != "" {
+			video.ThumbnailSmall = video.thumbnailUrl(gridThumbnailWidth)
+		}
+		if renditions := renditionFiles[key]; len(renditions) > 1 && video.MediaType == "video" {
+			video.renditions = renditions
+		}
+		if object, ok := objects[video.VideoPath]; ok {
+			video.Size = object.size
+			video.SizeDisplay = formatBytes(object.size)
+			video.videoVersion = object.generation
+		}
+		if name, ok := subtitleFiles[key]; ok && video.MediaType == "video" {
+			subtitlesUrl := objects[name].url
+			video.Subtitles = &subtitlesUrl
+			video.SubtitlesPath = name
+		}
+		if name, ok := previewFiles[key]; ok && video.MediaType == "video" {
+			previewUrl := objects[name].url
+			video.Preview = &previewUrl
+			video.PreviewPath = name
+		}
+		videos = append(videos, video)
+	}
+	t.loadChapters(videos, chapterFiles, objects)
+	if err := t.loadCaptureDates(videos); err != nil {
+		return Library{}, err
+	}
+
+	return Library{
+		Videos:        videos,
+		Covers:        covers,
+		Placeholders:  placeholders,
+		GalleryBytes:  galleryBytes,
+		ContactSheets: contactSheets,
+		Collages:      collages,
+		TotalBytes:    totalBytes,
+		RefreshedAt:   time.Now(),
+		objects:       objects,
+	}, nil
+}
+
+const manifestPath = "_manifest.json"
+
+// ManifestObject is an object as the last refresh found it
+type ManifestObject struct {
+	Name       string    `json:"name"`
+	Size       int64     `json:"size"`
+	Created    time.Time `json:"created"`
+	Generation int64     `json:"generation"`
+	CRC32C     uint32    `json:"crc32c"`
+	Url        string    `json:"url"`
+	Signed     time.Time `json:"signed"`
+	Chapters   []Chapter `json:"chapters,omitempty"`
+}
+
+type Manifest struct {
+	RefreshedAt time.Time        `json:"refreshed_at"`
+	Objects     []ManifestObject `json:"objects"`
+}
+
+func isManifestCache() bool {
+	return os.Getenv("MANIFEST_CACHE") == "true"
+}
+
+// saveManifest writes what the refresh found to the bucket, so the next instance can start from it
+func (t *Tenant) saveManifest(library Library) {
+	manifest := Manifest{RefreshedAt: library.RefreshedAt, Objects: []ManifestObject{}}
+	for name, object := range library.objects {
+		manifest.Objects = append(manifest.Objects, ManifestObject{
+			Name:       name,
+			Size:       object.size,
+			Created:    object.created,
+			Generation: object.generation,
+			CRC32C:     object.crc32c,
+			Url:        object.url,
+			Signed:     object.signed,
+			Chapters:   object.chapters,
+		})
+	}
+	sort.Slice(manifest.Objects, func(i, j int) bool {
+		return manifest.Objects[i].Name < manifest.Objects[j].Name
+	})
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(manifest)
+	if err != nil {
+		panic(err)
+	}
+	writer := t.getBucket().Object(manifestPath).NewWriter(context.Background())
+	writer.ContentType = "application/json"
+	if _, err := writer.Write(jsonString); err != nil {
+		_ = writer.Close()
+		log.Println("Failed to save the manifest: " + err.Error())
+		return
+	}
+	if err := writer.Close(); err != nil {
+		log.Println("Failed to save the manifest: " + err.Error())
+	}
+}
+
+// loadManifest serves the library saved by the last refresh while the bucket is listed again
+func (t *Tenant) loadManifest() {
+	start := time.Now()
+	reader, err := t.getBucket().Object(manifestPath).NewReader(context.Background())
+	if err != nil {
+		if !errors.Is(err, storage.ErrObjectNotExist) {
+			log.Println("Failed to read the manifest: " + err.Error())
+		}
+		return
+	}
+	defer reader.Close()
+	var manifest Manifest
+	if err := json.NewDecoder(reader).Decode(&manifest); err != nil {
+		log.Println("Failed to read the manifest: " + err.Error())
+		return
+	}
+
+	files := make([]*storage.ObjectAttrs, 0, len(manifest.Objects))
+	objects := make(map[string]listedObject)
+	for _, object := range manifest.Objects {
+		files = append(files, &storage.ObjectAttrs{
+			Name:       object.Name,
+			Size:       object.Size,
+			Created:    object.Created,
+			Generation: object.Generation,
+			CRC32C:     object.CRC32C,
+		})
+		objects[object.Name] = listedObject{
+			generation: object.Generation,
+			crc32c:     object.CRC32C,
+			size:       object.Size,
+			created:    object.Created,
+			url:        object.Url,
+			signed:     object.Signed,
+			chapters:   object.Chapters,
+		}
+	}
+	library, err := t.buildLibrary(files, objects)
+	if err != nil {
+		log.Println("Failed to load the manifest: " + err.Error())
+		return
+	}
+	// The cache age is that of the listing
+	library.RefreshedAt = manifest.RefreshedAt
+	library.RefreshDuration = time.Since(start)
+	log.Printf("Loaded %d Objects From The Manifest in %.1fs\n", len(files), library.RefreshDuration.Seconds())
+
+	t.refreshMutex.Lock()
+	t.staleLibrary = &library
+	t.refreshMutex.Unlock()
+	t.videoCache.Set("videos", library, getCacheTTL())
+
+	// Check it against the bucket straight away
+	go func() {
+		_, _ = t.refreshOnce()
+	}()
+}
+
+func (t *Tenant) diffLibraries(previous Library, current Library) []LibraryEvent {
+	var events []LibraryEvent
+	newEvent := func(event string, video Video) LibraryEvent {
+		return LibraryEvent{
+			Event:    event,
+			Time:     time.Now().UTC(),
+			Bucket:   t.BucketName,
+			Category: video.Category,
+			Gallery:  video.Gallery,
+			Video:    video.Name,
+		}
+	}
+
+	hiddenVideos := t.getMetadata().HiddenVideos
+	previousVideos := make(map[string]Video)
+	previousGalleries := make(map[string]bool)
+	for _, video := range previous.Videos {
+		previousVideos[video.Key()] = video
+		previousGalleries[video.Category+"/"+video.Gallery] = true
+	}
+	currentVideos := make(map[string]Video)
+	currentGalleries := make(map[string]bool)
+	for _, video := range current.Videos {
+		currentVideos[video.Key()] = video
+		galleryKey := video.Category + "/" + video.Gallery
+		if !currentGalleries[galleryKey] && !previousGalleries[galleryKey] {
+			event := newEvent("gallery.created", video)
+			event.Video = ""
+			events = append(events, event)
+		}
+		currentGalleries[galleryKey] = true
+
+		// Hidden videos stay out of the stream and webhooks, like everywhere else they're left out
+		if slices.Contains(hiddenVideos, video.Key()) {
+			continue
+		}
+		previousVideo, existed := previousVideos[video.Key()]
+		if !existed {
+			events = append(events, newEvent("video.added", video))
+		} else if previousVideo.ThumbnailPath == "" && video.ThumbnailPath != "" {
+			events = append(events, newEvent("thumbnail.added", video))
+		}
+	}
+
+	removedGalleries := make(map[string]bool)
+	for _, video := range previous.Videos {
+		if _, exists := currentVideos[video.Key()]; !exists && !slices.Contains(hiddenVideos, video.Key()) {
+			events = append(events, newEvent("video.removed", video))
+		}
+		galleryKey := video.Category + "/" + video.Gallery
+		if !currentGalleries[galleryKey] && !removedGalleries[galleryKey] {
+			event := newEvent("gallery.removed", video)
+			event.Video = ""
+			events = append(events, event)
+			removedGalleries[galleryKey] = true
+		}
+	}
+	return events
+}
+
+var webhookClient = &http.Client{Timeout: 10 * time.Second}
+
+func (t *Tenant) sendWebhooks(events []LibraryEvent) {
+	webhookUrls := getList("WEBHOOK_URLS")
+	if len(webhookUrls) == 0 || len(events) == 0 {
+		return
+	}
+	log.Printf("Sending %d Webhook Events\n", len(events))
+
+	for _, event := range events {
+		body, err := json.Marshal(event)
+		if err != nil {
+			log.Println("Failed to encode webhook event: " + err.Error())
+			continue
+		}
+		for _, webhookUrl := range webhookUrls {
+			sendWebhook(webhookUrl, body)
+		}
+	}
+}
+
+// claimWebhookEvents keeps the events no other instance has sent, since every instance finds the same changes
+func (t *Tenant) claimWebhookEvents(events []LibraryEvent) []LibraryEvent {
+	if len(getList("WEBHOOK_URLS")) == 0 || len(events) == 0 {
+		return nil
+	}
+
+	// Instances refresh up to a cache lifetime apart
+	window := max(time.Hour, 2*getCacheTTL())
+	var claimed []LibraryEvent
+	err := t.updateMetadata(context.Background(), func(metadata *Metadata) {
+		claimed = nil
+		if metadata.WebhookClaims == nil {
+			metadata.WebhookClaims = make(map[string]time.Time)
+		}
+		for key, since := range metadata.WebhookClaims {
+			if time.Since(since) > window {
+				delete(metadata.WebhookClaims, key)
+			}
+		}
+		for _, event := range events {
+			key := event.Event + " " + event.Category + "/" + event.Gallery + "/" + event.Video
+			if _, found := metadata.WebhookClaims[key]; found {
+				continue
+			}
+			metadata.WebhookClaims[key] = event.Time
+			claimed = append(claimed, event)
+		}
+	})
+	if err != nil {
+		// Better sent twice than not at all
+		log.Println("Failed to claim webhook events: " + err.Error())
+		return events
+	}
+	return claimed
+}
+
+func (t *Tenant) subscribeEvents() chan []LibraryEvent {
+	events := make(chan []LibraryEvent, 8)
+	t.subscribersMutex.Lock()
+	if t.subscribers == nil {
+		t.subscribers = make(map[chan []LibraryEvent]struct{})
+	}
+	t.subscribers[events] = struct{}{}
+	t.subscribersMutex.Unlock()
+	return events
+}
+
+func (t *Tenant) unsubscribeEvents(events chan []LibraryEvent) {
+	t.subscribersMutex.Lock()
+	delete(t.subscribers, events)
+	t.subscribersMutex.Unlock()
+}
+
+// publishEvents hands the changes to every open stream, skipping streams that have fallen behind
+func (t *Tenant) publishEvents(events []LibraryEvent) {
+	if len(events) == 0 {
+		return
+	}
+	t.subscribersMutex.Lock()
+	defer t.subscribersMutex.Unlock()
+	for subscriber := range t.subscribers {
+		select {
+		case subscriber <- events:
+		default:
+		}
+	}
+}
+
+func sendWebhook(webhookUrl string, body []byte) {
+	for attempt := 1; attempt <= 3; attempt++ {
+		request, err := http.NewRequest(http.MethodPost, webhookUrl, bytes.NewReader(body))
+		if err != nil {
+			log.Println("Invalid webhook url: " + err.Error())
+			return
+		}
+		request.Header.Set("Content-Type", "application/json")
+
+		// Let receivers check the payload came from us
+		if secret := os.Getenv("WEBHOOK_SECRET"); secret != "" {
+			mac := hmac.New(sha256.New, []byte(secret))
+			mac.Write(body)
+			request.Header.Set("X-Signature-256", "sha256="+hex.EncodeToString(mac.Sum(nil)))
+		}
+
+		response, err := webhookClient.Do(request)
+		if err == nil {
+			_ = response.Body.Close()
+			if response.StatusCode < 300 {
+				return
+			}
+			err = fmt.Errorf("status %d", response.StatusCode)
+		}
+		log.Printf("Webhook attempt %d to %s failed: %v\n", attempt, webhookUrl, err)
+		time.Sleep(time.Duration(attempt) * 2 * time.Second)
+	}
+}
+
+type Digest struct {
+	Since     string
+	Count     int
+	Galleries []DigestGallery
+}
+
+type DigestGallery struct {
+	Name     string
+	Category string
+	Url      string
+	Videos   []string
+}
+
+func isDigestEnabled() bool {
+	return os.Getenv("SMTP_HOST") != "" && len(getList("DIGEST_RECIPIENTS")) > 0
+}
+
+// getPublicUrl is where links in emails point, the tenant's own host unless PUBLIC_URL is set
+func (t *Tenant) getPublicUrl() string {
+	if publicUrl := os.Getenv("PUBLIC_URL"); publicUrl != "" {
+		return strings.TrimSuffix(publicUrl, "/")
+	}
+	if t.Host != "" {
+		return "https://" + t.Host
+	}
+	return ""
+}
+
+// getDigest lists the videos uploaded since the given time, grouped by gallery
+func (t *Tenant) getDigest(since time.Time) Digest {
+	digest := Digest{Since: since.Format("2 January 2006")}
+	for _, gallery := range t.withoutProtected(withoutHiddenVideos(t.getPublicGalleries())) {
+		entry := DigestGallery{
+			Name:     gallery.Name,
+			Category: gallery.Category,
+		}
+		// Without a public url the galleries are listed without links
+		if publicUrl := t.getPublicUrl(); publicUrl != "" {
+			entry.Url = publicUrl + gallery.Stub
+			if isSignedGalleryLinks() {
+				entry.Url = publicUrl + t.signGalleryLink(gallery, time.Now().Add(guestLinkTTL))
+			}
+		}
+		for _, video := range gallery.Videos {
+			if video.Created.After(since) {
+				entry.Videos = append(entry.Videos, video.Name)
+			}
+		}
+		if len(entry.Videos) > 0 {
+			digest.Count += len(entry.Videos)
+			digest.Galleries = append(digest.Galleries, entry)
+		}
+	}
+	return digest
+}
+
+func (t *Tenant) sendDigest(digest Digest, recipients []string) error {
+	template, err := pug.CompileFile(viewsDir+"/digest.pug", pug.Options{})
+	if err != nil {
+		return err
+	}
+	var body bytes.Buffer
+	if err := template.Execute(&body, digest); err != nil {
+		return err
+	}
+
+	from := os.Getenv("SMTP_FROM")
+	if from == "" {
+		from = os.Getenv("SMTP_USERNAME")
+	}
+	var message bytes.Buffer
+	fmt.Fprintf(&message, "From: %s\r\n", from)
+	fmt.Fprintf(&message, "To: %s\r\n", strings.Join(recipients, ", "))
+	fmt.Fprintf(&message, "Subject: %s\r\n", mime.QEncoding.Encode("utf-8", fmt.Sprintf("%d new videos", digest.Count)))
+	fmt.Fprintf(&message, "Date: %s\r\n", time.Now().Format(time.RFC1123Z))
+	message.WriteString("MIME-Version: 1.0\r\n")
+	message.WriteString("Content-Type: text/html; charset=utf-8\r\n\r\n")
+	message.Write(body.Bytes())
+
+	host := os.Getenv("SMTP_HOST")
+	port := os.Getenv("SMTP_PORT")
+	if port == "" {
+		port = "587"
+	}
+	var auth smtp.Auth
+	if username := os.Getenv("SMTP_USERNAME"); username != "" {
+		auth = smtp.PlainAuth("", username, os.Getenv("SMTP_PASSWORD"), host)
+	}
+	log.Printf("Sending Digest of %d Videos\n", digest.Count)
+	return smtp.SendMail(net.JoinHostPort(host, port), auth, from, recipients, message.Bytes())
+}
+
+// sendDigests emails the new videos once every DIGEST_INTERVAL_HOURS
+func (t *Tenant) sendDigests(interval time.Duration) {
+	for range time.Tick(interval) {
+		// Settings are checked every time, so digests can be turned on by reloading the config
+		if !isDigestEnabled() {
+			continue
+		}
+		every := time.Duration(getConcurrency("DIGEST_INTERVAL_HOURS", 7*24)) * time.Hour
+
+		// Claiming the digest in the metadata keeps other instances from sending it too
+		var since *time.Time
+		claimed := false
+		now := time.Now().UTC()
+		err := t.updateMetadata(context.Background(), func(metadata *Metadata) {
+			since = metadata.DigestSent
+			claimed = since == nil || time.Since(*since) >= every
+			if claimed {
+				metadata.DigestSent = &now
+			}
+		})
+		if err != nil {
+			log.Println("Failed to claim the digest: " + err.Error())
+			continue
+		}
+
+		// The first run only marks where the next digest starts
+		if !claimed || since == nil {
+			continue
+		}
+		digest := t.getDigest(*since)
+		if digest.Count == 0 {
+			continue
+		}
+		if err := t.sendDigest(digest, getList("DIGEST_RECIPIENTS")); err != nil {
+			log.Println("Failed to send the digest: " + err.Error())
+
+			// Give the claim back, so the next run sends these videos instead of skipping them
+			err := t.updateMetadata(context.Background(), func(metadata *Metadata) {
+				if metadata.DigestSent != nil && metadata.DigestSent.Equal(now) {
+					metadata.DigestSent = since
+				}
+			})
+			if err != nil {
+				log.Println("Failed to release the digest: " + err.Error())
+			}
+		}
+	}
+}
+
+func (t *Tenant) digestHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to send a test digest")
+		return
+	}
+	if !isDigestEnabled() {
+		errorHandler(w, r, http.StatusBadRequest, "Set SMTP_HOST and DIGEST_RECIPIENTS to send digests")
+		return
+	}
+
+	// Covers the videos the next digest would, without moving it
+	since := time.Now().AddDate(0, 0, -7)
+	if sent := t.getMetadata().DigestSent; sent != nil {
+		since = *sent
+	}
+	recipients := getList("DIGEST_RECIPIENTS")
+	if to := r.FormValue("to"); to != "" {
+		recipients = []string{to}
+	}
+	if err := t.sendDigest(t.getDigest(since), recipients); err != nil {
+		errorHandler(w, r, http.StatusBadGateway, "Unable to send the digest: "+err.Error())
+		return
+	}
+	w.WriteHeader(http.StatusNoContent)
+}
+
+func errorHandler(w http.ResponseWriter, r *http.Request, status int, message string) {
+	errorPage := ErrorPage{
+		Status:  status,
+		Title:   http.StatusText(status),
+		Message: message,
+	}
+
+	// Machine readable errors
+	if strings.Contains(r.Header.Get("Accept"), "application/json") {
+		jsonString, err := json.Marshal(errorPage)
+		if err != nil {
+			http.Error(w, message, status)
+			return
+		}
+		w.Header().Set("Content-Type", "application/json")
+		w.WriteHeader(status)
+		_, _ = w.Write(jsonString)
+		return
+	}
+
+	template, err := pug.CompileFile(viewsDir+"/error.pug", pug.Options{})
+	if err != nil {
+		log.Println(err)
+		http.Error(w, message, status)
+		return
+	}
+	w.Header().Set("Content-Type", "text/html; charset=utf-8")
+	w.WriteHeader(status)
+	err = template.Execute(w, errorPage)
+	if err != nil {
+		log.Println(err)
+	}
+}
+
+// Request counts since the server started, shared by every tenant
+var requestMetrics struct {
+	started      time.Time
+	requests     atomic.Int64
+	inFlight     atomic.Int64
+	clientErrors atomic.Int64
+	serverErrors atomic.Int64
+}
+
+// statusRecorder remembers the status code of a response
+type statusRecorder struct {
+	http.ResponseWriter
+	status int
+}
+
+func (s *statusRecorder) WriteHeader(status int) {
+	if s.status == 0 {
+		s.status = status
+	}
+	s.ResponseWriter.WriteHeader(status)
+}
+
+func (s *statusRecorder) Write(content []byte) (int, error) {
+	if s.status == 0 {
+		s.status = http.StatusOK
+	}
+	return s.ResponseWriter.Write(content)
+}
+
+// Flush keeps server-sent events streaming through the recorder
+func (s *statusRecorder) Flush() {
+	if flusher, ok := s.ResponseWriter.(http.Flusher); ok {
+		flusher.Flush()
+	}
+}
+
+func (s *statusRecorder) Unwrap() http.ResponseWriter {
+	return s.ResponseWriter
+}
+
+func countRequests(next http.Handler) http.Handler {
+	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		requestMetrics.requests.Add(1)
+		requestMetrics.inFlight.Add(1)
+		defer requestMetrics.inFlight.Add(-1)
+
+		recorder := &statusRecorder{ResponseWriter: w}
+		next.ServeHTTP(recorder, r)
+		switch {
+		case recorder.status >= 500:
+			requestMetrics.serverErrors.Add(1)
+		case recorder.status >= 400:
+			requestMetrics.clientErrors.Add(1)
+		}
+	})
+}
+
+func recoverHandler(next http.Handler) http.Handler {
+	return http.HandlerFunc(func(w http.ResponseWriter, r *http.Request) {
+		recorder := &statusRecorder{ResponseWriter: w}
+		defer func() {
+			err := recover()
+			if err == nil {
+				return
+			}
+			// The server drops the connection quietly for these
+			if err == http.ErrAbortHandler {
+				panic(err)
+			}
+			log.Printf("Request failed: %s %v\n", r.URL.Path, err)
+
+			// Part of the response is already sent, so the client has to see it's cut off
+			if recorder.status != 0 {
+				panic(http.ErrAbortHandler)
+			}
+			errorHandler(w, r, http.StatusInternalServerError, "Something went wrong")
+		}()
+		next.ServeHTTP(recorder, r)
+	})
+}
+
+//go:embed public
+var publicFiles embed.FS
+
+//go:embed views
+var viewFiles embed.FS
+
+// Where the pug templates are read from, the built in ones are used when it's missing
+var viewsDir = "./views"
+
+// extractViews writes the built in templates to a temporary directory, next to the stylesheet they include
+func extractViews() (string, error) {
+	dir, err := os.MkdirTemp("", "video-gallery")
+	if err != nil {
+		return "", err
+	}
+	for _, files := range []embed.FS{viewFiles, publicFiles} {
+		err := fs.WalkDir(files, ".", func(name string, entry fs.DirEntry, err error) error {
+			if err != nil {
+				return err
+			}
+			target := filepath.Join(dir, name)
+			if entry.IsDir() {
+				return os.MkdirAll(target, 0o755)
+			}
+			content, err := fs.ReadFile(files, name)
+			if err != nil {
+				return err
+			}
+			return os.WriteFile(target, content, 0o644)
+		})
+		if err != nil {
+			return "", err
+		}
+	}
+	return filepath.Join(dir, "views"), nil
+}
+
+// publicAsset is a public file, which is also served under a name that changes with its content
+type publicAsset struct {
+	Name    string
+	Hashed  string
+	ETag    string
+	Content []byte
+}
+
+// getPublicAssets hashes the public files once, keyed by both their own and their hashed names
+var getPublicAssets = sync.OnceValue(func() map[string]publicAsset {
+	publicAssets := make(map[string]publicAsset)
+	err := fs.WalkDir(publicFiles, "public", func(name string, entry fs.DirEntry, err error) error {
+		if err != nil || entry.IsDir() {
+			return err
+		}
+		content, err := fs.ReadFile(publicFiles, name)
+		if err != nil {
+			return err
+		}
+
+		hash := sha1.Sum(content)
+		name = strings.TrimPrefix(name, "public/")
+		extension := path.Ext(name)
+		asset := publicAsset{
+			Name:    name,
+			Hashed:  fmt.Sprintf("%s.%x%s", strings.TrimSuffix(name, extension), hash[:4], extension),
+			ETag:    fmt.Sprintf("\"%x\"", hash[:8]),
+			Content: content,
+		}
+		publicAssets[asset.Name] = asset
+		publicAssets[asset.Hashed] = asset
+		return nil
+	})
+	if err != nil {
+		panic(err)
+	}
+	// Pages would link to a missing stylesheet
+	if _, ok := publicAssets["styles.css"]; !ok {
+		panic("public/styles.css is missing, run npm run build before go build")
+	}
+	return publicAssets
+})
+
+// assets gives the pages the hashed urls of the public files they link to
+type assets struct{}
+
+func (assets) Stylesheet() string {
+	return "/" + getPublicAssets()["styles.css"].Hashed
+}
+
+func publicHandler() http.HandlerFunc {
+	// Hash the files at startup rather than on the first request
+	getPublicAssets()
+
+	return func(w http.ResponseWriter, r *http.Request) {
+		// Unknown routes fall through to the public files
+		name := strings.TrimPrefix(path.Clean(r.URL.Path), "/")
+		if name == "" {
+			name = "index.html"
+		}
+
+		// Directories aren't files, so they're never listed
+		asset, ok := getPublicAssets()[name]
+		if !ok {
+			errorHandler(w, r, http.StatusNotFound, "Page not found")
+			return
+		}
+
+		w.Header().Set("ETag", asset.ETag)
+		// A hashed name always has the same content
+		if name == asset.Hashed {
+			w.Header().Set("Cache-Control", "public, max-age=31536000, immutable")
+		} else {
+			w.Header().Set("Cache-Control", "public, max-age=86400")
+		}
+		http.ServeContent(w, r, asset.Name, time.Time{}, bytes.NewReader(asset.Content))
+	}
+}
+
+func healthHandler(w http.ResponseWriter, _ *http.Request) {
+	_, _ = w.Write([]byte("OK"))
+}
+
+func (t *Tenant) checkStorage(ctx context.Context) HealthCheck {
+	ctx, cancel := context.WithTimeout(ctx, 5*time.Second)
+	defer cancel()
+
+	_, err := t.getBucket().Objects(ctx, &storage.Query{Delimiter: "/"}).Next()
+	if err != nil && !errors.Is(err, iterator.Done) {
+		return HealthCheck{Status: "error", Message: err.Error()}
+	}
+	return HealthCheck{Status: "ok"}
+}
+
+func (t *Tenant) checkCache() HealthCheck {
+	if err := t.getRefreshError(); err != nil {
+		return HealthCheck{Status: "degraded", Message: err.Error()}
+	}
+	cachedLibrary, found := t.videoCache.Get("videos")
+	if !found {
+		return HealthCheck{Status: "cold"}
+	}
+	library := cachedLibrary.(Library)
+	return HealthCheck{
+		Status:  "ok",
+		Message: fmt.Sprintf("refreshed %.0fs ago", time.Since(library.RefreshedAt).Seconds()),
+	}
+}
+
+func readyHandler(tenants []*Tenant) http.HandlerFunc {
+	return func(w http.ResponseWriter, r *http.Request) {
+		health := Health{
+			Status: "ok",
+			Checks: make(map[string]HealthCheck),
+		}
+		for _, tenant := range tenants {
+			storageCheck := tenant.checkStorage(r.Context())
+			if storageCheck.Status != "ok" {
+				health.Status = "error"
+			}
+			health.Checks["storage:"+tenant.BucketName] = storageCheck
+			cacheCheck := tenant.checkCache()
+			if cacheCheck.Status == "degraded" {
+				health.Degraded = true
+			}
+			health.Checks["cache:"+tenant.BucketName] = cacheCheck
+		}
+
+		// Convert to JSON
+		jsonString, err := json.Marshal(health)
+		if err != nil {
+			panic(err)
+		}
+		// Write JSON
+		w.Header().Set("Content-Type", "application/json")
+		if health.Status != "ok" {
+			w.WriteHeader(http.StatusServiceUnavailable)
+		}
+		_, err = w.Write(jsonString)
+		if err != nil {
+			return
+		}
+	}
+}
+
+func (t *Tenant) galleryHandler(w http.ResponseWriter, r *http.Request) {
+	log.Println("Generating Index")
+	t.renderIndex(w, r, t.getCategories(), false)
+}
+
+// categoryIndexHandler serves the index of a single category under its own key
+func (t *Tenant) categoryIndexHandler(category string) http.HandlerFunc {
+	return func(w http.ResponseWriter, r *http.Request) {
+		log.Println("Generating Index: " + category)
+
+		var categories []Category
+		for _, c := range t.getCategories() {
+			if c.Name == category {
+				// Whoever has the key was given this category, hidden or not
+				c.Hidden = false
+				categories = append(categories, c)
+			}
+		}
+		t.renderIndex(w, r, categories, true)
+	}
+}
+
+const (
+	indexLayoutFull      = "full"
+	indexLayoutCollapsed = "collapsed"
+	indexLayoutPreview   = "preview"
+)
+
+func validateIndexLayout(name string, value string) error {
+	if value != indexLayoutFull && value != indexLayoutCollapsed && value != indexLayoutPreview {
+		return fmt.Errorf("%s must be full, collapsed or preview", name)
+	}
+	return nil
+}
+
+// getIndexLayout picks the layout asked for in the query, falling back to INDEX_LAYOUT
+func getIndexLayout(query url.Values) string {
+	for _, layout := range []string{query.Get("layout"), os.Getenv("INDEX_LAYOUT")} {
+		if validateIndexLayout("", layout) == nil {
+			return layout
+		}
+	}
+	return indexLayoutFull
+}
+
+func getIndexPreviewCount() int {
+	count := os.Getenv("INDEX_PREVIEW_COUNT")
+	if count == "" {
+		return 8
+	}
+	value, err := strconv.Atoi(count)
+	if err != nil || value <= 0 {
+		panic("INDEX_PREVIEW_COUNT must be a positive number")
+	}
+	return value
+}
+
+func isIndexJumpBar() bool {
+	return os.Getenv("INDEX_JUMP_BAR") == "true"
+}
+
+func (t *Tenant) renderIndex(w http.ResponseWriter, r *http.Request, categories []Category, scoped bool) {
+	template, err := pug.CompileFile(viewsDir+"/index.pug", pug.Options{})
+	if err != nil {
+		panic(err)
+	}
+
+	err = template.Execute(w, t.getIndex(r, categories, scoped))
+	if err != nil {
+		panic(err)
+	}
+}
+
+// getIndex lays out the categories for the index page
+func (t *Tenant) getIndex(r *http.Request, categories []Category, scoped bool) Index {
+	query := r.URL.Query()
+
+	// A single category is always shown whole
+	layout := getIndexLayout(query)
+	if name := query.Get("category"); name != "" && !scoped {
+		categories = slices.DeleteFunc(categories, func(category Category) bool { return category.Name != name })
+		layout = indexLayoutFull
+	}
+	if scoped {
+		layout = indexLayoutFull
+	}
+
+	var jumpBar []IndexLetter
+	for i := range categories {
+		category := &categories[i]
+		sortGalleries(category.Galleries, query.Get("sort"))
+		category.Anchor = "category-" + strconv.Itoa(i)
+
+		// Only the galleries shown are sent, so large libraries keep a small page
+		if layout != indexLayoutFull {
+			category.Count = len(category.Galleries)
+			shown := 0
+			if layout == indexLayoutPreview {
+				shown = min(getIndexPreviewCount(), len(category.Galleries))
+			}
+			if shown < len(category.Galleries) {
+				link := url.Values{"category": {category.Name}}
+				if order := query.Get("sort"); order != "" {
+					link.Set("sort", order)
+				}
+				category.ViewAll = "?" + link.Encode()
+			}
+			category.Galleries = category.Galleries[:shown]
+		}
+
+		// Links from the index need a signature too
+		if isSignedGalleryLinks() {
+			for j := range category.Galleries {
+				category.Galleries[j].Stub = t.signGalleryLink(category.Galleries[j], time.Now().Add(24*time.Hour))
+			}
+		}
+
+		if isIndexJumpBar() {
+			first, _ := utf8.DecodeRuneInString(category.Name)
+			letter := strings.ToUpper(string(first))
+			if !unicode.IsLetter(first) {
+				letter = "#"
+			}
+			if !slices.ContainsFunc(jumpBar, func(l IndexLetter) bool { return l.Letter == letter }) {
+				jumpBar = append(jumpBar, IndexLetter{Letter: letter, Anchor: category.Anchor})
+			}
+		}
+	}
+	slices.SortFunc(jumpBar, func(a, b IndexLetter) int { return strings.Compare(a.Letter, b.Letter) })
+	popular := r.URL.Query()
+	popular.Set("sort", "popular")
+	return Index{
+		Categories:  categories,
+		Stats:       t.getStats(),
+		Degraded:    t.getRefreshError() != nil,
+		Scoped:      scoped,
+		JumpBar:     jumpBar,
+		PopularLink: "?" + popular.Encode(),
+	}
+}
+
+func filterGalleries(galleries []Gallery, category string, hasThumbnail string, tag string) []Gallery {
+	filtered := []Gallery{}
+	for _, gallery := range galleries {
+		if category != "" && gallery.Category != category {
+			continue
+		}
+		if hasThumbnail != "" || tag != "" {
+			var videos []Video
+			for _, video := range gallery.Videos {
+				if hasThumbnail != "" && (video.Thumbnail != nil) != (hasThumbnail == "true") {
+					continue
+				}
+				if tag != "" && !slices.Contains(video.Tags, tag) {
+					continue
+				}
+				videos = append(videos, video)
+			}
+			if len(videos) == 0 {
+				continue
+			}
+			gallery.Videos = videos
+		}
+		filtered = append(filtered, gallery)
+	}
+	return filtered
+}
+
+func (t *Tenant) feedHandler(w http.ResponseWriter, r *http.Request) {
+	log.Println("Generating Feed")
+
+	query := r.URL.Query()
+	galleries := append(withoutHiddenVideos(t.getPublicGalleries()), t.getCollections()...)
+	galleries = filterGalleries(galleries, query.Get("category"), query.Get("has_thumbnail"), query.Get("tag"))
+	if !isTokenRequest(r) {
+		galleries = t.withoutProtected(galleries)
+	}
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(galleries)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+// categoryFeedHandler serves /{SECRET_KEY}/feed/{CATEGORY}
+func (t *Tenant) categoryFeedHandler(w http.ResponseWriter, r *http.Request) {
+	category := strings.TrimPrefix(r.URL.Path, "/"+t.SecretKey+"/feed/")
+	t.writeCategoryFeed(w, r, t.getPublicGalleries(), category)
+}
+
+// categoryKeyFeedHandler serves the feed of a single category under its own key
+func (t *Tenant) categoryKeyFeedHandler(category string) http.HandlerFunc {
+	return func(w http.ResponseWriter, r *http.Request) {
+		// Unlike the feeds behind SECRET_KEY, these are handed out, so rated galleries need to be unlocked
+		t.writeCategoryFeed(w, r, t.withoutGated(r, t.getGalleries()), category)
+	}
+}
+
+func (t *Tenant) writeCategoryFeed(w http.ResponseWriter, r *http.Request, galleries []Gallery, category string) {
+	// An empty category would match every gallery
+	if category == "" {
+		errorHandler(w, r, http.StatusNotFound, "Category not found")
+		return
+	}
+	log.Println("Generating Feed: " + category)
+
+	query := r.URL.Query()
+	galleries = filterGalleries(withoutHiddenVideos(galleries), category, query.Get("has_thumbnail"), query.Get("tag"))
+	if !isTokenRequest(r) {
+		galleries = t.withoutProtected(galleries)
+	}
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(galleries)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+func getBaseUrl(r *http.Request) string {
+	scheme := "https"
+	if r.TLS == nil && r.Header.Get("X-Forwarded-Proto") != "https" {
+		scheme = "http"
+	}
+	return scheme + "://" + r.Host
+}
+
+func (t *Tenant) jsonFeedHandler(w http.ResponseWriter, r *http.Request) {
+	log.Println("Generating JSON Feed")
+
+	baseUrl := getBaseUrl(r)
+	feed := JsonFeed{
+		Version: "https://jsonfeed.org/version/1.1",
+		Title:   "Video Gallery",
+		FeedUrl: baseUrl + r.URL.Path,
+		Items:   []JsonFeedItem{},
+	}
+	// Token holders don't get to see the secret path
+	if !strings.HasPrefix(r.URL.Path, "/api/") {
+		feed.HomePageUrl = baseUrl + "/" + t.SecretKey + "/index"
+	}
+	galleries := filterGalleries(withoutHiddenVideos(t.getPublicGalleries()), "", "", r.URL.Query().Get("tag"))
+	if !isTokenRequest(r) {
+		galleries = t.withoutProtected(galleries)
+	}
+	for _, gallery := range galleries {
+		for _, video := range gallery.Videos {
+			mimeType := mime.TypeByExtension(path.Ext(video.VideoPath))
+			if mimeType == "" {
+				mimeType = "application/octet-stream"
+			}
+			item := JsonFeedItem{
+				Id:          video.Id,
+				Url:         baseUrl + gallery.Stub,
+				Title:       video.Name,
+				ContentText: video.Name + " in " + gallery.Category + " / " + gallery.Name,
+				Image:       video.Thumbnail,
+				Tags:        append([]string{gallery.Category, gallery.Name}, video.Tags...),
+				Attachments: []JsonFeedAttachment{{
+					Url:      video.Url,
+					MimeType: mimeType,
+				}},
+			}
+			if !video.Created.IsZero() {
+				created := video.Created
+				item.DatePublished = &created
+			}
+			feed.Items = append(feed.Items, item)
+		}
+	}
+
+	// Newest first
+	sort.SliceStable(feed.Items, func(i, j int) bool {
+		if feed.Items[i].DatePublished == nil || feed.Items[j].DatePublished == nil {
+			return feed.Items[j].DatePublished == nil && feed.Items[i].DatePublished != nil
+		}
+		return feed.Items[i].DatePublished.After(*feed.Items[j].DatePublished)
+	})
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(feed)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/feed+json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+func (t *Tenant) statsHandler(w http.ResponseWriter, _ *http.Request) {
+	log.Println("Generating Stats")
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(t.getStats())
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+func (t *Tenant) getMetrics() Metrics {
+	library := t.getLibrary()
+	metrics := Metrics{
+		UptimeSecs:       time.Since(requestMetrics.started).Seconds(),
+		Requests:         requestMetrics.requests.Load(),
+		InFlightRequests: requestMetrics.inFlight.Load(),
+		ClientErrors:     requestMetrics.clientErrors.Load(),
+		ServerErrors:     requestMetrics.serverErrors.Load(),
+		CacheAgeSecs:     time.Since(library.RefreshedAt).Seconds(),
+		LastRefreshSecs:  library.RefreshDuration.Seconds(),
+		Degraded:         t.getRefreshError() != nil,
+	}
+	t.subscribersMutex.Lock()
+	metrics.OpenStreams = len(t.subscribers)
+	t.subscribersMutex.Unlock()
+	t.jobsMutex.Lock()
+	for _, job := range t.jobs {
+		if job.getStatus() == jobRunning {
+			metrics.RunningJobs++
+		}
+	}
+	t.jobsMutex.Unlock()
+	t.viewsMutex.Lock()
+	for _, count := range t.pendingViews {
+		metrics.PendingViews += count
+	}
+	t.viewsMutex.Unlock()
+	t.refreshMutex.Lock()
+	metrics.Refreshes = t.refreshStats.count
+	metrics.FailedRefreshes = t.refreshStats.failures
+	t.refreshMutex.Unlock()
+	return metrics
+}
+
+func (t *Tenant) metricsHandler(w http.ResponseWriter, _ *http.Request) {
+	// Convert to JSON
+	jsonString, err := json.Marshal(t.getMetrics())
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+const metricPrefix = "custom.googleapis.com/video_gallery/"
+
+// newTimeSeries makes one point of a custom metric, counters are cumulative since the server started
+func newTimeSeries(project string, instance string, host string, name string, value float64, cumulative bool, now time.Time) *monitoring.TimeSeries {
+	interval := &monitoring.TimeInterval{EndTime: now.Format(time.RFC3339Nano)}
+	kind := "GAUGE"
+	if cumulative {
+		kind = "CUMULATIVE"
+		interval.StartTime = requestMetrics.started.Format(time.RFC3339Nano)
+	}
+	return &monitoring.TimeSeries{
+		Metric: &monitoring.Metric{
+			Type:   metricPrefix + name,
+			Labels: map[string]string{"instance": instance, "host": host},
+		},
+		Resource: &monitoring.MonitoredResource{
+			Type:   "global",
+			Labels: map[string]string{"project_id": project},
+		},
+		MetricKind: kind,
+		ValueType:  "DOUBLE",
+		Points:     []*monitoring.Point{{Interval: interval, Value: &monitoring.TypedValue{DoubleValue: &value}}},
+	}
+}
+
+// pushMetrics writes the metrics of every tenant to Cloud Monitoring until the server stops
+func pushMetrics(tenants []*Tenant, project string, interval time.Duration) {
+	service, err := monitoring.NewService(context.Background())
+	if err != nil {
+		log.Println("Unable to push metrics: " + err.Error())
+		return
+	}
+	// Every instance writes its own series, since a series only takes one writer
+	var random [4]byte
+	if _, err := rand.Read(random[:]); err != nil {
+		panic(err)
+	}
+	instance := hex.EncodeToString(random[:])
+
+	for range time.Tick(interval) {
+		now := time.Now()
+		var series []*monitoring.TimeSeries
+		for _, tenant := range tenants {
+			metrics := tenant.getMetrics()
+			host := tenant.Host
+			if host == "" {
+				host = "default"
+			}
+			degraded := 0.0
+			if metrics.Degraded {
+				degraded = 1
+			}
+			series = append(series,
+				newTimeSeries(project, instance, host, "cache_age_secs", metrics.CacheAgeSecs, false, now),
+				newTimeSeries(project, instance, host, "open_streams", float64(metrics.OpenStreams), false, now),
+				newTimeSeries(project, instance, host, "running_jobs", float64(metrics.RunningJobs), false, now),
+				newTimeSeries(project, instance, host, "pending_views", float64(metrics.PendingViews), false, now),
+				newTimeSeries(project, instance, host, "failed_refreshes", float64(metrics.FailedRefreshes), true, now),
+				newTimeSeries(project, instance, host, "degraded", degraded, false, now),
+			)
+		}
+		// Request counts aren't split by tenant
+		series = append(series,
+			newTimeSeries(project, instance, "all", "requests", float64(requestMetrics.requests.Load()), true, now),
+			newTimeSeries(project, instance, "all", "in_flight_requests", float64(requestMetrics.inFlight.Load()), false, now),
+			newTimeSeries(project, instance, "all", "client_errors", float64(requestMetrics.clientErrors.Load()), true, now),
+			newTimeSeries(project, instance, "all", "server_errors", float64(requestMetrics.serverErrors.Load()), true, now),
+		)
+
+		// A request takes at most 200 series
+		for start := 0; start < len(series); start += 200 {
+			request := &monitoring.CreateTimeSeriesRequest{TimeSeries: series[start:min(start+200, len(series))]}
+			if _, err := service.Projects.TimeSeries.Create("projects/"+project, request).Do(); err != nil {
+				log.Println("Failed to push metrics: " + err.Error())
+			}
+		}
+	}
+}
+
+func (t *Tenant) coverageHandler(w http.ResponseWriter, r *http.Request) {
+	log.Println("Generating Coverage Report")
+	coverage := t.getCoverage()
+
+	if strings.Contains(r.Header.Get("Accept"), "application/json") {
+		// Convert to JSON
+		jsonString, err := json.Marshal(coverage)
+		if err != nil {
+			panic(err)
+		}
+		// Write JSON
+		w.Header().Set("Content-Type", "application/json")
+		_, err = w.Write(jsonString)
+		if err != nil {
+			return
+		}
+		return
+	}
+
+	template, err := pug.CompileFile(viewsDir+"/coverage.pug", pug.Options{})
+	if err != nil {
+		panic(err)
+	}
+
+	err = template.Execute(w, CoverageReport{Galleries: coverage})
+	if err != nil {
+		panic(err)
+	}
+}
+
+func (t *Tenant) catalogHandler(w http.ResponseWriter, r *http.Request) {
+	// Get catalog level
+	prefix := "/" + t.SecretKey + "/catalog"
+	parts := strings.Split(strings.Trim(strings.TrimPrefix(r.URL.Path, prefix), "/"), "/")
+	if parts[0] == "" {
+		parts = []string{}
+	}
+	log.Println("Generating Catalog: " + strings.Join(parts, "/"))
+
+	var response any
+	switch len(parts) {
+	case 0:
+		// List categories
+		entries := []CatalogEntry{}
+		for _, category := range t.getPublicCategories() {
+			entries = append(entries, CatalogEntry{
+				Name:  category.Name,
+				Href:  prefix + "/" + url.PathEscape(category.Name),
+				Count: len(category.Galleries),
+			})
+		}
+		response = entries
+	case 1:
+		// List galleries in a category
+		entries := []CatalogEntry{}
+		for _, gallery := range t.getPublicGalleries() {
+			if gallery.Category == parts[0] {
+				entries = append(entries, CatalogEntry{
+					Name:  gallery.Name,
+					Href:  prefix + "/" + url.PathEscape(gallery.Category) + "/" + url.PathEscape(gallery.Name),
+					Count: len(gallery.Videos),
+					Cover: gallery.Cover,
+				})
+			}
+		}
+		if len(entries) == 0 {
+			errorHandler(w, r, http.StatusNotFound, "Category not found")
+			return
+		}
+		response = entries
+	case 2:
+		// List videos in a gallery
+		for _, gallery := range t.getPublicGalleries() {
+			if gallery.Category == parts[0] && gallery.Name == parts[1] {
+				response = toAdminGallery(gallery)
+			}
+		}
+		if response == nil {
+			errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+			return
+		}
+	default:
+		errorHandler(w, r, http.StatusNotFound, "Page not found")
+		return
+	}
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(response)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+var errThumbnailNotFound = errors.New("video thumbnail not found")
+
+func (t *Tenant) setCover(ctx context.Context, gallery Gallery, videoName string, actor string) error {
+	// Find the video thumbnail to use
+	var thumbnailPath string
+	for _, video := range gallery.Videos {
+		if video.Name == videoName {
+			thumbnailPath = video.ThumbnailPath
+		}
+	}
+	if thumbnailPath == "" {
+		return errThumbnailNotFound
+	}
+	log.Println("Setting Gallery Cover: " + thumbnailPath)
+
+	// Copy thumbnail next to the videos
+	extension := regexp.MustCompile(`\.[a-zA-Z0-9]+$`).FindString(thumbnailPath)
+	coverPath := path.Dir(thumbnailPath) + "/_cover" + extension
+	bucket := t.getBucket()
+	_, err := bucket.Object(coverPath).CopierFrom(bucket.Object(thumbnailPath)).Run(ctx)
+	if err != nil {
+		return err
+	}
+
+	t.audit(actor, "set_cover", map[string]string{
+		"gallery": gallery.Category + "/" + gallery.Name,
+		"video":   videoName,
+	})
+	go t.sendWebhooks([]LibraryEvent{{
+		Event:    "gallery.cover_set",
+		Time:     time.Now().UTC(),
+		Bucket:   t.BucketName,
+		Category: gallery.Category,
+		Gallery:  gallery.Name,
+		Video:    videoName,
+	}})
+
+	// Pick up the new cover on the next request
+	t.videoCache.Delete("videos")
+	return nil
+}
+
+func (t *Tenant) coverHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to set a cover")
+		return
+	}
+
+	gallery, err := t.getGallery("/gallery/" + r.FormValue("gallery"))
+	if err != nil {
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+
+	err = t.setCover(r.Context(), gallery, r.FormValue("video"), getActor(r))
+	if errors.Is(err, errThumbnailNotFound) {
+		errorHandler(w, r, http.StatusBadRequest, "Video thumbnail not found")
+		return
+	}
+	if err != nil {
+		panic(err)
+	}
+	w.WriteHeader(http.StatusNoContent)
+}
+
+func (t *Tenant) shareHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to share a gallery")
+		return
+	}
+
+	gallery, err := t.getGallery("/gallery/" + r.FormValue("gallery"))
+	if err != nil {
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+	galleryKey := gallery.Category + "/" + gallery.Name
+
+	ttl := guestLinkTTL
+	if hours := r.FormValue("expires_in"); hours != "" {
+		value, err := strconv.Atoi(hours)
+		if err != nil || value <= 0 {
+			errorHandler(w, r, http.StatusBadRequest, "expires_in must be a positive number of hours")
+			return
+		}
+		ttl = time.Duration(value) * time.Hour
+	}
+
+	// Revoke the links shared so far
+	if r.FormValue("rotate") == "true" {
+		log.Println("Rotating Gallery Key: " + galleryKey)
+		key := make([]byte, 16)
+		if _, err := rand.Read(key); err != nil {
+			panic(err)
+		}
+		err = t.updateMetadata(r.Context(), func(metadata *Metadata) {
+			if metadata.GalleryKeys == nil {
+				metadata.GalleryKeys = make(map[string]string)
+			}
+			metadata.GalleryKeys[galleryKey] = hex.EncodeToString(key)
+		})
+		if err != nil {
+			panic(err)
+		}
+		t.audit(getActor(r), "rotate_gallery_key", map[string]string{
+			"gallery": galleryKey,
+		})
+	}
+
+	expires := time.Now().Add(ttl).UTC().Truncate(time.Second)
+	link := GalleryLink{
+		Url:     getBaseUrl(r) + t.signGalleryLink(gallery, expires),
+		Expires: expires,
+	}
+	t.audit(getActor(r), "share_gallery", map[string]string{
+		"gallery": galleryKey,
+		"expires": expires.Format(time.RFC3339),
+	})
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(link)
+	if err != nil {
+		panic(err)
+	}
+
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+func (t *Tenant) tagsHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to change tags")
+		return
+	}
+
+	gallery, err := t.getGallery("/gallery/" + r.FormValue("gallery"))
+	if err != nil {
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+
+	// Tag the whole gallery unless a video is given
+	key := gallery.Category + "/" + gallery.Name
+	if videoName := r.FormValue("video"); videoName != "" {
+		key += "/" + videoName
+		if !slices.ContainsFunc(gallery.Videos, func(video Video) bool { return video.Name == videoName }) {
+			errorHandler(w, r, http.StatusNotFound, "Video not found")
+			return
+		}
+	}
+	var add []string
+	for _, tag := range r.Form["add"] {
+		if tag = strings.TrimSpace(tag); tag != "" {
+			add = append(add, tag)
+		}
+	}
+	remove := r.Form["remove"]
+	log.Println("Tagging: " + key)
+
+	err = t.updateMetadata(r.Context(), func(metadata *Metadata) {
+		if metadata.Tags == nil {
+			metadata.Tags = make(map[string][]string)
+		}
+		tags := mergeTags(metadata.Tags[key], add)
+		tags = slices.DeleteFunc(tags, func(tag string) bool { return slices.Contains(remove, tag) })
+		if len(tags) == 0 {
+			delete(metadata.Tags, key)
+		} else {
+			metadata.Tags[key] = tags
+		}
+	})
+	if err != nil {
+		panic(err)
+	}
+
+	t.audit(getActor(r), "tag", map[string]string{
+		"key":    key,
+		"add":    strings.Join(add, ","),
+		"remove": strings.Join(remove, ","),
+	})
+	w.WriteHeader(http.StatusNoContent)
+}
+
+func (t *Tenant) orderHandler(w http.ResponseWriter, r *http.Request) {
+	gallery, err := t.getGallery("/gallery/" + r.FormValue("gallery"))
+	if err != nil {
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+	galleryKey := gallery.Category + "/" + gallery.Name
+
+	switch r.Method {
+	case http.MethodGet:
+		names := []string{}
+		for _, video := range gallery.Videos {
+			names = append(names, video.Name)
+		}
+
+		// Convert to JSON
+		jsonString, err := json.Marshal(names)
+		if err != nil {
+			panic(err)
+		}
+		// Write JSON
+		w.Header().Set("Content-Type", "application/json")
+		_, err = w.Write(jsonString)
+		if err != nil {
+			return
+		}
+	case http.MethodPost:
+		// Videos left out follow the listed ones by name
+		order := r.Form["video"]
+		for i, name := range order {
+			if !slices.ContainsFunc(gallery.Videos, func(video Video) bool { return video.Name == name }) {
+				errorHandler(w, r, http.StatusNotFound, "Video not found: "+name)
+				return
+			}
+			if slices.Contains(order[:i], name) {
+				errorHandler(w, r, http.StatusBadRequest, "Video listed twice: "+name)
+				return
+			}
+		}
+		log.Println("Ordering Gallery: " + galleryKey)
+
+		err := t.updateMetadata(r.Context(), func(metadata *Metadata) {
+			if metadata.VideoOrder == nil {
+				metadata.VideoOrder = make(map[string][]string)
+			}
+			if len(order) == 0 {
+				delete(metadata.VideoOrder, galleryKey)
+			} else {
+				metadata.VideoOrder[galleryKey] = order
+			}
+		})
+		if err != nil {
+			panic(err)
+		}
+		t.audit(getActor(r), "order", map[string]string{
+			"gallery": galleryKey,
+			"videos":  strconv.Itoa(len(order)),
+		})
+		w.WriteHeader(http.StatusNoContent)
+	case http.MethodDelete:
+		log.Println("Resetting Gallery Order: " + galleryKey)
+		err := t.updateMetadata(r.Context(), func(metadata *Metadata) {
+			delete(metadata.VideoOrder, galleryKey)
+		})
+		if err != nil {
+			panic(err)
+		}
+		t.audit(getActor(r), "reset_order", map[string]string{
+			"gallery": galleryKey,
+		})
+		w.WriteHeader(http.StatusNoContent)
+	default:
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to set the order or DELETE to reset it")
+	}
+}
+
+func (t *Tenant) hiddenHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method == http.MethodGet {
+		hidden := t.getMetadata().HiddenVideos
+		if hidden == nil {
+			hidden = []string{}
+		}
+
+		// Convert to JSON
+		jsonString, err := json.Marshal(hidden)
+		if err != nil {
+			panic(err)
+		}
+		// Write JSON
+		w.Header().Set("Content-Type", "application/json")
+		_, err = w.Write(jsonString)
+		if err != nil {
+			return
+		}
+		return
+	}
+	if r.Method != http.MethodPost && r.Method != http.MethodDelete {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to hide a video or DELETE to show it again")
+		return
+	}
+
+	gallery, err := t.getGallery("/gallery/" + r.FormValue("gallery"))
+	if err != nil {
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+	videoName := r.FormValue("video")
+	if !slices.ContainsFunc(gallery.Videos, func(video Video) bool { return video.Name == videoName }) {
+		errorHandler(w, r, http.StatusNotFound, "Video not found")
+		return
+	}
+	key := gallery.Category + "/" + gallery.Name + "/" + videoName
+	hide := r.Method == http.MethodPost
+	if hide {
+		log.Println("Hiding Video: " + key)
+	} else {
+		log.Println("Showing Video: " + key)
+	}
+
+	err = t.updateMetadata(r.Context(), func(metadata *Metadata) {
+		metadata.HiddenVideos = slices.DeleteFunc(metadata.HiddenVideos, func(hidden string) bool { return hidden == key })
+		if hide {
+			metadata.HiddenVideos = append(metadata.HiddenVideos, key)
+		}
+	})
+	if err != nil {
+		panic(err)
+	}
+
+	action := "show"
+	if hide {
+		action = "hide"
+	}
+	t.audit(getActor(r), action, map[string]string{
+		"video": key,
+	})
+	w.WriteHeader(http.StatusNoContent)
+}
+
+var metadataColumns = []string{"category", "gallery", "video", "path", "media_type", "views", "tags", "hidden"}
+
+func (t *Tenant) getMetadataRows() []MetadataRow {
+	metadata := t.getMetadata()
+	rows := []MetadataRow{}
+	for _, gallery := range t.getGalleries() {
+		sortVideos(gallery.Videos, "")
+		for _, video := range gallery.Videos {
+			// Only the video's own tags, gallery tags are set on the gallery
+			tags := metadata.Tags[video.Key()]
+			if tags == nil {
+				tags = []string{}
+			}
+			rows = append(rows, MetadataRow{
+				Category:  video.Category,
+				Gallery:   video.Gallery,
+				Video:     video.Name,
+				Path:      video.VideoPath,
+				MediaType: video.MediaType,
+				Views:     video.Views,
+				Tags:      tags,
+				Hidden:    video.Hidden,
+			})
+		}
+	}
+	return rows
+}
+
+// readMetadataCsv reads rows by their header, so columns can be left out or in any order
+func readMetadataCsv(body io.Reader) ([]metadataImportRow, error) {
+	records, err := csv.NewReader(body).ReadAll()
+	if err != nil {
+		return nil, err
+	}
+	if len(records) == 0 {
+		return nil, fmt.Errorf("the CSV needs a header row")
+	}
+	columns := make(map[string]int)
+	for i, name := range records[0] {
+		columns[strings.TrimSpace(name)] = i
+	}
+	for _, name := range []string{"category", "gallery", "video"} {
+		if _, ok := columns[name]; !ok {
+			return nil, fmt.Errorf("the CSV needs a %s column", name)
+		}
+	}
+
+	var rows []metadataImportRow
+	for i, record := range records[1:] {
+		row := metadataImportRow{
+			Category: record[columns["category"]],
+			Gallery:  record[columns["gallery"]],
+			Video:    record[columns["video"]],
+		}
+		if column, ok := columns["tags"]; ok {
+			tags := []string{}
+			for _, tag := range strings.Split(record[column], ",") {
+				if tag = strings.TrimSpace(tag); tag != "" {
+					tags = append(tags, tag)
+				}
+			}
+			row.Tags = &tags
+		}
+		if column, ok := columns["hidden"]; ok {
+			hidden, err := strconv.ParseBool(strings.TrimSpace(record[column]))
+			if err != nil {
+				return nil, fmt.Errorf("row %d: hidden must be true or false", i+2)
+			}
+			row.Hidden = &hidden
+		}
+		rows = append(rows, row)
+	}
+	return rows, nil
+}
+
+// importMetadata checks every row and only applies them when they're all valid
+func (t *Tenant) importMetadata(ctx context.Context, rows []metadataImportRow, dryRun bool) (MetadataImport, error) {
+	result := MetadataImport{DryRun: dryRun, Rows: len(rows), Changed: []string{}}
+	metadata := t.getMetadata()
+	videos := make(map[string]bool)
+	for _, gallery := range t.getGalleries() {
+		for _, video := range gallery.Videos {
+			videos[video.Key()] = true
+		}
+	}
+
+	seen := make(map[string]bool)
+	for i, row := range rows {
+		// Rows are numbered like in a spreadsheet, after the header
+		key := row.Category + "/" + row.Gallery + "/" + row.Video
+		switch {
+		case !videos[key]:
+			result.Errors = append(result.Errors, MetadataRowError{Row: i + 2, Error: "Video not found: " + key})
+			continue
+		case seen[key]:
+			result.Errors = append(result.Errors, MetadataRowError{Row: i + 2, Error: "Video listed twice: " + key})
+			continue
+		}
+		seen[key] = true
+
+		changed := row.Tags != nil && !slices.Equal(mergeTags(*row.Tags), metadata.Tags[key])
+		changed = changed || row.Hidden != nil && *row.Hidden != slices.Contains(metadata.HiddenVideos, key)
+		if changed {
+			result.Changed = append(result.Changed, key)
+		}
+	}
+	if dryRun || len(result.Errors) > 0 || len(result.Changed) == 0 {
+		return result, nil
+	}
+
+	// A single metadata write, so the import applies completely or not at all
+	err := t.updateMetadata(ctx, func(metadata *Metadata) {
+		for _, row := range rows {
+			key := row.Category + "/" + row.Gallery + "/" + row.Video
+			if row.Tags != nil {
+				if metadata.Tags == nil {
+					metadata.Tags = make(map[string][]string)
+				}
+				if len(*row.Tags) == 0 {
+					delete(metadata.Tags, key)
+				} else {
+					metadata.Tags[key] = mergeTags(*row.Tags)
+				}
+			}
+			if row.Hidden != nil {
+				metadata.HiddenVideos = slices.DeleteFunc(metadata.HiddenVideos, func(hidden string) bool { return hidden == key })
+				if *row.Hidden {
+					metadata.HiddenVideos = append(metadata.HiddenVideos, key)
+				}
+			}
+		}
+	})
+	return result, err
+}
+
+func (t *Tenant) metadataHandler(w http.ResponseWriter, r *http.Request) {
+	var response any
+	switch r.Method {
+	case http.MethodGet:
+		log.Println("Exporting Metadata")
+		rows := t.getMetadataRows()
+		if r.URL.Query().Get("format") == "json" {
+			response = rows
+			break
+		}
+
+		w.Header().Set("Content-Type", "text/csv; charset=utf-8")
+		w.Header().Set("Content-Disposition", `attachment; filename="video-gallery-metadata.csv"`)
+		writer := csv.NewWriter(w)
+		_ = writer.Write(metadataColumns)
+		for _, row := range rows {
+			_ = writer.Write([]string{
+				row.Category,
+				row.Gallery,
+				row.Video,
+				row.Path,
+				row.MediaType,
+				strconv.Itoa(row.Views),
+				strings.Join(row.Tags, ", "),
+				strconv.FormatBool(row.Hidden),
+			})
+		}
+		writer.Flush()
+		return
+	case http.MethodPost:
+		var rows []metadataImportRow
+		var err error
+		body := http.MaxBytesReader(w, r.Body, 10*1024*1024)
+		if strings.HasPrefix(r.Header.Get("Content-Type"), "application/json") {
+			err = json.NewDecoder(body).Decode(&rows)
+		} else {
+			rows, err = readMetadataCsv(body)
+		}
+		if err != nil {
+			errorHandler(w, r, http.StatusBadRequest, "Couldn't read the import: "+err.Error())
+			return
+		}
+		dryRun := r.URL.Query().Get("dry_run") == "true"
+		log.Println("Importing Metadata: " + strconv.Itoa(len(rows)) + " rows")
+
+		result, err := t.importMetadata(r.Context(), rows, dryRun)
+		if err != nil {
+			panic(err)
+		}
+		if !dryRun && len(result.Errors) == 0 && len(result.Changed) > 0 {
+			t.audit(getActor(r), "import_metadata", map[string]string{
+				"rows":    strconv.Itoa(result.Rows),
+				"changed": strconv.Itoa(len(result.Changed)),
+			})
+		}
+		if len(result.Errors) > 0 {
+			w.Header().Set("Content-Type", "application/json")
+			w.WriteHeader(http.StatusBadRequest)
+		}
+		response = result
+	default:
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use GET to export or POST to import metadata")
+		return
+	}
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(response)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+func (t *Tenant) tagHandler(w http.ResponseWriter, r *http.Request) {
+	tag := strings.TrimPrefix(r.URL.Path, "/"+t.SecretKey+"/tag/")
+	log.Println("Generating Tag Page: " + tag)
+
+	videos := t.getTaggedVideos(tag)
+	if len(videos) == 0 {
+		errorHandler(w, r, http.StatusNotFound, "No videos are tagged "+tag)
+		return
+	}
+	sortVideos(videos, r.URL.Query().Get("sort"))
+
+	template, err := pug.CompileFile(viewsDir+"/gallery.pug", pug.Options{})
+	if err != nil {
+		panic(err)
+	}
+
+	err = template.Execute(w, Gallery{
+		Name:        tag,
+		Category:    "Tagged",
+		Videos:      videos,
+		PopularLink: "?sort=popular",
+	})
+	if err != nil {
+		panic(err)
+	}
+}
+
+func (t *Tenant) collectionsHandler(w http.ResponseWriter, r *http.Request) {
+	name := strings.TrimSpace(r.FormValue("name"))
+	switch r.Method {
+	case http.MethodGet:
+		// Convert to JSON
+		jsonString, err := json.Marshal(t.getCollections())
+		if err != nil {
+			panic(err)
+		}
+		// Write JSON
+		w.Header().Set("Content-Type", "application/json")
+		_, err = w.Write(jsonString)
+		if err != nil {
+			return
+		}
+	case http.MethodPost:
+		if name == "" {
+			errorHandler(w, r, http.StatusBadRequest, "A collection needs a name")
+			return
+		}
+
+		// Videos are given by id and kept by key, like tags
+		var add, remove []string
+		for _, id := range r.Form["add"] {
+			video, err := t.getVideo(id)
+			if err != nil {
+				errorHandler(w, r, http.StatusNotFound, "Video not found: "+id)
+				return
+			}
+			add = append(add, video.Key())
+		}
+		for _, id := range r.Form["remove"] {
+			if video, err := t.getVideo(id); err == nil {
+				remove = append(remove, video.Key())
+			}
+		}
+		log.Println("Updating Collection: " + name)
+
+		err := t.updateMetadata(r.Context(), func(metadata *Metadata) {
+			if metadata.Collections == nil {
+				metadata.Collections = make(map[string][]string)
+			}
+			keys := metadata.Collections[name]
+			if keys == nil {
+				keys = []string{}
+			}
+			for _, key := range add {
+				if !slices.Contains(keys, key) {
+					keys = append(keys, key)
+				}
+			}
+			metadata.Collections[name] = slices.DeleteFunc(keys, func(key string) bool { return slices.Contains(remove, key) })
+		})
+		if err != nil {
+			panic(err)
+		}
+
+		t.audit(getActor(r), "collection", map[string]string{
+			"name":   name,
+			"add":    strings.Join(add, ","),
+			"remove": strings.Join(remove, ","),
+		})
+		w.WriteHeader(http.StatusNoContent)
+	case http.MethodDelete:
+		if _, ok := t.getMetadata().Collections[name]; !ok {
+			errorHandler(w, r, http.StatusNotFound, "Collection not found")
+			return
+		}
+		log.Println("Deleting Collection: " + name)
+
+		err := t.updateMetadata(r.Context(), func(metadata *Metadata) {
+			delete(metadata.Collections, name)
+		})
+		if err != nil {
+			panic(err)
+		}
+
+		t.audit(getActor(r), "delete_collection", map[string]string{
+			"name": name,
+		})
+		w.WriteHeader(http.StatusNoContent)
+	default:
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to change a collection or DELETE to remove one")
+	}
+}
+
+func (t *Tenant) collectionHandler(w http.ResponseWriter, r *http.Request) {
+	// Collection pages can't be signed, so they would get around signed gallery links
+	if isSignedGalleryLinks() {
+		errorHandler(w, r, http.StatusNotFound, "Collection not found")
+		return
+	}
+
+	collections := t.getCollections()
+	index := slices.IndexFunc(collections, func(collection Gallery) bool { return collection.Stub == r.URL.Path })
+	if index < 0 {
+		log.Println("Collection not found: " + r.URL.Path)
+		errorHandler(w, r, http.StatusNotFound, "Collection not found")
+		return
+	}
+	collection := collections[index]
+	log.Println("Generating Collection Page: " + r.URL.Path)
+
+	t.selectRenditions(r, collection.Videos)
+	t.lockVideos(r, collection.Videos)
+	// Collections keep the order videos were added in unless asked otherwise
+	if order := r.URL.Query().Get("sort"); order != "" {
+		sortVideos(collection.Videos, order)
+	}
+	collection.PopularLink = "?sort=popular"
+	// Play all follows a single gallery
+	collection.Stub = ""
+
+	template, err := pug.CompileFile(viewsDir+"/gallery.pug", pug.Options{})
+	if err != nil {
+		panic(err)
+	}
+
+	err = template.Execute(w, collection)
+	if err != nil {
+		panic(err)
+	}
+}
+
+const uploadSessionTTL = time.Hour
+
+// getUploadMaxBytes is the largest file that can be uploaded, zero when there's no limit
+func getUploadMaxBytes() int64 {
+	maxBytes := os.Getenv("UPLOAD_MAX_BYTES")
+	if maxBytes == "" {
+		return 0
+	}
+	value, err := strconv.ParseInt(maxBytes, 10, 64)
+	if err != nil || value <= 0 {
+		panic("UPLOAD_MAX_BYTES must be a positive number")
+	}
+	return value
+}
+
+// startUpload signs a resumable upload session for name, writing the error when the upload isn't allowed.
+// The session only replaces the object at generation, or creates it when generation is 0.
+func (t *Tenant) startUpload(w http.ResponseWriter, r *http.Request, name string, generation int64) (UploadSession, bool) {
+	// The bytes never pass through here, so the size is checked up front and enforced by the bucket
+	headers := map[string]string{
+		"x-goog-resumable":           "start",
+		"x-goog-if-generation-match": strconv.FormatInt(generation, 10),
+	}
+	if maxBytes := getUploadMaxBytes(); maxBytes > 0 {
+		size, err := strconv.ParseInt(r.FormValue("size"), 10, 64)
+		if err != nil || size <= 0 {
+			errorHandler(w, r, http.StatusBadRequest, "size must be the file size in bytes")
+			return UploadSession{}, false
+		}
+		if size > maxBytes {
+			errorHandler(w, r, http.StatusRequestEntityTooLarge, "Uploads can be at most "+formatBytes(maxBytes))
+			return UploadSession{}, false
+		}
+		headers["x-goog-content-length-range"] = "0," + strconv.FormatInt(maxBytes, 10)
+	}
+	log.Println("Starting Upload: " + name)
+
+	// The client starts a resumable session with this url and sends the chunks straight to the bucket
+	var signedHeaders []string
+	for header, value := range headers {
+		signedHeaders = append(signedHeaders, header+":"+value)
+	}
+	expires := time.Now().Add(uploadSessionTTL).UTC().Truncate(time.Second)
+	signedUrl, err := t.getBucket().SignedURL(name, &storage.SignedURLOptions{
+		Expires: expires,
+		Method:  "POST",
+		Headers: signedHeaders,
+		Scheme:  storage.SigningSchemeV4,
+	})
+	if err != nil {
+		panic(err)
+	}
+	return UploadSession{
+		Url:     signedUrl,
+		Method:  "POST",
+		Headers: headers,
+		Expires: expires,
+	}, true
+}
+
+func (t *Tenant) uploadHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to start an upload")
+		return
+	}
+	if isStorageAnonymous() {
+		errorHandler(w, r, http.StatusBadRequest, "Uploads need storage credentials")
+		return
+	}
+
+	name := r.FormValue("path")
+	if problem := checkUploadPath(name); problem != "" {
+		errorHandler(w, r, http.StatusBadRequest, problem)
+		return
+	}
+	// Replacing a file goes through versions, so it's archived first
+	if _, err := t.getBucket().Object(name).Attrs(r.Context()); err == nil {
+		errorHandler(w, r, http.StatusConflict, "A file with that path already exists, use versions to replace it")
+		return
+	}
+	session, ok := t.startUpload(w, r, name, 0)
+	if !ok {
+		return
+	}
+	t.audit(getActor(r), "upload", map[string]string{
+		"path": name,
+	})
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(session)
+	if err != nil {
+		panic(err)
+	}
+
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+// checkUploadPath says what's wrong with the path of an upload, or nothing when it can be uploaded to
+func checkUploadPath(name string) string {
+	// Uploads go into a gallery folder like every other video
+	parts := strings.Split(name, "/")
+	if len(parts) != 3 || !isValidFolderName(parts[0]) || !isValidFolderName(parts[1]) || !isSafeName(parts[2]) {
+		return "path must look like {CATEGORY}/{GALLERY}/{FILE}"
+	}
+	extension := strings.ToLower(path.Ext(name))
+	videoExtensions := getExtensions("VIDEO_EXTENSIONS", []string{".mp4", ".m4v", ".webm", ".mov", ".avi", ".mkv", ".ts"})
+	if !slices.Contains(videoExtensions, extension) && !isImageFile(name) {
+		return "Only videos and images can be uploaded"
+	}
+	return ""
+}
+
+func isImageFile(name string) bool {
+	imageExtensions := getExtensions("IMAGE_EXTENSIONS", []string{".jpg", ".jpeg", ".png", ".heic", ".webp", ".avif"})
+	return slices.Contains(imageExtensions, strings.ToLower(path.Ext(name)))
+}
+
+// verifyImage checks that the start of a file is one of the image formats galleries show
+func verifyImage(header []byte) error {
+	switch {
+	case bytes.HasPrefix(header, []byte{0xFF, 0xD8, 0xFF}):
+		return nil
+	case bytes.HasPrefix(header, []byte("\x89PNG\r\n\x1a\n")):
+		return nil
+	case len(header) >= 12 && string(header[0:4]) == "RIFF" && string(header[8:12]) == "WEBP":
+		return nil
+	case len(header) >= 12 && string(header[4:8]) == "ftyp" &&
+		slices.Contains([]string{"heic", "heix", "hevc", "mif1", "msf1", "avif", "avis"}, string(header[8:12])):
+		// HEIC and AVIF
+		return nil
+	}
+	return fmt.Errorf("not a JPEG, PNG, WebP, HEIC or AVIF image")
+}
+
+// scanWithClamd sends a file to clamd with the INSTREAM command, and returns the name of what it found
+func scanWithClamd(ctx context.Context, address string, file io.Reader) (string, error) {
+	network := "tcp"
+	if socket, found := strings.CutPrefix(address, "unix:"); found {
+		network, address = "unix", socket
+	}
+	var dialer net.Dialer
+	conn, err := dialer.DialContext(ctx, network, address)
+	if err != nil {
+		return "", err
+	}
+	defer conn.Close()
+	if deadline, ok := ctx.Deadline(); ok {
+		_ = conn.SetDeadline(deadline)
+	}
+
+	// The file goes in chunks with their length in front, and an empty chunk ends it
+	if _, err := io.WriteString(conn, "zINSTREAM\x00"); err != nil {
+		return "", err
+	}
+	chunk := make([]byte, 64*1024)
+	for {
+		n, err := file.Read(chunk)
+		if n > 0 {
+			if _, err := conn.Write(binary.BigEndian.AppendUint32(nil, uint32(n))); err != nil {
+				return "", err
+			}
+			if _, err := conn.Write(chunk[:n]); err != nil {
+				return "", err
+			}
+		}
+		if errors.Is(err, io.EOF) {
+			break
+		}
+		if err != nil {
+			return "", err
+		}
+	}
+	if _, err := conn.Write(make([]byte, 4)); err != nil {
+		return "", err
+	}
+
+	// Like "stream: OK" or "stream: Eicar-Signature FOUND"
+	reply, err := bufio.NewReader(conn).ReadString(0)
+	if err != nil && !errors.Is(err, io.EOF) {
+		return "", err
+	}
+	result := strings.TrimSpace(strings.TrimPrefix(strings.TrimSuffix(reply, "\x00"), "stream:"))
+	switch {
+	case result == "OK":
+		return "", nil
+	case strings.HasSuffix(result, " FOUND"):
+		return strings.TrimSuffix(result, " FOUND"), nil
+	}
+	return "", fmt.Errorf("clamd answered %q", result)
+}
+
+// finalizeUploadHandler checks a finished upload, and deletes it when it isn't a video or image or clamd finds malware in it
+func (t *Tenant) finalizeUploadHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to finalize an upload")
+		return
+	}
+	if isStorageAnonymous() {
+		errorHandler(w, r, http.StatusBadRequest, "Uploads need storage credentials")
+		return
+	}
+	name := r.FormValue("path")
+	if problem := checkUploadPath(name); problem != "" {
+		errorHandler(w, r, http.StatusBadRequest, problem)
+		return
+	}
+	object := t.getBucket().Object(name)
+	attrs, err := object.Attrs(r.Context())
+	if errors.Is(err, storage.ErrObjectNotExist) {
+		errorHandler(w, r, http.StatusNotFound, "Upload not found")
+		return
+	}
+	if err != nil {
+		panic(err)
+	}
+	// Only the file that was checked is deleted, not one uploaded again in the meantime
+	object = object.Generation(attrs.Generation)
+
+	var problem error
+	if attrs.Size == 0 {
+		problem = fmt.Errorf("file is empty")
+	} else if isImageFile(name) {
+		header, _, err := readRange(r.Context(), object, 0, 16)
+		if err != nil {
+			panic(err)
+		}
+		problem = verifyImage(header)
+	} else {
+		problem = t.verifyVideo(r.Context(), name)
+	}
+	scanned := false
+	if address := os.Getenv("CLAMD_ADDRESS"); problem == nil && address != "" {
+		reader, err := object.NewReader(r.Context())
+		if err != nil {
+			panic(err)
+		}
+		found, err := scanWithClamd(r.Context(), address, reader)
+		_ = reader.Close()
+		if err != nil {
+			log.Println("Failed to scan " + name + ": " + err.Error())
+			errorHandler(w, r, http.StatusBadGateway, "The upload can't be scanned right now")
+			return
+		}
+		if found != "" {
+			problem = fmt.Errorf("clamd found %s", found)
+		}
+		scanned = true
+	}
+
+	if problem != nil {
+		log.Println("Rejecting Upload: " + name + ": " + problem.Error())
+		if err := object.Delete(r.Context()); err != nil && !errors.Is(err, storage.ErrObjectNotExist) {
+			panic(err)
+		}
+		t.audit(getActor(r), "reject_upload", map[string]string{
+			"path":   name,
+			"reason": problem.Error(),
+		})
+		errorHandler(w, r, http.StatusUnprocessableEntity, "The upload was deleted: "+problem.Error())
+		return
+	}
+
+	// Show the new file without waiting for the cache to expire
+	t.videoCache.Delete("videos")
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(map[string]any{"path": name, "bytes": attrs.Size, "scanned": scanned})
+	if err != nil {
+		panic(err)
+	}
+
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+const shortCodeLength = 7
+
+const shortCodeAlphabet = "abcdefghijkmnpqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ23456789"
+
+func newShortCode() string {
+	// Bytes from the top of the range that doesn't divide evenly are skipped, so every letter is as likely
+	limit := 256 - 256%len(shortCodeAlphabet)
+	code := make([]byte, 0, shortCodeLength)
+	random := make([]byte, shortCodeLength)
+	for len(code) < shortCodeLength {
+		if _, err := rand.Read(random); err != nil {
+			panic(err)
+		}
+		for _, value := range random {
+			if int(value) < limit && len(code) < shortCodeLength {
+				code = append(code, shortCodeAlphabet[int(value)%len(shortCodeAlphabet)])
+			}
+		}
+	}
+	return string(code)
+}
+
+func (t *Tenant) shortenHandler(w http.ResponseWriter, r *http.Request) {
+	var response any
+	switch r.Method {
+	case http.MethodGet:
+		// List the links with their clicks
+		response = t.getMetadata().ShortLinks
+	case http.MethodPost:
+		gallery, err := t.getGallery("/gallery/" + r.FormValue("gallery"))
+		if err != nil {
+			errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+			return
+		}
+		link := ShortLink{Gallery: gallery.Category + "/" + gallery.Name}
+		if videoName := r.FormValue("video"); videoName != "" {
+			if !slices.ContainsFunc(gallery.Videos, func(video Video) bool { return video.Name == videoName }) {
+				errorHandler(w, r, http.StatusNotFound, "Video not found")
+				return
+			}
+			link.Video = videoName
+		}
+		if hours := r.FormValue("expires_in"); hours != "" {
+			value, err := strconv.Atoi(hours)
+			if err != nil || value <= 0 {
+				errorHandler(w, r, http.StatusBadRequest, "expires_in must be a positive number of hours")
+				return
+			}
+			expires := time.Now().Add(time.Duration(value) * time.Hour).UTC().Truncate(time.Second)
+			link.Expires = &expires
+		}
+
+		var code string
+		err = t.updateMetadata(r.Context(), func(metadata *Metadata) {
+			if metadata.ShortLinks == nil {
+				metadata.ShortLinks = make(map[string]ShortLink)
+			}
+			for {
+				code = newShortCode()
+				if _, exists := metadata.ShortLinks[code]; !exists {
+					break
+				}
+			}
+			metadata.ShortLinks[code] = link
+		})
+		if err != nil {
+			panic(err)
+		}
+		log.Println("Created Short Link: " + code)
+		t.audit(getActor(r), "short_link", map[string]string{
+			"code":    code,
+			"gallery": link.Gallery,
+			"video":   link.Video,
+		})
+		response = CreatedShortLink{
+			Code:      code,
+			Url:       getBaseUrl(r) + "/s/" + code,
+			ShortLink: link,
+		}
+	default:
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to create a short link")
+		return
+	}
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(response)
+	if err != nil {
+		panic(err)
+	}
+
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+func (t *Tenant) shortLinkHandler(w http.ResponseWriter, r *http.Request) {
+	code := strings.TrimPrefix(r.URL.Path, "/s/")
+	link, ok := t.getMetadata().ShortLinks[code]
+	if !ok {
+		errorHandler(w, r, http.StatusNotFound, "Link not found")
+		return
+	}
+	if link.Expires != nil && time.Now().After(*link.Expires) {
+		errorHandler(w, r, http.StatusGone, "This link has expired")
+		return
+	}
+
+	var target string
+	metadata := t.getMetadata()
+	for _, gallery := range t.getGalleries() {
+		// Links made before a category turned admin only stop working
+		if gallery.Category+"/"+gallery.Name != link.Gallery || isCategoryAdminOnly(gallery.Category) {
+			continue
+		}
+		// Short links stand in for a signed link
+		target = gallery.Stub
+		if isSignedGalleryLinks() {
+			target = t.signGalleryLink(gallery, time.Now().Add(24*time.Hour))
+		}
+		for _, video := range gallery.Videos {
+			if link.Video != "" && video.Name == link.Video {
+				target = video.Url
+				// Hidden videos aren't shared, not even through their gallery
+				if slices.Contains(metadata.HiddenVideos, video.Key()) {
+					target = ""
+					break
+				}
+				// Protected and rated videos ask for their password or PIN first
+				if getProtection(metadata, video) != "" || !t.passesGate(r, metadata, video.Category, video.Gallery) {
+					target = "/video/" + video.Id
+					if isSignedGalleryLinks() {
+						target += "?" + t.signGalleryQuery(gallery, time.Now().Add(24*time.Hour))
+					}
+				}
+			}
+		}
+	}
+	if target == "" {
+		errorHandler(w, r, http.StatusNotFound, "Link not found")
+		return
+	}
+	log.Println("Following Short Link: " + code)
+
+	// Clicks are saved in batches with the views
+	t.viewsMutex.Lock()
+	t.pendingClicks[code]++
+	t.viewsMutex.Unlock()
+	http.Redirect(w, r, target, http.StatusFound)
+}
+
+const (
+	scopeRead   = "read"
+	scopeViewer = "viewer"
+	scopeEditor = "editor"
+	scopeAdmin  = "admin"
+)
+
+// scopeRanks orders the scopes, each one can do everything the ones below it can
+var scopeRanks = map[string]int{
+	scopeRead:   0,
+	scopeViewer: 1,
+	scopeEditor: 2,
+	scopeAdmin:  3,
+}
+
+// requireToken lets requests through with a bearer token of the given scope or a higher one
+func (t *Tenant) requireToken(scope string, next http.HandlerFunc) http.HandlerFunc {
+	return func(w http.ResponseWriter, r *http.Request) {
+		token, found := strings.CutPrefix(r.Header.Get("Authorization"), "Bearer ")
+		if !found || token == "" {
+			w.Header().Set("WWW-Authenticate", "Bearer")
+			errorHandler(w, r, http.StatusUnauthorized, "An API token is required")
+			return
+		}
+		hash := sha256.Sum256([]byte(token))
+		apiToken, ok := t.getMetadata().ApiTokens[hex.EncodeToString(hash[:])]
+		if !ok {
+			w.Header().Set("WWW-Authenticate", "Bearer")
+			errorHandler(w, r, http.StatusUnauthorized, "The API token is not valid")
+			return
+		}
+		if scopeRanks[apiToken.Scope] < scopeRanks[scope] {
+			errorHandler(w, r, http.StatusForbidden, "The API token needs the "+scope+" scope")
+			return
+		}
+		next(w, r.WithContext(context.WithValue(r.Context(), apiTokenContextKey{}, apiToken.Name)))
+	}
+}
+
+// requireRole lets viewers look at an admin route, while changes need the given scope
+func (t *Tenant) requireRole(scope string, next http.HandlerFunc) http.HandlerFunc {
+	view := t.requireToken(scopeViewer, next)
+	change := t.requireToken(scope, next)
+	return func(w http.ResponseWriter, r *http.Request) {
+		if r.Method == http.MethodGet || r.Method == http.MethodHead {
+			view(w, r)
+			return
+		}
+		change(w, r)
+	}
+}
+
+func (t *Tenant) tokensHandler(w http.ResponseWriter, r *http.Request) {
+	var response any
+	switch r.Method {
+	case http.MethodGet:
+		tokens := []ApiToken{}
+		for _, apiToken := range t.getMetadata().ApiTokens {
+			tokens = append(tokens, apiToken)
+		}
+		sort.Slice(tokens, func(i, j int) bool {
+			return tokens[i].Name < tokens[j].Name
+		})
+		response = tokens
+	case http.MethodPost:
+		apiToken := ApiToken{
+			Name:    r.FormValue("name"),
+			Scope:   r.FormValue("scope"),
+			Created: time.Now().UTC().Truncate(time.Second),
+		}
+		if apiToken.Name == "" {
+			errorHandler(w, r, http.StatusBadRequest, "Tokens need a name")
+			return
+		}
+		if apiToken.Scope == "" {
+			apiToken.Scope = scopeRead
+		}
+		if _, ok := scopeRanks[apiToken.Scope]; !ok {
+			errorHandler(w, r, http.StatusBadRequest, "scope must be read, viewer, editor or admin")
+			return
+		}
+
+		// Only the hash is kept, so the token is shown once
+		random := make([]byte, 32)
+		if _, err := rand.Read(random); err != nil {
+			panic(err)
+		}
+		token := "vg_" + base64.RawURLEncoding.EncodeToString(random)
+		hash := sha256.Sum256([]byte(token))
+		nameTaken := false
+		err := t.updateMetadata(r.Context(), func(metadata *Metadata) {
+			if metadata.ApiTokens == nil {
+				metadata.ApiTokens = make(map[string]ApiToken)
+			}
+			for _, existing := range metadata.ApiTokens {
+				if existing.Name == apiToken.Name {
+					nameTaken = true
+				}
+			}
+			if !nameTaken {
+				metadata.ApiTokens[hex.EncodeToString(hash[:])] = apiToken
+			}
+		})
+		if err != nil {
+			panic(err)
+		}
+		if nameTaken {
+			errorHandler(w, r, http.StatusConflict, "A token with that name exists")
+			return
+		}
+		log.Println("Created API Token: " + apiToken.Name)
+		t.audit(getActor(r), "create_token", map[string]string{
+			"name":  apiToken.Name,
+			"scope": apiToken.Scope,
+		})
+		response = CreatedApiToken{Token: token, ApiToken: apiToken}
+	case http.MethodDelete:
+		name := r.FormValue("name")
+		err := t.updateMetadata(r.Context(), func(metadata *Metadata) {
+			for hash, apiToken := range metadata.ApiTokens {
+				if apiToken.Name == name {
+					delete(metadata.ApiTokens, hash)
+				}
+			}
+		})
+		if err != nil {
+			panic(err)
+		}
+		log.Println("Revoked API Token: " + name)
+		t.audit(getActor(r), "revoke_token", map[string]string{
+			"name": name,
+		})
+		w.WriteHeader(http.StatusNoContent)
+		return
+	default:
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to create a token")
+		return
+	}
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(response)
+	if err != nil {
+		panic(err)
+	}
+
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+const placeholderName = "_gallery"
+
+// isSafeName is true for a single path segment that can't walk out of its folder or break log lines
+func isSafeName(name string) bool {
+	return name != "" && name != "." && name != ".." && len(name) <= 255 && utf8.ValidString(name) &&
+		strings.TrimSpace(name) == name && !strings.ContainsAny(name, "/\\") && !strings.ContainsFunc(name, unicode.IsControl)
+}
+
+// Names starting with an underscore are kept for the service's own objects
+func isValidFolderName(name string) bool {
+	return isSafeName(name) && !strings.HasPrefix(name, "_")
+}
+
+// KodiNfo is the movie metadata Kodi and Jellyfin read next to a .strm file
+type KodiNfo struct {
+	XMLName   xml.Name `xml:"movie"`
+	Title     string   `xml:"title"`
+	Set       string   `xml:"set"`
+	Genre     string   `xml:"genre"`
+	Tags      []string `xml:"tag"`
+	Thumb     string   `xml:"thumb,omitempty"`
+	Premiered string   `xml:"premiered,omitempty"`
+}
+
+type VerifyProgress struct {
+	Checked int `json:"checked"`
+	Total   int `json:"total"`
+}
+
+type UnreadableVideo struct {
+	Name  string `json:"name"`
+	Path  string `json:"path"`
+	Error string `json:"error"`
+}
+
+type VerifyReport struct {
+	Checked int `json:"checked"`
+	// Unreadable videos keyed by category and gallery name
+	Unreadable map[string][]UnreadableVideo `json:"unreadable"`
+}
+
+type DeletePreview struct {
+	Objects int    `json:"objects"`
+	Bytes   int64  `json:"bytes"`
+	Confirm string `json:"confirm"`
+}
+
+// ArchiveCandidate is a gallery that hasn't been watched or added to for a while
+type ArchiveCandidate struct {
+	Category   string    `json:"category"`
+	Gallery    string    `json:"gallery"`
+	Stub       string    `json:"stub"`
+	Views      int       `json:"views"`
+	Bytes      int64     `json:"bytes"`
+	LastUpload time.Time `json:"last_upload"`
+}
+
+type ArchiveResult struct {
+	StorageClass string `json:"storage_class"`
+	Objects      int    `json:"objects"`
+	Bytes        int64  `json:"bytes"`
+	Skipped      int    `json:"skipped"`
+}
+
+var storageClasses = []string{"STANDARD", "NEARLINE", "COLDLINE", "ARCHIVE"}
+
+// Orphan is a sidecar or archived version left behind by a video that no longer exists
+type Orphan struct {
+	Path  string `json:"path"`
+	Kind  string `json:"kind"`
+	Bytes int64  `json:"bytes"`
+}
+
+type OrphanReport struct {
+	Orphans []Orphan `json:"orphans"`
+	Bytes   int64    `json:"bytes"`
+	Confirm string   `json:"confirm"`
+}
+
+type DeleteProgress struct {
+	Deleted int    `json:"deleted"`
+	Total   int    `json:"total"`
+	Done    bool   `json:"done,omitempty"`
+	Error   string `json:"error,omitempty"`
+}
+
+func (t *Tenant) galleriesHandler(w http.ResponseWriter, r *http.Request) {
+	category := r.FormValue("category")
+	gallery := r.FormValue("gallery")
+	if !isValidFolderName(category) || (gallery != "" && !isValidFolderName(gallery)) {
+		errorHandler(w, r, http.StatusBadRequest, "category and gallery must be plain folder names")
+		return
+	}
+
+	switch r.Method {
+	case http.MethodPost:
+		if gallery == "" {
+			errorHandler(w, r, http.StatusBadRequest, "gallery is required")
+			return
+		}
+		if _, err := t.getGallery(getStub(category+"/"+gallery, t.SecretKey, stubLength)); err == nil {
+			errorHandler(w, r, http.StatusConflict, "Gallery already exists")
+			return
+		}
+		log.Println("Creating Gallery: " + category + "/" + gallery)
+
+		// Folders only exist while something is in them
+		writer := t.getBucket().Object(category + "/" + gallery + "/" + placeholderName).If(storage.Conditions{DoesNotExist: true}).NewWriter(r.Context())
+		if err := writer.Close(); err != nil {
+			panic(err)
+		}
+		t.audit(getActor(r), "create_gallery", map[string]string{
+			"category": category,
+			"gallery":  gallery,
+		})
+		t.videoCache.Delete("videos")
+		w.WriteHeader(http.StatusCreated)
+	case http.MethodDelete:
+		// Only folders the library knows about can be removed
+		if !slices.ContainsFunc(t.getGalleries(), func(g Gallery) bool { return g.Category == category && (gallery == "" || g.Name == gallery) }) {
+			errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+			return
+		}
+		t.deleteFolder(w, r, category, gallery)
+	default:
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to create a gallery or DELETE to remove one")
+	}
+}
+
+func (t *Tenant) archiveHandler(w http.ResponseWriter, r *http.Request) {
+	var response any
+	switch r.Method {
+	case http.MethodGet:
+		days := 180
+		if value := r.FormValue("days"); value != "" {
+			parsed, err := strconv.Atoi(value)
+			if err != nil || parsed <= 0 {
+				errorHandler(w, r, http.StatusBadRequest, "days must be a positive number")
+				return
+			}
+			days = parsed
+		}
+		maxViews, err := strconv.Atoi(r.FormValue("max_views"))
+		if err != nil {
+			maxViews = 0
+		}
+		response = t.getArchiveCandidates(time.Now().AddDate(0, 0, -days), maxViews)
+	case http.MethodPost:
+		gallery, err := t.getGallery("/gallery/" + r.FormValue("gallery"))
+		if err != nil {
+			errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+			return
+		}
+		storageClass := strings.ToUpper(r.FormValue("class"))
+		if !slices.Contains(storageClasses, storageClass) {
+			errorHandler(w, r, http.StatusBadRequest, "class must be one of "+strings.Join(storageClasses, ", "))
+			return
+		}
+		log.Println("Archiving Gallery: " + gallery.Category + "/" + gallery.Name + " to " + storageClass)
+		t.audit(getActor(r), "set_storage_class", map[string]string{
+			"gallery": gallery.Category + "/" + gallery.Name,
+			"class":   storageClass,
+		})
+		result, err := t.setStorageClass(r.Context(), gallery.Category+"/"+gallery.Name+"/", storageClass)
+		if err != nil {
+			panic(err)
+		}
+		response = result
+	default:
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use GET to list candidates or POST to change a gallery's storage class")
+		return
+	}
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(response)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+// getArchiveCandidates lists galleries with few views and nothing uploaded since the cutoff, largest first
+func (t *Tenant) getArchiveCandidates(cutoff time.Time, maxViews int) []ArchiveCandidate {
+	library := t.getLibrary()
+	candidates := []ArchiveCandidate{}
+	for _, gallery := range t.getGalleries() {
+		if len(gallery.Videos) == 0 || gallery.getViews() > maxViews {
+			continue
+		}
+		var lastUpload time.Time
+		for _, video := range gallery.Videos {
+			if video.Created.After(lastUpload) {
+				lastUpload = video.Created
+			}
+		}
+		if lastUpload.After(cutoff) {
+			continue
+		}
+		candidates = append(candidates, ArchiveCandidate{
+			Category:   gallery.Category,
+			Gallery:    gallery.Name,
+			Stub:       strings.TrimPrefix(gallery.Stub, "/gallery/"),
+			Views:      gallery.getViews(),
+			Bytes:      library.GalleryBytes[gallery.Category+"/"+gallery.Name],
+			LastUpload: lastUpload,
+		})
+	}
+	sort.SliceStable(candidates, func(i, j int) bool {
+		return candidates[i].Bytes > candidates[j].Bytes
+	})
+	return candidates
+}
+
+// setStorageClass rewrites every object under the prefix in place with the new storage class
+func (t *Tenant) setStorageClass(ctx context.Context, prefix string, storageClass string) (ArchiveResult, error) {
+	result := ArchiveResult{StorageClass: storageClass}
+	bucket := t.getBucket()
+	objects := bucket.Objects(ctx, &storage.Query{Prefix: prefix})
+	for {
+		object, err := objects.Next()
+		if errors.Is(err, iterator.Done) {
+			break
+		}
+		if err != nil {
+			return result, err
+		}
+		if object.StorageClass == storageClass {
+			result.Skipped++
+			continue
+		}
+		// Setting any attribute replaces them all, so the rest are carried over
+		copier := bucket.Object(object.Name).CopierFrom(bucket.Object(object.Name))
+		copier.StorageClass = storageClass
+		copier.ContentType = object.ContentType
+		copier.ContentEncoding = object.ContentEncoding
+		copier.ContentDisposition = object.ContentDisposition
+		copier.ContentLanguage = object.ContentLanguage
+		copier.CacheControl = object.CacheControl
+		copier.Metadata = object.Metadata
+		if _, err := copier.Run(ctx); err != nil {
+			return result, err
+		}
+		result.Objects++
+		result.Bytes += object.Size
+	}
+	return result, nil
+}
+
+// Replaced videos are kept under this prefix, followed by the video's path and the time it was replaced
+const versionsPrefix = ".versions/"
+
+const versionFormat = "20060102T150405Z"
+
+func (t *Tenant) getVersions(ctx context.Context, video Video) ([]VideoVersion, error) {
+	versions := []VideoVersion{}
+	objects := t.getBucket().Objects(ctx, &storage.Query{Prefix: versionsPrefix + video.VideoPath + "/"})
+	for {
+		object, err := objects.Next()
+		if errors.Is(err, iterator.Done) {
+			break
+		}
+		if err != nil {
+			return nil, err
+		}
+		version := path.Base(object.Name)
+		archived, err := time.Parse(versionFormat, version)
+		if err != nil {
+			continue
+		}
+		versions = append(versions, VideoVersion{Version: version, Bytes: object.Size, Archived: archived})
+	}
+	// Newest first
+	slices.Reverse(versions)
+	return versions, nil
+}
+
+// archiveVersion copies a generation of a video's file under versionsPrefix
+func (t *Tenant) archiveVersion(ctx context.Context, video Video, generation int64) (VideoVersion, error) {
+	bucket := t.getBucket()
+	archived := time.Now().UTC().Truncate(time.Second)
+	version := archived.Format(versionFormat)
+	copier := bucket.Object(versionsPrefix + video.VideoPath + "/" + version).CopierFrom(bucket.Object(video.VideoPath).Generation(generation))
+	attrs, err := copier.Run(ctx)
+	if err != nil {
+		return VideoVersion{}, err
+	}
+	return VideoVersion{Version: version, Bytes: attrs.Size, Archived: archived}, nil
+}
+
+func (t *Tenant) versionsHandler(w http.ResponseWriter, r *http.Request) {
+	video, err := t.getVideo(r.FormValue("video"))
+	if err != nil {
+		errorHandler(w, r, http.StatusNotFound, "Video not found")
+		return
+	}
+
+	var response any
+	switch r.Method {
+	case http.MethodGet:
+		versions, err := t.getVersions(r.Context(), video)
+		if err != nil {
+			panic(err)
+		}
+		response = versions
+	case http.MethodPost:
+		// Like uploads, replacing and restoring write to the bucket
+		if isStorageAnonymous() {
+			errorHandler(w, r, http.StatusBadRequest, "storage is read only")
+			return
+		}
+		restore := r.FormValue("restore")
+		// The generation that's archived is the only one the new file may replace
+		attrs, err := t.getBucket().Object(video.VideoPath).Attrs(r.Context())
+		if err != nil {
+			panic(err)
+		}
+		var session UploadSession
+		if restore == "" {
+			// Sign the upload first, so nothing is archived when it isn't allowed
+			var ok bool
+			session, ok = t.startUpload(w, r, video.VideoPath, attrs.Generation)
+			if !ok {
+				return
+			}
+		} else {
+			versions, err := t.getVersions(r.Context(), video)
+			if err != nil {
+				panic(err)
+			}
+			if !slices.ContainsFunc(versions, func(version VideoVersion) bool { return version.Version == restore }) {
+				errorHandler(w, r, http.StatusNotFound, "Version not found")
+				return
+			}
+		}
+
+		archived, err := t.archiveVersion(r.Context(), video, attrs.Generation)
+		if err != nil {
+			panic(err)
+		}
+		if restore == "" {
+			log.Println("Replacing Video: " + video.VideoPath)
+			session.Archived = archived.Version
+			response = session
+			t.audit(getActor(r), "replace", map[string]string{
+				"path":     video.VideoPath,
+				"archived": archived.Version,
+			})
+			break
+		}
+
+		log.Println("Restoring Video: " + video.VideoPath + " from " + restore)
+		bucket := t.getBucket()
+		target := bucket.Object(video.VideoPath).If(storage.Conditions{GenerationMatch: attrs.Generation})
+		copier := target.CopierFrom(bucket.Object(versionsPrefix + video.VideoPath + "/" + restore))
+		if _, err := copier.Run(r.Context()); err != nil {
+			var apiErr *googleapi.Error
+			if errors.As(err, &apiErr) && apiErr.Code == http.StatusPreconditionFailed {
+				errorHandler(w, r, http.StatusConflict, "The video changed while it was archived, try again")
+				return
+			}
+			panic(err)
+		}
+		t.audit(getActor(r), "restore", map[string]string{
+			"path":     video.VideoPath,
+			"version":  restore,
+			"archived": archived.Version,
+		})
+
+		// Pick up the restored file
+		t.videoCache.Delete("videos")
+		response = archived
+	default:
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to replace or restore a video")
+		return
+	}
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(response)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+// deleteFolder removes a gallery or a whole category once the request repeats the confirmation it was given
+func (t *Tenant) deleteFolder(w http.ResponseWriter, r *http.Request, category string, gallery string) {
+	prefix := category + "/"
+	if gallery != "" {
+		prefix += gallery + "/"
+	}
+
+	// List everything that would be deleted
+	var names []string
+	var totalBytes int64
+	objects := t.getBucket().Objects(r.Context(), &storage.Query{Prefix: prefix})
+	for {
+		object, err := objects.Next()
+		if errors.Is(err, iterator.Done) {
+			break
+		}
+		if err != nil {
+			panic(err)
+		}
+		names = append(names, object.Name)
+		totalBytes += object.Size
+	}
+	if len(names) == 0 {
+		errorHandler(w, r, http.StatusNotFound, "Nothing to delete")
+		return
+	}
+
+	// The confirmation changes when the contents do
+	confirm := getHash(fmt.Sprintf("delete:%s:%d:%d", prefix, len(names), totalBytes), t.SecretKey, stubLength)
+	if r.FormValue("confirm") != confirm {
+		jsonString, err := json.Marshal(DeletePreview{
+			Objects: len(names),
+			Bytes:   totalBytes,
+			Confirm: confirm,
+		})
+		if err != nil {
+			panic(err)
+		}
+		w.Header().Set("Content-Type", "application/json")
+		_, err = w.Write(jsonString)
+		if err != nil {
+			return
+		}
+		return
+	}
+	log.Println("Deleting: " + prefix)
+	t.audit(getActor(r), "delete", map[string]string{
+		"prefix":  prefix,
+		"objects": strconv.Itoa(len(names)),
+	})
+	t.deleteObjects(w, r, names)
+}
+
+// deleteObjects removes the objects, reporting progress as one JSON line per batch
+func (t *Tenant) deleteObjects(w http.ResponseWriter, r *http.Request, names []string) {
+	w.Header().Set("Content-Type", "application/x-ndjson")
+	flusher, _ := w.(http.Flusher)
+	encoder := json.NewEncoder(w)
+	progress := DeleteProgress{Total: len(names)}
+	bucket := t.getBucket()
+	for _, name := range names {
+		err := bucket.Object(name).Delete(r.Context())
+		if err != nil && !errors.Is(err, storage.ErrObjectNotExist) {
+			progress.Error = err.Error()
+			break
+		}
+		progress.Deleted++
+		if progress.Deleted%50 == 0 {
+			_ = encoder.Encode(progress)
+			if flusher != nil {
+				flusher.Flush()
+			}
+		}
+	}
+	t.videoCache.Delete("videos")
+	progress.Done = progress.Error == ""
+	_ = encoder.Encode(progress)
+}
+
+// findOrphans lists the chapters, subtitles and preview sidecars and archived versions whose video is gone
+func (t *Tenant) findOrphans(ctx context.Context) ([]Orphan, error) {
+	files, err := t.listObjects(ctx)
+	if err != nil {
+		return nil, err
+	}
+	videoExtensions := getExtensions("VIDEO_EXTENSIONS", []string{".mp4", ".m4v", ".webm", ".mov", ".avi", ".mkv", ".ts"})
+	extensionRegex, _ := regexp.Compile(`\.[a-zA-Z0-9]+$`)
+
+	// Sidecars are matched to videos the same way as when listing the library
+	videoKeys := make(map[string]bool)
+	videoPaths := make(map[string]bool)
+	isVideo := func(name string) bool {
+		lowerName := strings.ToLower(name)
+		return slices.ContainsFunc(videoExtensions, func(extension string) bool { return strings.HasSuffix(lowerName, extension) })
+	}
+	for _, file := range files {
+		if base := extensionRegex.ReplaceAllString(file.Name, ""); isVideo(file.Name) && !strings.HasSuffix(base, previewSuffix) {
+			videoKeys[base] = true
+			videoPaths[file.Name] = true
+		}
+	}
+	orphans := []Orphan{}
+	for _, file := range files {
+		if parts := strings.Split(file.Name, "/"); len(parts) != 3 || parts[2] == "" {
+			continue
+		}
+		base := extensionRegex.ReplaceAllString(file.Name, "")
+		if strings.HasSuffix(base, ".chapters") && !videoKeys[strings.TrimSuffix(base, ".chapters")] {
+			orphans = append(orphans, Orphan{Path: file.Name, Kind: "chapters", Bytes: file.Size})
+		} else if strings.EqualFold(path.Ext(file.Name), ".vtt") && !strings.HasSuffix(base, ".chapters") && !videoKeys[base] {
+			orphans = append(orphans, Orphan{Path: file.Name, Kind: "subtitles", Bytes: file.Size})
+		} else if isVideo(file.Name) && strings.HasSuffix(base, previewSuffix) && !videoKeys[strings.TrimSuffix(base, previewSuffix)] {
+			orphans = append(orphans, Orphan{Path: file.Name, Kind: "preview", Bytes: file.Size})
+		}
+	}
+
+	// Versions are kept under the path of the video they replaced
+	objects := t.getBucket().Objects(ctx, &storage.Query{Prefix: versionsPrefix})
+	for {
+		object, err := objects.Next()
+		if errors.Is(err, iterator.Done) {
+			break
+		}
+		if err != nil {
+			return nil, err
+		}
+		if !videoPaths[path.Dir(strings.TrimPrefix(object.Name, versionsPrefix))] {
+			orphans = append(orphans, Orphan{Path: object.Name, Kind: "version", Bytes: object.Size})
+		}
+	}
+	return orphans, nil
+}
+
+func (t *Tenant) orphansHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodGet && r.Method != http.MethodDelete {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use GET to list orphaned files or DELETE to remove them")
+		return
+	}
+	orphans, err := t.findOrphans(r.Context())
+	if err != nil {
+		panic(err)
+	}
+	kind := r.FormValue("kind")
+	if kind != "" {
+		orphans = slices.DeleteFunc(orphans, func(orphan Orphan) bool { return orphan.Kind != kind })
+	}
+
+	// The confirmation changes when the orphans do
+	var names []string
+	report := OrphanReport{Orphans: orphans}
+	for _, orphan := range orphans {
+		names = append(names, orphan.Path)
+		report.Bytes += orphan.Bytes
+	}
+	report.Confirm = getHash("orphans:"+strings.Join(names, "\n"), t.SecretKey, stubLength)
+
+	if r.Method == http.MethodDelete && r.FormValue("confirm") == report.Confirm && len(names) > 0 {
+		log.Println("Deleting Orphans: " + strconv.Itoa(len(names)))
+		t.audit(getActor(r), "delete_orphans", map[string]string{
+			"kind":    kind,
+			"objects": strconv.Itoa(len(names)),
+		})
+		t.deleteObjects(w, r, names)
+		return
+	}
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(report)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+func readRange(ctx context.Context, object *storage.ObjectHandle, offset int64, length int64) ([]byte, int64, error) {
+	reader, err := object.NewRangeReader(ctx, offset, length)
+	if err != nil {
+		return nil, 0, err
+	}
+	defer reader.Close()
+	data, err := io.ReadAll(reader)
+	return data, reader.Attrs.Size, err
+}
+
+// verifyVideo checks the container header, and for MP4 and MOV files that every top level box is complete
+func (t *Tenant) verifyVideo(ctx context.Context, name string) error {
+	object := t.getBucket().Object(name)
+	header, size, err := readRange(ctx, object, 0, 16)
+	if err != nil {
+		return err
+	}
+	if size == 0 || len(header) < 12 {
+		return fmt.Errorf("file is empty or too short")
+	}
+
+	switch {
+	case slices.Contains([]string{"ftyp", "moov", "mdat", "wide", "free", "skip"}, string(header[4:8])):
+		return verifyBoxes(size, func(offset int64) ([]byte, error) {
+			header, _, err := readRange(ctx, object, offset, 16)
+			return header, err
+		})
+	case bytes.HasPrefix(header, []byte{0x1A, 0x45, 0xDF, 0xA3}):
+		// Matroska and WebM
+		return nil
+	case string(header[0:4]) == "RIFF" && string(header[8:12]) == "AVI ":
+		if int64(binary.LittleEndian.Uint32(header[4:8]))+8 > size {
+			return fmt.Errorf("file is truncated")
+		}
+		return nil
+	case header[0] == 0x47:
+		// MPEG transport stream sync byte
+		return nil
+	default:
+		return fmt.Errorf("unknown container format")
+	}
+}
+
+// verifyBoxes walks the top level boxes of a file of size bytes, reading up to 16 bytes of each box header
+func verifyBoxes(size int64, readHeader func(offset int64) ([]byte, error)) error {
+	foundMovie := false
+	var offset int64
+	for boxes := 0; offset < size && boxes < 1000; boxes++ {
+		header, err := readHeader(offset)
+		if err != nil {
+			return err
+		}
+		if len(header) < 8 {
+			return fmt.Errorf("box header at %d is truncated", offset)
+		}
+		boxType := string(header[4:8])
+		boxSize := int64(binary.BigEndian.Uint32(header[0:4]))
+		switch boxSize {
+		case 0:
+			// Runs to the end of the file
+			boxSize = size - offset
+		case 1:
+			if len(header) < 16 {
+				return fmt.Errorf("%s box at %d is truncated", boxType, offset)
+			}
+			// Sizes past the int64 range turn negative and are caught below
+			boxSize = int64(binary.BigEndian.Uint64(header[8:16]))
+			if boxSize < 16 {
+				return fmt.Errorf("%s box at %d has an invalid size", boxType, offset)
+			}
+		}
+		if boxSize < 8 {
+			return fmt.Errorf("%s box at %d has an invalid size", boxType, offset)
+		}
+		// Compared with what's left, so a huge size can't overflow the offset
+		if boxSize > size-offset {
+			return fmt.Errorf("%s box at %d is truncated", boxType, offset)
+		}
+		if boxType == "moov" {
+			foundMovie = true
+		}
+		offset += boxSize
+	}
+	if !foundMovie {
+		return fmt.Errorf("moov box is missing")
+	}
+	return nil
+}
+
+func (t *Tenant) verifyHandler(w http.ResponseWriter, r *http.Request) {
+	log.Println("Verifying Videos")
+	var videos []Video
+	for _, video := range t.getLibrary().Videos {
+		if !video.IsImage() && video.VideoPath != "" {
+			videos = append(videos, video)
+		}
+	}
+
+	// Check the videos in parallel and report as server sent events
+	type result struct {
+		video Video
+		err   error
+	}
+	results := make(chan result, len(videos))
+	semaphore := make(chan struct{}, getConcurrency("LIST_CONCURRENCY", 4))
+	for _, video := range videos {
+		go func(video Video) {
+			semaphore <- struct{}{}
+			defer func() { <-semaphore }()
+			results <- result{video: video, err: t.verifyVideo(r.Context(), video.VideoPath)}
+		}(video)
+	}
+
+	w.Header().Set("Content-Type", "text/event-stream")
+	w.Header().Set("Cache-Control", "no-cache")
+	flusher, _ := w.(http.Flusher)
+	sendEvent := func(event string, data any) {
+		jsonString, err := json.Marshal(data)
+		if err != nil {
+			panic(err)
+		}
+		_, _ = fmt.Fprintf(w, "event: %s\ndata: %s\n\n", event, jsonString)
+		if flusher != nil {
+			flusher.Flush()
+		}
+	}
+
+	report := VerifyReport{Unreadable: make(map[string][]UnreadableVideo)}
+	for range videos {
+		result := <-results
+		report.Checked++
+		if result.err != nil {
+			log.Println("Unreadable Video: " + result.video.VideoPath + ": " + result.err.Error())
+			gallery := result.video.Category + "/" + result.video.Gallery
+			report.Unreadable[gallery] = append(report.Unreadable[gallery], UnreadableVideo{
+				Name:  result.video.Name,
+				Path:  result.video.VideoPath,
+				Error: result.err.Error(),
+			})
+		}
+		sendEvent("progress", VerifyProgress{Checked: report.Checked, Total: len(videos)})
+	}
+	sendEvent("done", report)
+}
+
+func isDebugTemplates() bool {
+	return os.Getenv("DEBUG_TEMPLATES") == "true"
+}
+
+// templateValue turns a template context into plain values keyed by field name, the way templates see them
+func templateValue(value reflect.Value) any {
+	switch value.Kind() {
+	case reflect.Pointer, reflect.Interface:
+		if value.IsNil() {
+			return nil
+		}
+		return templateValue(value.Elem())
+	case reflect.Struct:
+		if value.Type() == reflect.TypeOf(time.Time{}) {
+			return value.Interface()
+		}
+		fields := make(map[string]any)
+		for i := 0; i < value.NumField(); i++ {
+			if field := value.Type().Field(i); field.IsExported() {
+				fields[field.Name] = templateValue(value.Field(i))
+			}
+		}
+		return fields
+	case reflect.Slice:
+		if value.IsNil() {
+			return nil
+		}
+		items := make([]any, value.Len())
+		for i := range items {
+			items[i] = templateValue(value.Index(i))
+		}
+		return items
+	case reflect.Map:
+		if value.IsNil() {
+			return nil
+		}
+		entries := make(map[string]any)
+		iter := value.MapRange()
+		for iter.Next() {
+			entries[fmt.Sprint(iter.Key().Interface())] = templateValue(iter.Value())
+		}
+		return entries
+	}
+	return value.Interface()
+}
+
+// debugRenderHandler renders a template with the context a page would give it, to troubleshoot custom views
+func (t *Tenant) debugRenderHandler(w http.ResponseWriter, r *http.Request) {
+	if !isDebugTemplates() {
+		errorHandler(w, r, http.StatusNotFound, "Set DEBUG_TEMPLATES to preview templates")
+		return
+	}
+
+	name := r.URL.Query().Get("template")
+	var templateContext any
+	switch name {
+	case "index.pug":
+		templateContext = t.getIndex(r, t.getCategories(), false)
+	case "gallery.pug":
+		gallery, err := t.getGallery("/gallery/" + r.URL.Query().Get("stub"))
+		if err != nil {
+			errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+			return
+		}
+		templateContext = t.getGalleryPage(r, gallery)
+	case "error.pug":
+		templateContext = ErrorPage{Status: http.StatusNotFound, Title: http.StatusText(http.StatusNotFound), Message: "Gallery not found"}
+	default:
+		errorHandler(w, r, http.StatusBadRequest, "template must be index.pug, gallery.pug or error.pug")
+		return
+	}
+	log.Println("Rendering Template: " + name)
+
+	page := DebugPage{Template: name}
+	contextJson, err := json.MarshalIndent(templateValue(reflect.ValueOf(templateContext)), "", "  ")
+	if err != nil {
+		panic(err)
+	}
+	page.Context = string(contextJson)
+
+	// Errors are shown with the numbered source instead of failing the request
+	source, err := os.ReadFile(viewsDir + "/" + name)
+	if err != nil {
+		panic(err)
+	}
+	for i, line := range strings.Split(string(source), "\n") {
+		page.Source = append(page.Source, DebugLine{Number: i + 1, Text: line})
+	}
+	var output bytes.Buffer
+	if template, err := pug.CompileFile(viewsDir+"/"+name, pug.Options{}); err != nil {
+		page.Error = err.Error()
+	} else if err := template.Execute(&output, templateContext); err != nil {
+		page.Error = err.Error()
+	}
+	page.Output = output.String()
+
+	template, err := pug.CompileFile(viewsDir+"/debug.pug", pug.Options{})
+	if err != nil {
+		panic(err)
+	}
+
+	err = template.Execute(w, page)
+	if err != nil {
+		panic(err)
+	}
+}
+
+func (t *Tenant) configHandler(w http.ResponseWriter, _ *http.Request) {
+	// Convert to JSON
+	jsonString, err := json.Marshal(getConfigSettings())
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+func (t *Tenant) reloadHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to reload the config")
+		return
+	}
+	log.Println("Reloading Config")
+	reload, err := reloadConfig()
+	if err != nil {
+		errorHandler(w, r, http.StatusBadRequest, "Unable to read CONFIG_FILE: "+err.Error())
+		return
+	}
+	if len(reload.Changed) > 0 {
+		t.audit(getActor(r), "reload_config", map[string]string{
+			"changed": strings.Join(reload.Changed, ","),
+		})
+	}
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(reload)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	switch {
+	case len(reload.Problems) > 0:
+		w.WriteHeader(http.StatusBadRequest)
+	case len(reload.Rejected) > 0:
+		w.WriteHeader(http.StatusConflict)
+	}
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+// logReloads reloads the config whenever the process gets a SIGHUP
+func logReloads(reloads <-chan os.Signal) {
+	for range reloads {
+		log.Println("Reloading Config")
+		reload, err := reloadConfig()
+		switch {
+		case err != nil:
+			log.Println("Unable to read CONFIG_FILE: " + err.Error())
+		case len(reload.Problems) > 0:
+			log.Println("Config not reloaded: " + strings.Join(reload.Problems, ", "))
+		case len(reload.Rejected) > 0:
+			log.Println("Config not reloaded, restart to change " + strings.Join(reload.Rejected, ", "))
+		default:
+			log.Println("Reloaded Config: " + strings.Join(reload.Changed, ", "))
+		}
+	}
+}
+
+func (t *Tenant) auditHandler(w http.ResponseWriter, r *http.Request) {
+	log.Println("Generating Audit Log")
+
+	page, err := t.getAuditPage(r.Context(), r.URL.Query().Get("after"), 50)
+	if err != nil {
+		panic(err)
+	}
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(page)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+func (t *Tenant) pageHandler(w http.ResponseWriter, r *http.Request) {
+	// Get path
+	path := r.URL.Path
+	if strings.HasSuffix(path, "/download.zip") {
+		t.downloadHandler(w, r)
+		return
+	}
+
+	gallery, err := t.getGallery(path)
+	if err == nil && isCategoryAdminOnly(gallery.Category) {
+		log.Println("Gallery is admin only: " + path)
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+	if err != nil {
+		// Keep links shared before the stubs were lengthened working
+		if legacyGallery, err := t.getLegacyGallery(path); err == nil {
+			log.Println("Redirecting Legacy Gallery: " + path)
+			target := legacyGallery.Stub
+			if r.URL.RawQuery != "" {
+				target += "?" + r.URL.RawQuery
+			}
+			http.Redirect(w, r, target, http.StatusMovedPermanently)
+			return
+		}
+		log.Println("Gallery not found: " + path)
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+	galleryKey := gallery.Category + "/" + gallery.Name
+	if _, protected := t.getMetadata().Passwords[galleryKey]; protected && !t.passwordPrompt(w, r, galleryKey, gallery.Name) {
+		return
+	}
+	if !t.gatePrompt(w, r, gallery.Category, gallery.Name, gallery.Name) {
+		return
+	}
+	log.Println("Generating Gallery Page: " + path)
+	template, err := pug.CompileFile(viewsDir+"/gallery.pug", pug.Options{})
+	if err != nil {
+		panic(err)
+	}
+
+	err = template.Execute(w, t.getGalleryPage(r, gallery))
+	if err != nil {
+		panic(err)
+	}
+}
+
+// getGalleryPage prepares a gallery for its page, with the first page of videos the visitor may see
+func (t *Tenant) getGalleryPage(r *http.Request, gallery Gallery) Gallery {
+	gallery.Videos = withoutHidden(gallery.Videos)
+	sortVideos(gallery.Videos, r.URL.Query().Get("sort"))
+	t.setPreview(r, &gallery)
+
+	// Large galleries load the rest while scrolling
+	if len(gallery.Videos) > galleryPageSize {
+		gallery.NextCursor = encodeCursor(gallery.Videos[galleryPageSize-1])
+		gallery.Videos = gallery.Videos[:galleryPageSize]
+	}
+	t.selectRenditions(r, gallery.Videos)
+	t.lockVideos(r, gallery.Videos)
+	query := r.URL.Query()
+	if isDownloadable(gallery) {
+		download := url.Values{}
+		for _, name := range []string{"exp", "sig"} {
+			if value := query.Get(name); value != "" {
+				download.Set(name, value)
+			}
+		}
+		gallery.DownloadLink = gallery.Stub + "/download.zip?" + download.Encode()
+	}
+	query.Set("sort", "popular")
+	gallery.PopularLink = "?" + query.Encode()
+	return gallery
+}
+
+// setPreview fills in the link preview of a gallery page, keeping any signature on its links
+func (t *Tenant) setPreview(r *http.Request, gallery *Gallery) {
+	signature := url.Values{}
+	for _, name := range []string{"exp", "sig"} {
+		if value := r.URL.Query().Get(name); value != "" {
+			signature.Set(name, value)
+		}
+	}
+	baseUrl := getBaseUrl(r)
+	gallery.PreviewUrl = baseUrl + gallery.Stub
+	gallery.PreviewImage = baseUrl + "/og/" + strings.TrimPrefix(gallery.Stub, "/gallery/") + ".jpg"
+	if len(signature) > 0 {
+		gallery.PreviewUrl += "?" + signature.Encode()
+		gallery.PreviewImage += "?" + signature.Encode()
+	}
+
+	var videos, photos int
+	for _, video := range gallery.Videos {
+		if video.IsImage() {
+			photos++
+		} else {
+			videos++
+		}
+	}
+	var counts []string
+	if videos > 0 {
+		counts = append(counts, fmt.Sprintf("%d videos", videos))
+	}
+	if photos > 0 {
+		counts = append(counts, fmt.Sprintf("%d photos", photos))
+	}
+	gallery.PreviewDescription = strings.Join(append(counts, gallery.Category), " · ")
+}
+
+// previewImageHandler serves the image shown in link previews, the gallery cover or else its first thumbnail
+func (t *Tenant) previewImageHandler(w http.ResponseWriter, r *http.Request) {
+	stub := strings.TrimSuffix(strings.TrimPrefix(r.URL.Path, "/og/"), ".jpg")
+	gallery, err := t.getGallery("/gallery/" + stub)
+	if err != nil || isCategoryAdminOnly(gallery.Category) {
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+
+	// Previews show nothing the gallery page wouldn't
+	if err := t.verifyGallerySignature(gallery, r.URL.Query()); err != nil {
+		errorHandler(w, r, http.StatusForbidden, err.Error())
+		return
+	}
+	metadata := t.getMetadata()
+	galleryKey := gallery.Category + "/" + gallery.Name
+	if _, protected := metadata.Passwords[galleryKey]; protected && !t.hasAccess(r, galleryKey) {
+		errorHandler(w, r, http.StatusForbidden, "This gallery needs a password")
+		return
+	}
+	if !t.passesGate(r, metadata, gallery.Category, gallery.Name) {
+		errorHandler(w, r, http.StatusForbidden, "This gallery needs to be unlocked")
+		return
+	}
+	if gallery.Cover != nil {
+		http.Redirect(w, r, *gallery.Cover, http.StatusFound)
+		return
+	}
+
+	videos := withoutHidden(gallery.Videos)
+	sortVideos(videos, "")
+	for _, video := range videos {
+		if video.ThumbnailPath == "" || !t.canWatch(r, video) {
+			continue
+		}
+		thumbnail, err := t.getResizedThumbnail(r.Context(), video, thumbnailWidths[len(thumbnailWidths)-1])
+		if err != nil {
+			log.Println("Failed to resize thumbnail: " + err.Error())
+			http.Redirect(w, r, *video.Thumbnail, http.StatusFound)
+			return
+		}
+		w.Header().Set("Content-Type", "image/jpeg")
+		w.Header().Set("Cache-Control", "public, max-age=86400")
+		_, err = w.Write(thumbnail)
+		if err != nil {
+			return
+		}
+		return
+	}
+	errorHandler(w, r, http.StatusNotFound, "Gallery has no thumbnails")
+}
+
+const accessCookieTTL = 12 * time.Hour
+
+const (
+	maxPasswordAttempts = 5
+	passwordLockout     = 15 * time.Minute
+)
+
+// failedPasswords counts the wrong passwords each visitor entered for each key
+var failedPasswords = cache.New(passwordLockout, time.Minute)
+
+// getAttemptSource is who wrong passwords are counted for, the client IP or its /64 network for IPv6,
+// since a single IPv6 client can pick any address of its network for each guess
+func getAttemptSource(r *http.Request) string {
+	ip := net.ParseIP(getClientIp(r))
+	if ip == nil || ip.To4() != nil {
+		return getClientIp(r)
+	}
+	return (&net.IPNet{IP: ip.Mask(net.CIDRMask(64, 128)), Mask: net.CIDRMask(64, 128)}).String()
+}
+
+func hashPassword(password string) (string, error) {
+	hash, err := bcrypt.GenerateFromPassword([]byte(password), bcrypt.DefaultCost)
+	return string(hash), err
+}
+
+// isLegacyPassword is true for passwords saved as a salted SHA-256 before bcrypt was used
+func isLegacyPassword(stored string) bool {
+	return stored != "" && !strings.HasPrefix(stored, "$2")
+}
+
+func checkPassword(stored string, password string) bool {
+	if !isLegacyPassword(stored) {
+		return stored != "" && bcrypt.CompareHashAndPassword([]byte(stored), []byte(password)) == nil
+	}
+	saltHex, hashHex, found := strings.Cut(stored, "$")
+	salt, err := hex.DecodeString(saltHex)
+	if !found || err != nil {
+		return false
+	}
+	hash := sha256.Sum256(append(salt, password...))
+	return hmac.Equal([]byte(hex.EncodeToString(hash[:])), []byte(hashHex))
+}
+
+// getProtection is the key of the password protecting a video, or empty when there is none
+func getProtection(metadata Metadata, video Video) string {
+	if _, ok := metadata.Passwords[video.Key()]; ok {
+		return video.Key()
+	}
+	if _, ok := metadata.Passwords[video.Category+"/"+video.Gallery]; ok {
+		return video.Category + "/" + video.Gallery
+	}
+	return ""
+}
+
+func (t *Tenant) getAccessSignature(key string, expires int64) string {
+	mac := hmac.New(sha256.New, []byte(t.SecretKey))
+	mac.Write([]byte("access\n" + key + "\n" + strconv.FormatInt(expires, 10)))
+	return base64.RawURLEncoding.EncodeToString(mac.Sum(nil))
+}
+
+func (t *Tenant) getAccessCookieName(key string) string {
+	return "access_" + getHash(key, t.SecretKey, 8)
+}
+
+// hasAccess checks for the signed cookie given out when the password was entered
+func (t *Tenant) hasAccess(r *http.Request, key string) bool {
+	cookie, err := r.Cookie(t.getAccessCookieName(key))
+	if err != nil {
+		return false
+	}
+	expiresText, signature, found := strings.Cut(cookie.Value, ".")
+	expires, err := strconv.ParseInt(expiresText, 10, 64)
+	if !found || err != nil || time.Now().Unix() > expires {
+		return false
+	}
+	return hmac.Equal([]byte(signature), []byte(t.getAccessSignature(key, expires)))
+}
+
+func (t *Tenant) canWatch(r *http.Request, video Video) bool {
+	metadata := t.getMetadata()
+	if key := getProtection(metadata, video); key != "" && !t.hasAccess(r, key) {
+		return false
+	}
+	return t.passesGate(r, metadata, video.Category, video.Gallery)
+}
+
+// lockVideos hides the urls of protected videos the visitor hasn't unlocked
+func (t *Tenant) lockVideos(r *http.Request, videos []Video) {
+	for i, video := range videos {
+		if t.canWatch(r, video) {
+			continue
+		}
+		videos[i].Locked = true
+		videos[i].Url = "/video/" + video.Id + guestLinkQuery(r)
+		videos[i].Thumbnail = nil
+		videos[i].ThumbnailSmall = ""
+		videos[i].Chapters = nil
+		videos[i].Subtitles = nil
+		videos[i].Preview = nil
+	}
+}
+
+// withoutProtected leaves out protected videos, and galleries left empty by that
+func (t *Tenant) withoutProtected(galleries []Gallery) []Gallery {
+	metadata := t.getMetadata()
+	filtered := []Gallery{}
+	for _, gallery := range galleries {
+		var videos []Video
+		for _, video := range gallery.Videos {
+			if getProtection(metadata, video) == "" {
+				videos = append(videos, video)
+			}
+		}
+		if len(videos) > 0 {
+			gallery.Videos = videos
+			filtered = append(filtered, gallery)
+		}
+	}
+	return filtered
+}
+
+// withoutGated leaves out rated galleries the visitor hasn't unlocked
+func (t *Tenant) withoutGated(r *http.Request, galleries []Gallery) []Gallery {
+	metadata := t.getMetadata()
+	return slices.DeleteFunc(slices.Clone(galleries), func(gallery Gallery) bool {
+		return !t.passesGate(r, metadata, gallery.Category, gallery.Name)
+	})
+}
+
+// grantAccess gives out the signed cookie for key and reloads the page
+func (t *Tenant) grantAccess(w http.ResponseWriter, r *http.Request, key string) {
+	expires := time.Now().Add(accessCookieTTL)
+	http.SetCookie(w, &http.Cookie{
+		Name:     t.getAccessCookieName(key),
+		Value:    strconv.FormatInt(expires.Unix(), 10) + "." + t.getAccessSignature(key, expires.Unix()),
+		Path:     "/",
+		Expires:  expires,
+		HttpOnly: true,
+		Secure:   r.TLS != nil || r.Header.Get("X-Forwarded-Proto") == "https",
+		SameSite: http.SameSiteLaxMode,
+	})
+	http.Redirect(w, r, r.URL.String(), http.StatusSeeOther)
+}
+
+// getRatingGates maps content ratings to the PIN that opens them, empty for a confirmation
+func getRatingGates() map[string]string {
+	gates := map[string]string{}
+	if gatesJson := os.Getenv("RATING_GATES"); gatesJson != "" {
+		if err := json.Unmarshal([]byte(gatesJson), &gates); err != nil {
+			panic("RATING_GATES is not valid JSON: " + err.Error())
+		}
+	}
+	return gates
+}
+
+// getRating finds the rating of a gallery, falling back to its category's
+func getRating(metadata Metadata, category string, gallery string) string {
+	if rating, ok := metadata.Ratings[category+"/"+gallery]; ok {
+		return rating
+	}
+	return metadata.Ratings[category]
+}
+
+// passesGate is true when the gallery isn't rated behind a gate, or the visitor got through it
+func (t *Tenant) passesGate(r *http.Request, metadata Metadata, category string, gallery string) bool {
+	rating := getRating(metadata, category, gallery)
+	if _, gated := getRatingGates()[rating]; !gated {
+		return true
+	}
+	return t.hasAccess(r, "rating:"+rating)
+}
+
+// gatePrompt asks for the PIN, or a confirmation, of a rated gallery, and is true once the visitor is through
+func (t *Tenant) gatePrompt(w http.ResponseWriter, r *http.Request, category string, gallery string, title string) bool {
+	metadata := t.getMetadata()
+	if t.passesGate(r, metadata, category, gallery) {
+		return true
+	}
+	rating := getRating(metadata, category, gallery)
+	pin := getRatingGates()[rating]
+
+	message := "This is rated " + rating + ". Confirm to continue"
+	if pin != "" {
+		message = "This is rated " + rating + ". Enter the PIN to continue"
+	}
+	if r.Method == http.MethodPost {
+		if pin == "" && r.FormValue("confirm") == "true" || pin != "" && hmac.Equal([]byte(r.FormValue("pin")), []byte(pin)) {
+			t.grantAccess(w, r, "rating:"+rating)
+			return false
+		}
+
+		// Slow down guessing
+		log.Println("Wrong PIN for rating: " + rating)
+		time.Sleep(time.Second)
+		message = "That PIN is wrong, try again"
+	}
+
+	template, err := pug.CompileFile(viewsDir+"/gate.pug", pug.Options{})
+	if err != nil {
+		panic(err)
+	}
+	w.Header().Set("Content-Type", "text/html; charset=utf-8")
+	w.WriteHeader(http.StatusUnauthorized)
+	err = template.Execute(w, GatePage{Title: title, Message: message, Rating: rating, NeedsPin: pin != ""})
+	if err != nil {
+		log.Println(err)
+	}
+	return false
+}
+
+func (t *Tenant) ratingsHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method == http.MethodGet {
+		ratings := t.getMetadata().Ratings
+		if ratings == nil {
+			ratings = map[string]string{}
+		}
+
+		// Convert to JSON
+		jsonString, err := json.Marshal(ratings)
+		if err != nil {
+			panic(err)
+		}
+		// Write JSON
+		w.Header().Set("Content-Type", "application/json")
+		_, err = w.Write(jsonString)
+		if err != nil {
+			return
+		}
+		return
+	}
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to set a rating")
+		return
+	}
+
+	// Rate the whole category unless a gallery is given
+	category := r.FormValue("category")
+	key := category
+	if gallery := r.FormValue("gallery"); gallery != "" {
+		key += "/" + gallery
+	}
+	if !slices.ContainsFunc(t.getGalleries(), func(g Gallery) bool { return key == g.Category || key == g.Category+"/"+g.Name }) {
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+	// An empty rating removes it
+	rating := r.FormValue("rating")
+	if _, known := getRatingGates()[rating]; rating != "" && !known {
+		errorHandler(w, r, http.StatusBadRequest, "rating must be one of the ratings in RATING_GATES")
+		return
+	}
+	log.Println("Rating: " + key + " " + rating)
+
+	err := t.updateMetadata(r.Context(), func(metadata *Metadata) {
+		if metadata.Ratings == nil {
+			metadata.Ratings = make(map[string]string)
+		}
+		if rating == "" {
+			delete(metadata.Ratings, key)
+		} else {
+			metadata.Ratings[key] = rating
+		}
+	})
+	if err != nil {
+		panic(err)
+	}
+	t.audit(getActor(r), "rating", map[string]string{
+		"key":    key,
+		"rating": rating,
+	})
+	w.WriteHeader(http.StatusNoContent)
+}
+
+// passwordPrompt asks for the password of key, and is true once the visitor has access
+func (t *Tenant) passwordPrompt(w http.ResponseWriter, r *http.Request, key string, title string) bool {
+	if t.hasAccess(r, key) {
+		return true
+	}
+
+	message := "Enter the password to continue"
+	status := http.StatusUnauthorized
+	if r.Method == http.MethodPost {
+		source := getAttemptSource(r)
+		attemptKey := source + " " + key
+		stored := t.getMetadata().Passwords[key]
+		password := r.FormValue("password")
+		if failures, found := failedPasswords.Get(attemptKey); found && failures.(int) >= maxPasswordAttempts {
+			log.Println("Too many wrong passwords for: " + key + " from " + source)
+			message = "Too many wrong passwords, try again later"
+			status = http.StatusTooManyRequests
+			w.Header().Set("Retry-After", strconv.Itoa(int(passwordLockout.Seconds())))
+		} else if checkPassword(stored, password) {
+			failedPasswords.Delete(attemptKey)
+			// Hash older passwords again with bcrypt while the password is at hand
+			if isLegacyPassword(stored) {
+				hash, err := hashPassword(password)
+				if err == nil {
+					err = t.updateMetadata(r.Context(), func(metadata *Metadata) {
+						if metadata.Passwords[key] == stored {
+							metadata.Passwords[key] = hash
+						}
+					})
+				}
+				if err != nil {
+					log.Println("Failed to rehash password: " + err.Error())
+				}
+			}
+			t.grantAccess(w, r, key)
+			return false
+		} else {
+			// Slow down guessing
+			log.Println("Wrong password for: " + key + " from " + source)
+			if failedPasswords.Add(attemptKey, 1, cache.DefaultExpiration) != nil {
+				_ = failedPasswords.Increment(attemptKey, 1)
+			}
+			time.Sleep(time.Second)
+			message = "That password is wrong, try again"
+		}
+	}
+
+	template, err := pug.CompileFile(viewsDir+"/password.pug", pug.Options{})
+	if err != nil {
+		panic(err)
+	}
+	w.Header().Set("Content-Type", "text/html; charset=utf-8")
+	w.WriteHeader(status)
+	err = template.Execute(w, PasswordPage{Title: title, Message: message})
+	if err != nil {
+		log.Println(err)
+	}
+	return false
+}
+
+func (t *Tenant) passwordHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to set a password")
+		return
+	}
+
+	gallery, err := t.getGallery("/gallery/" + r.FormValue("gallery"))
+	if err != nil {
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+
+	// Protect the whole gallery unless a video is given
+	key := gallery.Category + "/" + gallery.Name
+	if videoName := r.FormValue("video"); videoName != "" {
+		key += "/" + videoName
+		if !slices.ContainsFunc(gallery.Videos, func(video Video) bool { return video.Name == videoName }) {
+			errorHandler(w, r, http.StatusNotFound, "Video not found")
+			return
+		}
+	}
+
+	password := r.FormValue("password")
+	var hash string
+	if password != "" {
+		hash, err = hashPassword(password)
+		if err != nil {
+			errorHandler(w, r, http.StatusBadRequest, "password must be at most 72 bytes")
+			return
+		}
+	}
+	err = t.updateMetadata(r.Context(), func(metadata *Metadata) {
+		if metadata.Passwords == nil {
+			metadata.Passwords = make(map[string]string)
+		}
+		if password == "" {
+			delete(metadata.Passwords, key)
+		} else {
+			metadata.Passwords[key] = hash
+		}
+	})
+	if err != nil {
+		panic(err)
+	}
+
+	action := "set_password"
+	if password == "" {
+		action = "remove_password"
+	}
+	log.Println("Changing Password: " + key)
+	t.audit(getActor(r), action, map[string]string{
+		"key": key,
+	})
+	w.WriteHeader(http.StatusNoContent)
+}
+
+func (t *Tenant) viewsHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to count a view")
+		return
+	}
+
+	video, err := t.getVideo(strings.TrimPrefix(r.URL.Path, "/api/views/"))
+	if err != nil {
+		errorHandler(w, r, http.StatusNotFound, "Video not found")
+		return
+	}
+
+	// Counts are saved in batches
+	t.viewsMutex.Lock()
+	t.pendingViews[video.Key()]++
+	t.viewsMutex.Unlock()
+	w.WriteHeader(http.StatusNoContent)
+}
+
+// getBrowser names the browser family of a user agent, good enough to tell playback problems apart
+func getBrowser(userAgent string) string {
+	switch {
+	case strings.Contains(userAgent, "Edg/"):
+		return "edge"
+	case strings.Contains(userAgent, "Firefox/") || strings.Contains(userAgent, "FxiOS/"):
+		return "firefox"
+	case strings.Contains(userAgent, "Chrome/") || strings.Contains(userAgent, "CriOS/"):
+		return "chrome"
+	case strings.Contains(userAgent, "Safari/"):
+		return "safari"
+	}
+	return "other"
+}
+
+func (t *Tenant) analyticsHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to send a player event")
+		return
+	}
+
+	video, err := t.getVideo(r.FormValue("video"))
+	if err != nil {
+		errorHandler(w, r, http.StatusNotFound, "Video not found")
+		return
+	}
+	var counts VideoAnalytics
+	switch r.FormValue("event") {
+	case "play":
+		counts.Plays = 1
+	case "pause":
+		counts.Pauses = 1
+	case "complete":
+		counts.Completes = 1
+	case "error":
+		counts.Errors = map[string]int{getBrowser(r.UserAgent()): 1}
+	default:
+		errorHandler(w, r, http.StatusBadRequest, "Event must be play, pause, complete or error")
+		return
+	}
+
+	// Saved in batches with the view counts
+	t.viewsMutex.Lock()
+	t.pendingAnalytics[video.Key()] = t.pendingAnalytics[video.Key()].add(counts)
+	t.viewsMutex.Unlock()
+	w.WriteHeader(http.StatusNoContent)
+}
+
+func (t *Tenant) analyticsReportHandler(w http.ResponseWriter, _ *http.Request) {
+	log.Println("Generating Analytics Report")
+	analytics := t.getMetadata().Analytics
+
+	// Videos that fail to play most often come first
+	rows := []AnalyticsRow{}
+	for _, video := range t.getLibrary().Videos {
+		counts, ok := analytics[video.Key()]
+		if !ok {
+			continue
+		}
+		row := AnalyticsRow{
+			Category:  video.Category,
+			Gallery:   video.Gallery,
+			Video:     video.Name,
+			Plays:     counts.Plays,
+			Pauses:    counts.Pauses,
+			Completes: counts.Completes,
+			Browsers:  map[string]int{},
+		}
+		for browser, count := range counts.Errors {
+			row.Errors += count
+			row.Browsers[browser] = count
+		}
+		rows = append(rows, row)
+	}
+	sort.Slice(rows, func(i, j int) bool {
+		if rows[i].Errors != rows[j].Errors {
+			return rows[i].Errors > rows[j].Errors
+		}
+		return rows[i].Plays > rows[j].Plays
+	})
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(rows)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+const gridThumbnailWidth = 480
+
+var thumbnailWidths = []int{160, 320, 480, 640, 960, 1280}
+
+var thumbnailCache = cache.New(24*time.Hour, time.Hour)
+
+// Limits how many thumbnails are decoded and resized at once, created once the configuration is loaded
+var thumbnailSemaphore chan struct{}
+
+func validateQuality(name string, value string) error {
+	if number, err := strconv.Atoi(value); err != nil || number < 1 || number > 100 {
+		return fmt.Errorf("%s must be a number from 1 to 100", name)
+	}
+	return nil
+}
+
+func getThumbnailQuality() int {
+	quality := os.Getenv("THUMBNAIL_QUALITY")
+	if quality == "" {
+		return 80
+	}
+	value, err := strconv.Atoi(quality)
+	if err != nil || value < 1 || value > 100 {
+		panic("THUMBNAIL_QUALITY must be a number from 1 to 100")
+	}
+	return value
+}
+
+// limitThumbnailWidths drops the widths above the maximum, serving the maximum itself instead
+func limitThumbnailWidths(maxWidth int) {
+	thumbnailWidths = slices.DeleteFunc(thumbnailWidths, func(width int) bool {
+		return width > maxWidth
+	})
+	if len(thumbnailWidths) == 0 || thumbnailWidths[len(thumbnailWidths)-1] < maxWidth {
+		thumbnailWidths = append(thumbnailWidths, maxWidth)
+	}
+}
+
+// resizeImage scales the image down to the width by averaging pixels
+func resizeImage(source image.Image, width int) image.Image {
+	bounds := source.Bounds()
+	if width >= bounds.Dx() {
+		return source
+	}
+	height := max(1, bounds.Dy()*width/bounds.Dx())
+
+	resized := image.NewRGBA(image.Rect(0, 0, width, height))
+	for y := 0; y < height; y++ {
+		y0 := bounds.Min.Y + y*bounds.Dy()/height
+		y1 := max(y0+1, bounds.Min.Y+(y+1)*bounds.Dy()/height)
+
+		// Convert the rows of this output row at once, so the pixels are read straight from memory
+		rows := image.Rect(bounds.Min.X, y0, bounds.Max.X, y1)
+		band := image.NewRGBA(rows)
+		draw.Draw(band, rows, source, rows.Min, draw.Src)
+		for x := 0; x < width; x++ {
+			x0 := bounds.Min.X + x*bounds.Dx()/width
+			x1 := max(x0+1, bounds.Min.X+(x+1)*bounds.Dx()/width)
+
+			var sum [4]int
+			for sy := y0; sy < y1; sy++ {
+				for i, value := range band.Pix[band.PixOffset(x0, sy):band.PixOffset(x1, sy)] {
+					sum[i%4] += int(value)
+				}
+			}
+			count := (x1 - x0) * (y1 - y0)
+			offset := resized.PixOffset(x, y)
+			for i, total := range sum {
+				resized.Pix[offset+i] = uint8(total / count)
+			}
+		}
+	}
+	return resized
+}
+
+// Decoding larger images could run the server out of memory, however small the file
+const maxThumbnailPixels = 40_000_000
+
+func (t *Tenant) getResizedThumbnail(ctx context.Context, video Video, width int) ([]byte, error) {
+	cacheKey := fmt.Sprintf("%s/%s/%d/%d", t.BucketName, video.ThumbnailPath, video.thumbnailVersion, width)
+	if cached, found := thumbnailCache.Get(cacheKey); found {
+		return cached.([]byte), nil
+	}
+	thumbnailSemaphore <- struct{}{}
+	defer func() { <-thumbnailSemaphore }()
+	log.Println("Resizing Thumbnail: " + video.ThumbnailPath)
+
+	// Download it whole first, so its size can be checked before decoding
+	reader, err := t.getBucket().Object(video.ThumbnailPath).NewReader(ctx)
+	if err != nil {
+		return nil, err
+	}
+	content, err := io.ReadAll(io.LimitReader(reader, 50*1024*1024))
+	_ = reader.Close()
+	if err != nil {
+		return nil, err
+	}
+	config, _, err := image.DecodeConfig(bytes.NewReader(content))
+	if err != nil {
+		return nil, err
+	}
+	if config.Width*config.Height > maxThumbnailPixels {
+		return nil, fmt.Errorf("%s is %dx%d, too large to resize", video.ThumbnailPath, config.Width, config.Height)
+	}
+	source, _, err := image.Decode(bytes.NewReader(content))
+	if err != nil {
+		return nil, err
+	}
+
+	var buffer bytes.Buffer
+	if err := jpeg.Encode(&buffer, resizeImage(source, width), &jpeg.Options{Quality: getThumbnailQuality()}); err != nil {
+		return nil, err
+	}
+	thumbnailCache.Set(cacheKey, buffer.Bytes(), cache.DefaultExpiration)
+	return buffer.Bytes(), nil
+}
+
+// getThumbnailHash names a thumbnail by its content, so its url only changes when the image does
+func getThumbnailHash(file *storage.ObjectAttrs) string {
+	return fmt.Sprintf("%08x", file.CRC32C)
+}
+
+func isHashedThumbnails() bool {
+	return os.Getenv("HASHED_THUMBNAILS") == "true"
+}
+
+// thumbnailUrl is the resizing proxy's url for the video's thumbnail, which changes whenever the thumbnail does
+func (video Video) thumbnailUrl(width int) string {
+	if isHashedThumbnails() {
+		return fmt.Sprintf("/thumb/%s.%s.jpg?w=%d", video.Id, video.thumbnailHash, width)
+	}
+	return fmt.Sprintf("/thumb/%s?w=%d&v=%d", video.Id, width, video.thumbnailVersion)
+}
+
+func (t *Tenant) thumbnailHandler(w http.ResponseWriter, r *http.Request) {
+	id, hash, hashed := strings.Cut(strings.TrimSuffix(strings.TrimPrefix(r.URL.Path, "/thumb/"), ".jpg"), ".")
+	video, err := t.getVideo(id)
+	if err != nil || video.ThumbnailPath == "" || !t.canWatch(r, video) {
+		errorHandler(w, r, http.StatusNotFound, "Thumbnail not found")
+		return
+	}
+
+	// Snap to a known width so only a few variants get cached
+	requested, err := strconv.Atoi(r.URL.Query().Get("w"))
+	if err != nil {
+		requested = gridThumbnailWidth
+	}
+	width := thumbnailWidths[len(thumbnailWidths)-1]
+	for _, thumbnailWidth := range thumbnailWidths {
+		if thumbnailWidth >= requested {
+			width = thumbnailWidth
+			break
+		}
+	}
+
+	// Pages cached before the thumbnail changed get sent to the current one
+	if hashed && hash != video.thumbnailHash {
+		http.Redirect(w, r, video.thumbnailUrl(width), http.StatusFound)
+		return
+	}
+
+	thumbnail, err := t.getResizedThumbnail(r.Context(), video, width)
+	if err != nil {
+		// Formats the standard library can't decode are served as-is
+		log.Println("Failed to resize thumbnail: " + err.Error())
+		http.Redirect(w, r, *video.Thumbnail, http.StatusFound)
+		return
+	}
+	w.Header().Set("Content-Type", "image/jpeg")
+	if hashed || r.URL.Query().Get("v") == strconv.FormatInt(video.thumbnailVersion, 10) {
+		w.Header().Set("Cache-Control", "public, max-age=31536000, immutable")
+	} else {
+		// Without the version in the url, a new thumbnail has to show up eventually
+		w.Header().Set("Cache-Control", "public, max-age=300")
+	}
+	_, err = w.Write(thumbnail)
+	if err != nil {
+		return
+	}
+}
+
+const contactSheetName = "_contact_sheet"
+
+const (
+	contactSheetTileWidth  = 320
+	contactSheetTileHeight = 180
+	contactSheetGap        = 4
+	contactSheetMaxTiles   = 120
+)
+
+type ContactSheet struct {
+	Url    string   `json:"url"`
+	Videos []string `json:"videos"`
+}
+
+// drawContactSheet lays the thumbnails out in a grid of 16:9 tiles, cropping or letterboxing each one
+func drawContactSheet(tiles []image.Image, columns int) image.Image {
+	rows := (len(tiles) + columns - 1) / columns
+	sheet := image.NewRGBA(image.Rect(0, 0,
+		columns*(contactSheetTileWidth+contactSheetGap)+contactSheetGap,
+		rows*(contactSheetTileHeight+contactSheetGap)+contactSheetGap))
+	draw.Draw(sheet, sheet.Bounds(), image.NewUniform(color.RGBA{R: 0x22, G: 0x22, B: 0x22, A: 0xff}), image.Point{}, draw.Src)
+
+	for i, tile := range tiles {
+		if tile == nil {
+			continue
+		}
+		x := contactSheetGap + (i%columns)*(contactSheetTileWidth+contactSheetGap)
+		y := contactSheetGap + (i/columns)*(contactSheetTileHeight+contactSheetGap)
+		offset := (contactSheetTileHeight - tile.Bounds().Dy()) / 2
+		target := image.Rect(x, y+max(0, offset), x+contactSheetTileWidth, y+contactSheetTileHeight)
+		draw.Draw(sheet, target, tile, tile.Bounds().Min.Add(image.Pt(0, max(0, -offset))), draw.Src)
+	}
+	return sheet
+}
+
+// createContactSheet draws the gallery's thumbnails into one image stored next to the videos
+func (t *Tenant) createContactSheet(ctx context.Context, gallery Gallery, columns int) (ContactSheet, error) {
+	videos := gallery.Videos
+	if len(videos) > contactSheetMaxTiles {
+		videos = videos[:contactSheetMaxTiles]
+	}
+
+	// Thumbnails that are missing or can't be decoded stay blank
+	tiles := make([]image.Image, len(videos))
+	var wg sync.WaitGroup
+	for i, video := range videos {
+		if video.ThumbnailPath == "" {
+			continue
+		}
+		wg.Add(1)
+		go func(i int, video Video) {
+			defer wg.Done()
+			thumbnail, err := t.getResizedThumbnail(ctx, video, contactSheetTileWidth)
+			if err != nil {
+				log.Println("Skipping contact sheet tile: " + err.Error())
+				return
+			}
+			tiles[i], err = jpeg.Decode(bytes.NewReader(thumbnail))
+			if err != nil {
+				log.Println("Skipping contact sheet tile: " + err.Error())
+			}
+		}(i, video)
+	}
+	wg.Wait()
+
+	name := gallery.Category + "/" + gallery.Name + "/" + contactSheetName + ".jpg"
+	writer := t.getBucket().Object(name).NewWriter(ctx)
+	writer.ContentType = "image/jpeg"
+	if err := jpeg.Encode(writer, drawContactSheet(tiles, columns), &jpeg.Options{Quality: getThumbnailQuality()}); err != nil {
+		_ = writer.Close()
+		return ContactSheet{}, err
+	}
+	if err := writer.Close(); err != nil {
+		return ContactSheet{}, err
+	}
+
+	signedUrl, err := t.signUrl(name)
+	if err != nil {
+		return ContactSheet{}, err
+	}
+	sheet := ContactSheet{Url: signedUrl, Videos: []string{}}
+	for _, video := range videos {
+		sheet.Videos = append(sheet.Videos, video.Name)
+	}
+	return sheet, nil
+}
+
+const collageName = "_collage"
+
+// A collage needs a full grid of thumbnails
+const collageTiles = 4
+
+func isCollageCovers() bool {
+	return os.Getenv("COLLAGE_COVERS") == "true"
+}
+
+// updateCollages draws a cover from the first thumbnails of galleries without one, redrawing it when they change
+func (t *Tenant) updateCollages(ctx context.Context) {
+	if !t.collagesRunning.CompareAndSwap(false, true) {
+		return
+	}
+	defer t.collagesRunning.Store(false)
+	library := t.getLibrary()
+	metadata := t.getMetadata()
+	drawn := metadata.Collages
+	gates := getRatingGates()
+
+	updated := make(map[string]string)
+	for _, gallery := range t.getGalleries() {
+		key := gallery.Category + "/" + gallery.Name
+		if _, ok := library.Covers[key]; ok {
+			continue
+		}
+
+		// The first thumbnails in gallery order, leaving out hidden and protected videos. The cover shows
+		// before a rated gallery asks for its PIN, so those don't get one at all
+		sortVideos(gallery.Videos, "")
+		var videos []Video
+		var sources []string
+		_, gated := gates[getRating(metadata, gallery.Category, gallery.Name)]
+		for _, video := range withoutHidden(gallery.Videos) {
+			if !gated && video.ThumbnailPath != "" && getProtection(metadata, video) == "" && len(videos) < collageTiles {
+				videos = append(videos, video)
+				sources = append(sources, fmt.Sprintf("%s:%d", video.ThumbnailPath, video.thumbnailVersion))
+			}
+		}
+		name := key + "/" + collageName + ".jpg"
+		if len(videos) < collageTiles {
+			if _, ok := library.Collages[key]; ok {
+				log.Println("Removing Collage: " + name)
+				if err := t.getBucket().Object(name).Delete(ctx); err != nil && !errors.Is(err, storage.ErrObjectNotExist) {
+					log.Println("Failed to remove collage: " + err.Error())
+					continue
+				}
+				updated[key] = ""
+			}
+			continue
+		}
+		hash := getHash(strings.Join(sources, "\n"), t.SecretKey, stubLength)
+		if _, ok := library.Collages[key]; ok && drawn[key] == hash {
+			continue
+		}
+
+		log.Println("Drawing Collage: " + name)
+		if err := t.drawCollage(ctx, name, videos); err != nil {
+			log.Println("Failed to draw collage: " + err.Error())
+			continue
+		}
+		updated[key] = hash
+	}
+	if len(updated) == 0 {
+		return
+	}
+
+	err := t.updateMetadata(ctx, func(metadata *Metadata) {
+		if metadata.Collages == nil {
+			metadata.Collages = make(map[string]string)
+		}
+		for key, hash := range updated {
+			if hash == "" {
+				delete(metadata.Collages, key)
+			} else {
+				metadata.Collages[key] = hash
+			}
+		}
+	})
+	if err != nil {
+		log.Println("Failed to save collages: " + err.Error())
+	}
+
+	// Show the new covers straight away
+	t.videoCache.Delete("videos")
+}
+
+// drawCollage lays the thumbnails out two by two and stores the image next to the videos
+func (t *Tenant) drawCollage(ctx context.Context, name string, videos []Video) error {
+	tiles := make([]image.Image, len(videos))
+	for i, video := range videos {
+		thumbnail, err := t.getResizedThumbnail(ctx, video, contactSheetTileWidth)
+		if err != nil {
+			return err
+		}
+		tiles[i], err = jpeg.Decode(bytes.NewReader(thumbnail))
+		if err != nil {
+			return err
+		}
+	}
+
+	writer := t.getBucket().Object(name).NewWriter(ctx)
+	writer.ContentType = "image/jpeg"
+	if err := jpeg.Encode(writer, drawContactSheet(tiles, 2), &jpeg.Options{Quality: getThumbnailQuality()}); err != nil {
+		_ = writer.Close()
+		return err
+	}
+	return writer.Close()
+}
+
+func (t *Tenant) contactSheetHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to create a contact sheet")
+		return
+	}
+
+	gallery, err := t.getGallery("/gallery/" + r.FormValue("gallery"))
+	if err != nil {
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+	columns := 6
+	if value := r.FormValue("columns"); value != "" {
+		columns, err = strconv.Atoi(value)
+		if err != nil || columns <= 0 || columns > 12 {
+			errorHandler(w, r, http.StatusBadRequest, "columns must be between 1 and 12")
+			return
+		}
+	}
+	if len(gallery.Videos) == 0 {
+		errorHandler(w, r, http.StatusBadRequest, "The gallery is empty")
+		return
+	}
+	log.Println("Creating Contact Sheet: " + gallery.Category + "/" + gallery.Name)
+
+	sortVideos(gallery.Videos, "")
+	sheet, err := t.createContactSheet(r.Context(), gallery, columns)
+	if err != nil {
+		panic(err)
+	}
+	t.audit(getActor(r), "contact_sheet", map[string]string{
+		"gallery": gallery.Category + "/" + gallery.Name,
+	})
+
+	// Link the new sheet from the coverage report
+	t.videoCache.Delete("videos")
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(sheet)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+// subtitlesHandler serves a video's subtitles from the service's own origin, which text tracks need
+func (t *Tenant) subtitlesHandler(w http.ResponseWriter, r *http.Request) {
+	video, err := t.getVideo(strings.TrimPrefix(r.URL.Path, "/subtitles/"))
+	if err != nil || video.SubtitlesPath == "" || !t.canWatch(r, video) {
+		errorHandler(w, r, http.StatusNotFound, "Subtitles not found")
+		return
+	}
+	reader, err := t.getBucket().Object(video.SubtitlesPath).NewReader(r.Context())
+	if err != nil {
+		panic(err)
+	}
+	defer reader.Close()
+
+	w.Header().Set("Content-Type", "text/vtt; charset=utf-8")
+	w.Header().Set("Cache-Control", "no-cache")
+	_, err = io.Copy(w, reader)
+	if err != nil {
+		return
+	}
+}
+
+func isWhisperEnabled() bool {
+	return os.Getenv("WHISPER_URL") != ""
+}
+
+// progressReader reports how many bytes were read so far
+type progressReader struct {
+	reader   io.Reader
+	read     int64
+	progress func(read int64)
+}
+
+func (p *progressReader) Read(buffer []byte) (int, error) {
+	n, err := p.reader.Read(buffer)
+	p.read += int64(n)
+	p.progress(p.read)
+	return n, err
+}
+
+// transcribe sends a video to the speech to text endpoint and returns WebVTT subtitles
+func (t *Tenant) transcribe(ctx context.Context, video Video, language string, progress func(read int64, size int64)) ([]byte, error) {
+	reader, err := t.getBucket().Object(video.VideoPath).NewReader(ctx)
+	if err != nil {
+		return nil, err
+	}
+	defer reader.Close()
+	size := reader.Attrs.Size
+
+	// Stream the video into the form instead of holding it in memory
+	body, pipe := io.Pipe()
+	form := multipart.NewWriter(pipe)
+	go func() {
+		model := os.Getenv("WHISPER_MODEL")
+		if model == "" {
+			model = "whisper-1"
+		}
+		fields := map[string]string{"model": model, "response_format": "vtt", "language": language}
+		for name, value := range fields {
+			if value == "" {
+				continue
+			}
+			if err := form.WriteField(name, value); err != nil {
+				pipe.CloseWithError(err)
+				return
+			}
+		}
+		file, err := form.CreateFormFile("file", path.Base(video.VideoPath))
+		if err != nil {
+			pipe.CloseWithError(err)
+			return
+		}
+		counter := &progressReader{reader: reader, progress: func(read int64) { progress(read, size) }}
+		if _, err := io.Copy(file, counter); err != nil {
+			pipe.CloseWithError(err)
+			return
+		}
+		pipe.CloseWithError(form.Close())
+	}()
+
+	request, err := http.NewRequestWithContext(ctx, http.MethodPost, os.Getenv("WHISPER_URL"), body)
+	if err != nil {
+		_ = body.Close()
+		return nil, err
+	}
+	request.Header.Set("Content-Type", form.FormDataContentType())
+	if apiKey := os.Getenv("WHISPER_API_KEY"); apiKey != "" {
+		request.Header.Set("Authorization", "Bearer "+apiKey)
+	}
+	response, err := http.DefaultClient.Do(request)
+	if err != nil {
+		return nil, err
+	}
+	defer response.Body.Close()
+
+	content, err := io.ReadAll(io.LimitReader(response.Body, 10*1024*1024))
+	if err != nil {
+		return nil, err
+	}
+	if response.StatusCode != http.StatusOK {
+		return nil, fmt.Errorf("speech to text returned %s: %s", response.Status, strings.TrimSpace(string(content)))
+	}
+	if !strings.HasPrefix(strings.TrimPrefix(string(content), "\ufeff"), "WEBVTT") {
+		return nil, fmt.Errorf("speech to text didn't return WebVTT")
+	}
+	return content, nil
+}
+
+// startJob runs a task in the background, so it outlives the request that started it and can be cancelled
+func (t *Tenant) startJob(kind string, run func(ctx context.Context, send func(event string, data any)) error) *Job {
+	var random [8]byte
+	if _, err := rand.Read(random[:]); err != nil {
+		panic(err)
+	}
+	ctx, cancel := context.WithCancel(context.Background())
+	job := &Job{
+		ID:      hex.EncodeToString(random[:]),
+		Kind:    kind,
+		Status:  jobRunning,
+		Started: time.Now(),
+		changed: make(chan struct{}),
+		cancel:  cancel,
+	}
+
+	t.jobsMutex.Lock()
+	if t.jobs == nil {
+		t.jobs = make(map[string]*Job)
+	}
+	for id, other := range t.jobs {
+		other.mutex.Lock()
+		if other.Finished != nil && time.Since(*other.Finished) > jobRetention {
+			delete(t.jobs, id)
+		}
+		other.mutex.Unlock()
+	}
+	t.jobs[job.ID] = job
+	t.jobsMutex.Unlock()
+
+	go func() {
+		defer cancel()
+		// Each job writes its files to its own directory, which is removed with whatever is left in it
+		directory, err := makeJobDir(kind)
+		if err == nil {
+			defer os.RemoveAll(directory)
+			err = run(context.WithValue(ctx, jobDirContextKey{}, directory), job.send)
+		}
+		job.finish(ctx, err)
+	}()
+	return job
+}
+
+type jobDirContextKey struct{}
+
+// getJobsDir holds the working directories of the jobs
+func getJobsDir() string {
+	return filepath.Join(os.TempDir(), "video-gallery-jobs")
+}
+
+func makeJobDir(kind string) (string, error) {
+	if err := os.MkdirAll(getJobsDir(), 0o700); err != nil {
+		return "", err
+	}
+	return os.MkdirTemp(getJobsDir(), kind+"-*")
+}
+
+// getJobDir is the working directory of the job running with ctx
+func getJobDir(ctx context.Context) string {
+	directory, ok := ctx.Value(jobDirContextKey{}).(string)
+	if !ok {
+		panic("getJobDir called outside of a job")
+	}
+	return directory
+}
+
+// sweepJobDirs removes the working directories of jobs that were running when an earlier process stopped
+func sweepJobDirs() {
+	entries, err := os.ReadDir(getJobsDir())
+	if err != nil {
+		return
+	}
+	for _, entry := range entries {
+		info, err := entry.Info()
+		if err != nil || time.Since(info.ModTime()) < staleJobDirAge {
+			continue
+		}
+		if err := os.RemoveAll(filepath.Join(getJobsDir(), entry.Name())); err != nil {
+			log.Println("Failed to remove " + entry.Name() + ": " + err.Error())
+			continue
+		}
+		log.Println("Removed Stale Job Directory: " + entry.Name())
+	}
+}
+
+func (t *Tenant) getJob(id string) (*Job, bool) {
+	t.jobsMutex.Lock()
+	defer t.jobsMutex.Unlock()
+	job, ok := t.jobs[id]
+	return job, ok
+}
+
+// send adds an event and wakes up everyone following the job
+func (job *Job) send(event string, data any) {
+	job.mutex.Lock()
+	defer job.mutex.Unlock()
+	jobEvent := JobEvent{Event: event, Data: data}
+	job.events = append(job.events, jobEvent)
+	if event == "progress" {
+		job.Progress = &jobEvent
+	}
+	close(job.changed)
+	job.changed = make(chan struct{})
+}
+
+func (job *Job) finish(ctx context.Context, err error) {
+	switch {
+	case ctx.Err() != nil:
+		job.send(jobCancelled, map[string]any{})
+	case err != nil:
+		log.Println("Job " + job.ID + " failed: " + err.Error())
+		job.send("error", map[string]any{"error": err.Error()})
+	}
+
+	job.mutex.Lock()
+	defer job.mutex.Unlock()
+	now := time.Now()
+	job.Finished = &now
+	switch {
+	case ctx.Err() != nil:
+		job.Status = jobCancelled
+	case err != nil:
+		job.Status = jobFailed
+		job.Error = err.Error()
+	default:
+		job.Status = jobDone
+	}
+	close(job.changed)
+	job.changed = make(chan struct{})
+}
+
+func (job *Job) getStatus() string {
+	job.mutex.Lock()
+	defer job.mutex.Unlock()
+	return job.Status
+}
+
+// follow returns the events after the first ones already seen, and a channel that's closed when there are more
+func (job *Job) follow(seen int) ([]JobEvent, chan struct{}, bool) {
+	job.mutex.Lock()
+	defer job.mutex.Unlock()
+	return slices.Clone(job.events[seen:]), job.changed, job.Status != jobRunning
+}
+
+// writeJob answers the request that started a job with where to follow it
+func writeJob(w http.ResponseWriter, job *Job) {
+	// Convert to JSON
+	job.mutex.Lock()
+	jsonString, err := json.Marshal(job)
+	job.mutex.Unlock()
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	w.WriteHeader(http.StatusAccepted)
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+// streamJob sends the events of a job from the start as server-sent events, until it ends
+func streamJob(w http.ResponseWriter, r *http.Request, job *Job) {
+	w.Header().Set("Content-Type", "text/event-stream")
+	w.Header().Set("Cache-Control", "no-cache")
+	flusher, _ := w.(http.Flusher)
+	seen := 0
+	for {
+		next, changed, finished := job.follow(seen)
+		for _, event := range next {
+			// Convert to JSON
+			jsonString, err := json.Marshal(event.Data)
+			if err != nil {
+				panic(err)
+			}
+			_, _ = fmt.Fprintf(w, "event: %s\ndata: %s\n\n", event.Event, jsonString)
+		}
+		seen += len(next)
+		if flusher != nil {
+			flusher.Flush()
+		}
+		if finished {
+			return
+		}
+		select {
+		case <-changed:
+		case <-r.Context().Done():
+			return
+		}
+	}
+}
+
+// jobHandler returns the status of a job, cancels it, or streams its events from the start as server-sent events
+func (t *Tenant) jobHandler(w http.ResponseWriter, r *http.Request) {
+	id, events := strings.CutSuffix(r.URL.Path[strings.LastIndex(r.URL.Path, "/jobs/")+len("/jobs/"):], "/events")
+	job, ok := t.getJob(id)
+	if !ok {
+		errorHandler(w, r, http.StatusNotFound, "Job not found")
+		return
+	}
+
+	if events {
+		streamJob(w, r, job)
+		return
+	}
+
+	switch r.Method {
+	case http.MethodGet:
+	case http.MethodDelete:
+		if job.getStatus() == jobRunning {
+			log.Println("Cancelling Job: " + job.ID)
+			t.audit(getActor(r), "cancel_job", map[string]string{
+				"job":  job.ID,
+				"kind": job.Kind,
+			})
+			// Stops the ffmpeg or transcription request it's waiting for
+			job.cancel()
+		}
+	default:
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use GET to check a job or DELETE to cancel it")
+		return
+	}
+
+	// Convert to JSON
+	job.mutex.Lock()
+	jsonString, err := json.Marshal(job)
+	job.mutex.Unlock()
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
+
+const (
+	previewSuffix  = "-preview"
+	previewSeconds = 30
+	// The EBU R128 loudness previews are normalized to
+	previewLoudness = "I=-16:TP=-1.5:LRA=11"
+)
+
+func getFfmpegPath() string {
+	if name := os.Getenv("FFMPEG_PATH"); name != "" {
+		return name
+	}
+	return "ffmpeg"
+}
+
+func getFfprobePath() string {
+	if name := os.Getenv("FFPROBE_PATH"); name != "" {
+		return name
+	}
+	return "ffprobe"
+}
+
+func isFfmpegInstalled() bool {
+	_, err := exec.LookPath(getFfmpegPath())
+	return err == nil
+}
+
+// ffmpegHwaccel is how ffmpeg decodes and encodes H.264 with a hardware encoder
+type ffmpegHwaccel struct {
+	// Flags before the input
+	input []string
+	// Appended to the video filters, to hand the frames to the encoder
+	upload  string
+	encoder []string
+	// The constant quality flag, taking the 0 to 51 scale of libx264's CRF
+	quality string
+}
+
+// hwaccelOptions are keyed by FFMPEG_HWACCEL, the software encoder is used when it isn't set
+var hwaccelOptions = map[string]ffmpegHwaccel{
+	"":             {encoder: []string{"-c:v", "libx264", "-preset", "veryfast"}, quality: "-crf"},
+	"vaapi":        {input: []string{"-vaapi_device", "/dev/dri/renderD128"}, upload: ",format=nv12,hwupload", encoder: []string{"-c:v", "h264_vaapi"}, quality: "-qp"},
+	"nvenc":        {input: []string{"-hwaccel", "cuda"}, encoder: []string{"-c:v", "h264_nvenc", "-preset", "fast"}, quality: "-cq"},
+	"videotoolbox": {input: []string{"-hwaccel", "videotoolbox"}, encoder: []string{"-c:v", "h264_videotoolbox"}, quality: "-q:v"},
+}
+
+func getHwaccel() ffmpegHwaccel {
+	return hwaccelOptions[os.Getenv("FFMPEG_HWACCEL")]
+}
+
+// encode is the encoder flags for a quality on libx264's CRF scale, where lower is better
+func (hwaccel ffmpegHwaccel) encode(crf int) []string {
+	quality := crf
+	// VideoToolbox goes from 1 to 100, where higher is better
+	if hwaccel.quality == "-q:v" {
+		quality = max(1, 100-2*crf)
+	}
+	return append(slices.Clone(hwaccel.encoder), hwaccel.quality, strconv.Itoa(quality))
+}
+
+// filters adds what the encoder needs to the video filters, which may be empty
+func (hwaccel ffmpegHwaccel) filters(filters string) []string {
+	filters = strings.TrimPrefix(filters+hwaccel.upload, ",")
+	if filters == "" {
+		return nil
+	}
+	return []string{"-vf", filters}
+}
+
+// runFfmpeg runs ffmpeg, reporting how much of the output it has written from its progress lines
+func runFfmpeg(ctx context.Context, args []string, progress func(done time.Duration)) error {
+	command := exec.CommandContext(ctx, getFfmpegPath(), append([]string{"-hide_banner", "-nostats", "-progress", "pipe:1"}, args...)...)
+	stdout, err := command.StdoutPipe()
+	if err != nil {
+		return err
+	}
+	var stderr bytes.Buffer
+	command.Stderr = &stderr
+	if err := command.Start(); err != nil {
+		return err
+	}
+	scanner := bufio.NewScanner(stdout)
+	for scanner.Scan() {
+		// Microseconds, like out_time_ms despite its name
+		value, found := strings.CutPrefix(scanner.Text(), "out_time_us=")
+		if microseconds, err := strconv.ParseInt(value, 10, 64); found && err == nil && progress != nil {
+			progress(time.Duration(microseconds) * time.Microsecond)
+		}
+	}
+	if err := command.Wait(); err != nil {
+		return fmt.Errorf("%w: %s", err, lastLine(stderr.Bytes()))
+	}
+	return nil
+}
+
+// uploadFile copies a file that ffmpeg wrote to the bucket
+func uploadFile(ctx context.Context, object *storage.ObjectHandle, name string, contentType string) error {
+	file, err := os.Open(name)
+	if err != nil {
+		return err
+	}
+	defer file.Close()
+	writer := object.NewWriter(ctx)
+	writer.ContentType = contentType
+	if _, err := io.Copy(writer, file); err != nil {
+		_ = writer.Close()
+		return err
+	}
+	return writer.Close()
+}
+
+// lastLine is the end of ffmpeg's log, which says why it failed
+func lastLine(output []byte) string {
+	lines := strings.Split(strings.TrimSpace(string(output)), "\n")
+	return lines[len(lines)-1]
+}
+
+func isProbeVideos() bool {
+	return os.Getenv("PROBE_VIDEOS") == "true"
+}
+
+func isFfprobeInstalled() bool {
+	_, err := exec.LookPath(getFfprobePath())
+	return err == nil
+}
+
+// logFfmpeg says at startup which features are off because ffmpeg or ffprobe can't be found
+func logFfmpeg() {
+	if !isFfmpegInstalled() {
+		log.Println("ffmpeg not found at " + getFfmpegPath() + ", previews, thumbnails, clips, rotating and transcoding are turned off")
+	}
+	if isProbeVideos() && !isFfprobeInstalled() {
+		log.Println("ffprobe not found at " + getFfprobePath() + ", PROBE_VIDEOS is turned off")
+	}
+}
+
+// probeVideo reads the duration, size and frame rate of a video, which ffprobe gets from its headers
+func probeVideo(ctx context.Context, source string) (VideoProbe, error) {
+	output, err := exec.CommandContext(ctx, getFfprobePath(), "-v", "error", "-select_streams", "v:0",
+		"-show_entries", "stream=width,height,avg_frame_rate:format=duration", "-of", "json", source).Output()
+	if err != nil {
+		var exitError *exec.ExitError
+		if errors.As(err, &exitError) {
+			return VideoProbe{}, fmt.Errorf("%w: %s", err, lastLine(exitError.Stderr))
+		}
+		return VideoProbe{}, err
+	}
+	var result struct {
+		Streams []struct {
+			Width        int    `json:"width"`
+			Height       int    `json:"height"`
+			AvgFrameRate string `json:"avg_frame_rate"`
+		} `json:"streams"`
+		Format struct {
+			Duration string `json:"duration"`
+		} `json:"format"`
+	}
+	if err := json.Unmarshal(output, &result); err != nil {
+		return VideoProbe{}, err
+	}
+
+	var probe VideoProbe
+	probe.Duration, _ = strconv.ParseFloat(result.Format.Duration, 64)
+	if len(result.Streams) > 0 {
+		stream := result.Streams[0]
+		probe.Width = stream.Width
+		probe.Height = stream.Height
+		// The frame rate is a fraction, like 30000/1001
+		if numerator, denominator, ok := strings.Cut(stream.AvgFrameRate, "/"); ok {
+			frames, _ := strconv.ParseFloat(numerator, 64)
+			seconds, _ := strconv.ParseFloat(denominator, 64)
+			if seconds > 0 {
+				probe.Fps = math.Round(frames/seconds*100) / 100
+			}
+		}
+	}
+	return probe, nil
+}
+
+// updateProbes runs ffprobe on the videos added or replaced since they were last probed
+func (t *Tenant) updateProbes(ctx context.Context) {
+	if !t.probesRunning.CompareAndSwap(false, true) {
+		return
+	}
+	defer t.probesRunning.Store(false)
+	probed := t.getMetadata().Probes
+
+	keys := make(map[string]bool)
+	var videos []Video
+	for _, video := range t.getLibrary().Videos {
+		if video.IsImage() || video.VideoPath == "" {
+			continue
+		}
+		keys[video.Key()] = true
+		if probe, ok := probed[video.Key()]; !ok || probe.Generation != video.videoVersion {
+			videos = append(videos, video)
+		}
+	}
+	removed := false
+	for key := range probed {
+		if !keys[key] {
+			removed = true
+		}
+	}
+	if len(videos) == 0 && !removed {
+		return
+	}
+	log.Printf("Probing %d Videos\n", len(videos))
+
+	// Videos ffprobe can't read are kept too, so they aren't tried again until they're replaced
+	updated := make(map[string]VideoProbe)
+	var updatedMutex sync.Mutex
+	semaphore := make(chan struct{}, getConcurrency("LIST_CONCURRENCY", 4))
+	var wg sync.WaitGroup
+	for _, video := range videos {
+		wg.Add(1)
+		go func(video Video) {
+			defer wg.Done()
+			semaphore <- struct{}{}
+			defer func() { <-semaphore }()
+			var probe VideoProbe
+			source, err := t.signUrl(video.VideoPath)
+			if err == nil {
+				probe, err = probeVideo(ctx, source)
+			}
+			if err != nil {
+				log.Println("Failed to probe " + video.Key() + ": " + err.Error())
+			}
+			probe.Generation = video.videoVersion
+			updatedMutex.Lock()
+			updated[video.Key()] = probe
+			updatedMutex.Unlock()
+		}(video)
+	}
+	wg.Wait()
+
+	err := t.updateMetadata(ctx, func(metadata *Metadata) {
+		if metadata.Probes == nil {
+			metadata.Probes = make(map[string]VideoProbe)
+		}
+		for key, probe := range updated {
+			metadata.Probes[key] = probe
+		}
+		for key := range metadata.Probes {
+			if !keys[key] {
+				delete(metadata.Probes, key)
+			}
+		}
+	})
+	if err != nil {
+		log.Println("Failed to save probes: " + err.Error())
+	}
+}
+
+// measureLoudness runs the first loudnorm pass over the start of the video
+func measureLoudness(ctx context.Context, source string) (map[string]string, error) {
+	output, err := exec.CommandContext(ctx, getFfmpegPath(), "-hide_banner", "-nostats",
+		"-t", strconv.Itoa(previewSeconds), "-i", source,
+		"-af", "loudnorm="+previewLoudness+":print_format=json", "-f", "null", "-").CombinedOutput()
+	if err != nil {
+		return nil, fmt.Errorf("%w: %s", err, lastLine(output))
+	}
+
+	// The measurements are the last JSON object in the log
+	start := bytes.LastIndexByte(output, '{')
+	end := bytes.LastIndexByte(output, '}')
+	if start < 0 || end < start {
+		return nil, fmt.Errorf("no loudness measurements")
+	}
+	var measured map[string]string
+	if err := json.Unmarshal(output[start:end+1], &measured); err != nil {
+		return nil, err
+	}
+	return measured, nil
+}
+
+// createPreview encodes a small clip from the start of the video with normalized audio, stored next to it
+func (t *Tenant) createPreview(ctx context.Context, video Video) (string, error) {
+	source, err := t.signUrl(video.VideoPath)
+	if err != nil {
+		return "", err
+	}
+	file, err := os.CreateTemp(getJobDir(ctx), "preview-*.mp4")
+	if err != nil {
+		return "", err
+	}
+	_ = file.Close()
+	defer os.Remove(file.Name())
+
+	hwaccel := getHwaccel()
+	args := append([]string{"-hide_banner", "-nostats", "-y"}, hwaccel.input...)
+	args = append(args, "-t", strconv.Itoa(previewSeconds), "-i", source)
+	args = append(args, hwaccel.filters("scale=-2:'min(480,ih)'")...)
+	args = append(args, hwaccel.encode(28)...)
+	args = append(args, "-c:a", "aac", "-b:a", "96k", "-ar", "48000", "-movflags", "+faststart")
+
+	// The second pass corrects the audio with the first pass's measurements, videos without sound skip it
+	measured, err := measureLoudness(ctx, source)
+	if err != nil {
+		log.Println("Not normalizing " + video.Key() + ": " + err.Error())
+	} else {
+		args = append(args, "-af", fmt.Sprintf("loudnorm=%s:measured_I=%s:measured_TP=%s:measured_LRA=%s:measured_thresh=%s:offset=%s:linear=true",
+			previewLoudness, measured["input_i"], measured["input_tp"], measured["input_lra"], measured["input_thresh"], measured["target_offset"]))
+	}
+	output, err := exec.CommandContext(ctx, getFfmpegPath(), append(args, file.Name())...).CombinedOutput()
+	if err != nil {
+		return "", fmt.Errorf("%w: %s", err, lastLine(output))
+	}
+
+	name := video.Key() + previewSuffix + ".mp4"
+	return name, uploadFile(ctx, t.getBucket().Object(name), file.Name(), "video/mp4")
+}
+
+// previewsHandler starts a job creating preview clips for the videos of a gallery
+func (t *Tenant) previewsHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to create previews")
+		return
+	}
+	if !isFfmpegInstalled() {
+		errorHandler(w, r, http.StatusBadRequest, "ffmpeg is needed to create previews")
+		return
+	}
+	gallery, err := t.getGallery("/gallery/" + r.FormValue("gallery"))
+	if err != nil {
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+
+	// Only videos without a preview, unless they're being replaced
+	videoName := r.FormValue("video")
+	replace := r.FormValue("replace") == "true"
+	var videos []Video
+	for _, video := range gallery.Videos {
+		if !video.IsImage() && (videoName == "" || video.Name == videoName) && (replace || video.PreviewPath == "") {
+			videos = append(videos, video)
+		}
+	}
+	if videoName != "" && len(videos) == 0 && !slices.ContainsFunc(gallery.Videos, func(video Video) bool { return video.Name == videoName }) {
+		errorHandler(w, r, http.StatusNotFound, "Video not found")
+		return
+	}
+	log.Println("Creating Previews: " + gallery.Category + "/" + gallery.Name)
+
+	actor := getActor(r)
+	job := t.startJob("previews", func(ctx context.Context, send func(event string, data any)) error {
+		// One at a time, since encoding keeps a CPU busy
+		created := []string{}
+		failed := []string{}
+		defer func() {
+			if len(created) > 0 {
+				t.audit(actor, "previews", map[string]string{
+					"gallery": gallery.Category + "/" + gallery.Name,
+					"videos":  strconv.Itoa(len(created)),
+				})
+
+				// Attach the new clips to their videos
+				t.videoCache.Delete("videos")
+			}
+		}()
+		for i, video := range videos {
+			send("progress", map[string]any{"video": video.Name, "done": i, "total": len(videos)})
+			if _, err := t.createPreview(ctx, video); err != nil {
+				if ctx.Err() != nil {
+					return ctx.Err()
+				}
+				log.Println("Failed to create preview: " + err.Error())
+				send("error", map[string]any{"video": video.Name, "error": err.Error()})
+				failed = append(failed, video.Name)
+				continue
+			}
+			created = append(created, video.Name)
+		}
+		send("done", map[string]any{"created": created, "failed": failed})
+		return nil
+	})
+	writeJob(w, job)
+}
+
+// thumbnailCandidates is how many frames are compared when picking a thumbnail
+const thumbnailCandidates = 8
+
+// scoreFrame rates how well a frame works as a thumbnail, from its contrast and sharpness.
+// Black, white and blurry frames, like fades at the start of a video, score low.
+func scoreFrame(frame image.Image) float64 {
+	bounds := frame.Bounds()
+	if bounds.Empty() {
+		return 0
+	}
+	// A grid of samples is enough to tell frames apart
+	const columns, rows = 64, 36
+	var luma [rows][columns]float64
+	var sum float64
+	for y := 0; y < rows; y++ {
+		for x := 0; x < columns; x++ {
+			gray := color.GrayModel.Convert(frame.At(bounds.Min.X+x*bounds.Dx()/columns, bounds.Min.Y+y*bounds.Dy()/rows)).(color.Gray)
+			luma[y][x] = float64(gray.Y)
+			sum += luma[y][x]
+		}
+	}
+	mean := sum / (columns * rows)
+	var variance, edges float64
+	for y := 0; y < rows; y++ {
+		for x := 0; x < columns; x++ {
+			variance += (luma[y][x] - mean) * (luma[y][x] - mean)
+			if x > 0 {
+				edges += math.Abs(luma[y][x] - luma[y][x-1])
+			}
+			if y > 0 {
+				edges += math.Abs(luma[y][x] - luma[y-1][x])
+			}
+		}
+	}
+	score := math.Sqrt(variance/(columns*rows)) + edges/(2*columns*rows)
+	if mean < 24 || mean > 232 {
+		score /= 10
+	}
+	return score
+}
+
+// createThumbnail saves a frame of a video as its thumbnail, at timeMs or the best of several frames when smart is set
+func (t *Tenant) createThumbnail(ctx context.Context, video Video, timeMs int, smart bool) (string, error) {
+	source, err := t.signUrl(video.VideoPath)
+	if err != nil {
+		return "", err
+	}
+	directory, err := os.MkdirTemp(getJobDir(ctx), "thumbnail-*")
+	if err != nil {
+		return "", err
+	}
+	defer os.RemoveAll(directory)
+
+	// Candidates are spread over the video, leaving out the very start and end
+	times := []float64{float64(timeMs) / 1000}
+	if smart {
+		duration := video.Duration
+		if duration <= 0 {
+			probe, err := probeVideo(ctx, source)
+			if err != nil {
+				return "", err
+			}
+			duration = probe.Duration
+		}
+		times = times[:0]
+		for i := 1; i <= thumbnailCandidates; i++ {
+			times = append(times, duration*float64(i)/(thumbnailCandidates+1))
+		}
+	}
+
+	best := ""
+	bestScore := -1.0
+	for i, seconds := range times {
+		frame := filepath.Join(directory, strconv.Itoa(i)+".jpg")
+		args := []string{"-y", "-ss", strconv.FormatFloat(seconds, 'f', 3, 64), "-i", source,
+			"-frames:v", "1", "-vf", "scale='min(1280,iw)':-2", "-q:v", "3", frame}
+		if err := runFfmpeg(ctx, args, nil); err != nil {
+			return "", err
+		}
+		// Seeking past the end writes no frame
+		file, err := os.Open(frame)
+		if err != nil {
+			continue
+		}
+		decoded, err := jpeg.Decode(file)
+		_ = file.Close()
+		if err != nil {
+			continue
+		}
+		if score := scoreFrame(decoded); score > bestScore {
+			best = frame
+			bestScore = score
+		}
+	}
+	if best == "" {
+		return "", fmt.Errorf("no frame could be read from %s", video.VideoPath)
+	}
+
+	name := video.Key() + ".jpg"
+	if err := uploadFile(ctx, t.getBucket().Object(name), best, "image/jpeg"); err != nil {
+		return "", err
+	}
+	// A thumbnail in another format would compete with the new one
+	if video.ThumbnailPath != "" && video.ThumbnailPath != name {
+		if err := t.getBucket().Object(video.ThumbnailPath).Delete(ctx); err != nil && !errors.Is(err, storage.ErrObjectNotExist) {
+			return "", err
+		}
+	}
+	return name, nil
+}
+
+// thumbnailsHandler starts a job creating thumbnails from video frames for the videos of a gallery
+func (t *Tenant) thumbnailsHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to create thumbnails")
+		return
+	}
+	if isStorageAnonymous() {
+		errorHandler(w, r, http.StatusBadRequest, "storage is read only")
+		return
+	}
+	if !isFfmpegInstalled() {
+		errorHandler(w, r, http.StatusBadRequest, "ffmpeg is needed to create thumbnails")
+		return
+	}
+	gallery, err := t.getGallery("/gallery/" + r.FormValue("gallery"))
+	if err != nil {
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+	smart := r.FormValue("mode") == "smart"
+	if mode := r.FormValue("mode"); mode != "" && mode != "smart" && mode != "frame" {
+		errorHandler(w, r, http.StatusBadRequest, "mode must be frame or smart")
+		return
+	}
+	timeMs := 0
+	if value := r.FormValue("time_ms"); value != "" {
+		timeMs, err = strconv.Atoi(value)
+		if err != nil || timeMs < 0 {
+			errorHandler(w, r, http.StatusBadRequest, "time_ms must be a number of milliseconds")
+			return
+		}
+	}
+
+	// Only videos without a thumbnail, unless they're being replaced
+	videoName := r.FormValue("video")
+	replace := r.FormValue("replace") == "true"
+	var videos []Video
+	for _, video := range gallery.Videos {
+		if !video.IsImage() && (videoName == "" || video.Name == videoName) && (replace || video.ThumbnailPath == "") {
+			videos = append(videos, video)
+		}
+	}
+	if videoName != "" && len(videos) == 0 && !slices.ContainsFunc(gallery.Videos, func(video Video) bool { return video.Name == videoName }) {
+		errorHandler(w, r, http.StatusNotFound, "Video not found")
+		return
+	}
+	log.Println("Creating Thumbnails: " + gallery.Category + "/" + gallery.Name)
+
+	actor := getActor(r)
+	job := t.startJob("thumbnails", func(ctx context.Context, send func(event string, data any)) error {
+		created := []string{}
+		failed := []string{}
+		defer func() {
+			if len(created) > 0 {
+				t.audit(actor, "thumbnails", map[string]string{
+					"gallery": gallery.Category + "/" + gallery.Name,
+					"videos":  strconv.Itoa(len(created)),
+				})
+
+				// Attach the new thumbnails to their videos
+				t.videoCache.Delete("videos")
+			}
+		}()
+		for i, video := range videos {
+			send("progress", map[string]any{"video": video.Name, "done": i, "total": len(videos)})
+			if _, err := t.createThumbnail(ctx, video, timeMs, smart); err != nil {
+				if ctx.Err() != nil {
+					return ctx.Err()
+				}
+				log.Println("Failed to create thumbnail: " + err.Error())
+				send("error", map[string]any{"video": video.Name, "error": err.Error()})
+				failed = append(failed, video.Name)
+				continue
+			}
+			created = append(created, video.Name)
+		}
+		send("done", map[string]any{"created": created, "failed": failed})
+		return nil
+	})
+	writeJob(w, job)
+}
+
+// clipHandler starts a job cutting part of a video into a new video, in the same or another gallery
+func (t *Tenant) clipHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to cut a clip")
+		return
+	}
+	if isStorageAnonymous() {
+		errorHandler(w, r, http.StatusBadRequest, "storage is read only")
+		return
+	}
+	if !isFfmpegInstalled() {
+		errorHandler(w, r, http.StatusBadRequest, "ffmpeg is needed to cut clips")
+		return
+	}
+	video, err := t.getVideo(r.FormValue("video"))
+	if err != nil || video.IsImage() {
+		errorHandler(w, r, http.StatusNotFound, "Video not found")
+		return
+	}
+	startMs, startErr := strconv.Atoi(r.FormValue("start_ms"))
+	endMs, endErr := strconv.Atoi(r.FormValue("end_ms"))
+	if startErr != nil || endErr != nil || startMs < 0 || endMs <= startMs {
+		errorHandler(w, r, http.StatusBadRequest, "start_ms and end_ms must be milliseconds, with start_ms before end_ms")
+		return
+	}
+
+	// Clips go next to the video unless another gallery is given
+	folder := path.Dir(video.VideoPath)
+	if stub := r.FormValue("gallery"); stub != "" {
+		gallery, err := t.getGallery("/gallery/" + stub)
+		if err != nil {
+			errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+			return
+		}
+		folder = gallery.Category + "/" + gallery.Name
+	}
+	clipName := r.FormValue("name")
+	if clipName == "" {
+		clipName = fmt.Sprintf("%s (%d-%d)", video.Name, startMs/1000, endMs/1000)
+	}
+	name := folder + "/" + clipName + ".mp4"
+	if !isSafeName(clipName) || strings.HasSuffix(clipName, previewSuffix) {
+		errorHandler(w, r, http.StatusBadRequest, "name must be a file name without an extension")
+		return
+	}
+	if _, err := t.getBucket().Object(name).Attrs(r.Context()); err == nil {
+		errorHandler(w, r, http.StatusConflict, "A video with that name already exists")
+		return
+	}
+	log.Println("Cutting Clip: " + name)
+
+	actor := getActor(r)
+	job := t.startJob("clip", func(ctx context.Context, send func(event string, data any)) error {
+		source, err := t.signUrl(video.VideoPath)
+		if err != nil {
+			return err
+		}
+		file := filepath.Join(getJobDir(ctx), "clip.mp4")
+
+		// Seeking before the input is fast, and encoding again makes the cut exact
+		length := time.Duration(endMs-startMs) * time.Millisecond
+		hwaccel := getHwaccel()
+		args := append([]string{"-y"}, hwaccel.input...)
+		args = append(args, "-ss", strconv.FormatFloat(float64(startMs)/1000, 'f', 3, 64), "-i", source,
+			"-t", strconv.FormatFloat(length.Seconds(), 'f', 3, 64))
+		args = append(args, hwaccel.filters("")...)
+		args = append(args, hwaccel.encode(20)...)
+		args = append(args, "-c:a", "aac", "-b:a", "160k", "-movflags", "+faststart", file)
+		var reported time.Time
+		err = runFfmpeg(ctx, args, func(done time.Duration) {
+			if time.Since(reported) < time.Second {
+				return
+			}
+			reported = time.Now()
+			send("progress", map[string]any{"stage": "encoding", "done_ms": min(done, length).Milliseconds(), "total_ms": length.Milliseconds()})
+		})
+		if err != nil {
+			return err
+		}
+
+		// Never replace a video that was uploaded in the meantime
+		send("progress", map[string]any{"stage": "uploading"})
+		object := t.getBucket().Object(name).If(storage.Conditions{DoesNotExist: true})
+		if err := uploadFile(ctx, object, file, "video/mp4"); err != nil {
+			return err
+		}
+		t.audit(actor, "clip", map[string]string{
+			"video":    video.Key(),
+			"path":     name,
+			"start_ms": strconv.Itoa(startMs),
+			"end_ms":   strconv.Itoa(endMs),
+		})
+
+		// Pick up the new video
+		t.videoCache.Delete("videos")
+		send("done", map[string]any{"path": name})
+		return nil
+	})
+	writeJob(w, job)
+}
+
+// rotateHandler starts a job fixing the rotation a video is shown with, keeping the old file as a version
+func (t *Tenant) rotateHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to rotate a video")
+		return
+	}
+	if isStorageAnonymous() {
+		errorHandler(w, r, http.StatusBadRequest, "storage is read only")
+		return
+	}
+	if !isFfmpegInstalled() {
+		errorHandler(w, r, http.StatusBadRequest, "ffmpeg is needed to rotate videos")
+		return
+	}
+	video, err := t.getVideo(r.FormValue("video"))
+	if err != nil || video.IsImage() {
+		errorHandler(w, r, http.StatusNotFound, "Video not found")
+		return
+	}
+	// Only MP4 and MOV keep the rotation in the stream's metadata, so the video isn't encoded again
+	extension := strings.ToLower(path.Ext(video.VideoPath))
+	if !slices.Contains([]string{".mp4", ".m4v", ".mov"}, extension) {
+		errorHandler(w, r, http.StatusBadRequest, "Only MP4 and MOV videos can be rotated")
+		return
+	}
+	rotation := r.FormValue("rotation")
+	if !slices.Contains([]string{"0", "90", "180", "270"}, rotation) {
+		errorHandler(w, r, http.StatusBadRequest, "rotation must be 0, 90, 180 or 270")
+		return
+	}
+	thumbnail := r.FormValue("thumbnail") != "false"
+	log.Println("Rotating Video: " + video.VideoPath + " to " + rotation)
+
+	actor := getActor(r)
+	job := t.startJob("rotate", func(ctx context.Context, send func(event string, data any)) error {
+		source, err := t.signUrl(video.VideoPath)
+		if err != nil {
+			return err
+		}
+		// Copying the streams keeps the quality and only rewrites the rotation
+		send("progress", map[string]any{"stage": "remuxing"})
+		file := filepath.Join(getJobDir(ctx), "video"+extension)
+		args := []string{"-y", "-i", source, "-map", "0", "-c", "copy", "-metadata:s:v:0", "rotate=" + rotation, "-movflags", "+faststart", file}
+		if err := runFfmpeg(ctx, args, nil); err != nil {
+			return err
+		}
+
+		// Only the file that was archived is replaced
+		send("progress", map[string]any{"stage": "uploading"})
+		object := t.getBucket().Object(video.VideoPath)
+		attrs, err := object.Attrs(ctx)
+		if err != nil {
+			return err
+		}
+		archived, err := t.archiveVersion(ctx, video, attrs.Generation)
+		if err != nil {
+			return err
+		}
+		contentType := attrs.ContentType
+		if contentType == "" {
+			contentType = mime.TypeByExtension(extension)
+		}
+		if err := uploadFile(ctx, object.If(storage.Conditions{GenerationMatch: attrs.Generation}), file, contentType); err != nil {
+			return err
+		}
+		t.audit(actor, "rotate", map[string]string{
+			"path":     video.VideoPath,
+			"rotation": rotation,
+			"archived": archived.Version,
+		})
+		t.videoCache.Delete("videos")
+
+		// The thumbnail was taken from the video as it was shown before
+		result := map[string]any{"path": video.VideoPath, "archived": archived.Version}
+		if thumbnail {
+			send("progress", map[string]any{"stage": "thumbnail"})
+			name, err := t.createThumbnail(ctx, video, 0, true)
+			if err != nil {
+				return fmt.Errorf("the video was rotated, but its thumbnail failed: %w", err)
+			}
+			result["thumbnail"] = name
+		}
+		send("done", result)
+		return nil
+	})
+	writeJob(w, job)
+}
+
+// Transcodes of videos browsers can't play are kept in the bucket under this prefix, by video id and version
+const transcodesPrefix = ".transcodes/"
+
+// How often a transcode's last use is written to the bucket, for evicting the least recently used
+const transcodeTouchInterval = time.Hour
+
+// getTranscodeQuota is how many bytes of transcodes are kept
+func getTranscodeQuota() int64 {
+	return int64(getConcurrency("TRANSCODE_QUOTA_GB", 20)) << 30
+}
+
+// transcodeHandler serves an MP4 transcode of a video browsers can't play, starting or joining the transcode when there's none yet
+func (t *Tenant) transcodeHandler(w http.ResponseWriter, r *http.Request) {
+	video, err := t.getVideo(strings.TrimPrefix(r.URL.Path, "/stream/transcode/"))
+	if err != nil || video.Url == "" || video.IsImage() || slices.Contains(t.getMetadata().HiddenVideos, video.Key()) {
+		errorHandler(w, r, http.StatusNotFound, "Video not found")
+		return
+	}
+	gallery, err := t.getVideoGallery(video)
+	if err != nil || isCategoryAdminOnly(gallery.Category) {
+		errorHandler(w, r, http.StatusNotFound, "Video not found")
+		return
+	}
+	if err := t.verifyGallerySignature(gallery, r.URL.Query()); err != nil {
+		errorHandler(w, r, http.StatusForbidden, err.Error())
+		return
+	}
+	if key := getProtection(t.getMetadata(), video); key != "" && !t.passwordPrompt(w, r, key, video.Name) {
+		return
+	}
+	if !t.gatePrompt(w, r, video.Category, video.Gallery, video.Name) {
+		return
+	}
+	// Videos that play as they are don't need it
+	if !video.NeedsTranscode() {
+		http.Redirect(w, r, "/video/"+video.Id+guestLinkQuery(r), http.StatusFound)
+		return
+	}
+
+	name := transcodesPrefix + video.Id + "-" + strconv.FormatInt(video.videoVersion, 10) + ".mp4"
+	object := t.getBucket().Object(name)
+	if attrs, err := object.Attrs(r.Context()); err == nil {
+		if time.Since(attrs.CustomTime) > transcodeTouchInterval && !isStorageAnonymous() {
+			if _, err := object.Update(r.Context(), storage.ObjectAttrsToUpdate{CustomTime: time.Now()}); err != nil {
+				log.Println("Failed to mark transcode as used: " + err.Error())
+			}
+		}
+		signedUrl, err := t.signUrl(name)
+		if err != nil {
+			panic(err)
+		}
+		w.Header().Set("Cache-Control", "no-store")
+		http.Redirect(w, r, signedUrl, http.StatusFound)
+		return
+	}
+	if isStorageAnonymous() || !isFfmpegInstalled() {
+		errorHandler(w, r, http.StatusNotFound, "This video can't be converted for the browser")
+		return
+	}
+
+	job := t.startTranscode(video, name)
+	if r.URL.Query().Get("events") == "true" {
+		streamJob(w, r, job)
+		return
+	}
+	writeJob(w, job)
+}
+
+// startTranscode starts transcoding a video into name, or returns the transcode that's already running
+func (t *Tenant) startTranscode(video Video, name string) *Job {
+	t.transcodesMutex.Lock()
+	defer t.transcodesMutex.Unlock()
+	if job, ok := t.transcodes[name]; ok && job.getStatus() == jobRunning {
+		return job
+	}
+	if t.transcodes == nil {
+		t.transcodes = make(map[string]*Job)
+	}
+	for other, job := range t.transcodes {
+		if job.getStatus() != jobRunning {
+			delete(t.transcodes, other)
+		}
+	}
+	log.Println("Transcoding Video: " + video.VideoPath)
+	job := t.startJob("transcode", func(ctx context.Context, send func(event string, data any)) error {
+		source, err := t.signUrl(video.VideoPath)
+		if err != nil {
+			return err
+		}
+		file := filepath.Join(getJobDir(ctx), "transcode.mp4")
+		hwaccel := getHwaccel()
+		args := append([]string{"-y"}, hwaccel.input...)
+		args = append(args, "-i", source, "-map", "0:v:0", "-map", "0:a:0?")
+		args = append(args, hwaccel.filters("")...)
+		args = append(args, hwaccel.encode(23)...)
+		args = append(args, "-c:a", "aac", "-b:a", "160k", "-movflags", "+faststart", file)
+		total := time.Duration(video.Duration * float64(time.Second))
+		var reported time.Time
+		err = runFfmpeg(ctx, args, func(done time.Duration) {
+			if time.Since(reported) < time.Second {
+				return
+			}
+			reported = time.Now()
+			send("progress", map[string]any{"stage": "encoding", "done_ms": done.Milliseconds(), "total_ms": total.Milliseconds()})
+		})
+		if err != nil {
+			return err
+		}
+
+		// Another instance may have finished the same transcode first
+		send("progress", map[string]any{"stage": "uploading"})
+		object := t.getBucket().Object(name).If(storage.Conditions{DoesNotExist: true})
+		err = uploadFile(ctx, object, file, "video/mp4")
+		var apiErr *googleapi.Error
+		if err != nil && !(errors.As(err, &apiErr) && apiErr.Code == http.StatusPreconditionFailed) {
+			return err
+		}
+		if err := t.evictTranscodes(ctx); err != nil {
+			log.Println("Failed to evict transcodes: " + err.Error())
+		}
+		send("done", map[string]any{"url": "/stream/transcode/" + video.Id})
+		return nil
+	})
+	t.transcodes[name] = job
+	return job
+}
+
+// evictTranscodes deletes the least recently used transcodes until they fit in the quota
+func (t *Tenant) evictTranscodes(ctx context.Context) error {
+	var transcodes []*storage.ObjectAttrs
+	var total int64
+	objects := t.getBucket().Objects(ctx, &storage.Query{Prefix: transcodesPrefix})
+	for {
+		attrs, err := objects.Next()
+		if errors.Is(err, iterator.Done) {
+			break
+		}
+		if err != nil {
+			return err
+		}
+		transcodes = append(transcodes, attrs)
+		total += attrs.Size
+	}
+
+	// Transcodes that were never served again count from when they were made
+	lastUsed := func(attrs *storage.ObjectAttrs) time.Time {
+		if attrs.CustomTime.After(attrs.Created) {
+			return attrs.CustomTime
+		}
+		return attrs.Created
+	}
+	slices.SortFunc(transcodes, func(a, b *storage.ObjectAttrs) int { return lastUsed(a).Compare(lastUsed(b)) })
+	quota := getTranscodeQuota()
+	for _, attrs := range transcodes {
+		if total <= quota {
+			break
+		}
+		log.Println("Evicting Transcode: " + attrs.Name)
+		if err := t.getBucket().Object(attrs.Name).Delete(ctx); err != nil && !errors.Is(err, storage.ErrObjectNotExist) {
+			return err
+		}
+		total -= attrs.Size
+	}
+	return nil
+}
+
+// subtitlesJobHandler starts a job generating subtitles for a video
+func (t *Tenant) subtitlesJobHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to generate subtitles")
+		return
+	}
+	if !isWhisperEnabled() {
+		errorHandler(w, r, http.StatusBadRequest, "Set WHISPER_URL to generate subtitles")
+		return
+	}
+	video, err := t.getVideo(r.FormValue("video"))
+	if err != nil || video.IsImage() {
+		errorHandler(w, r, http.StatusNotFound, "Video not found")
+		return
+	}
+	log.Println("Generating Subtitles: " + video.Key())
+
+	actor := getActor(r)
+	language := r.FormValue("language")
+	job := t.startJob("subtitles", func(ctx context.Context, send func(event string, data any)) error {
+		// Report the upload at most once a second, then wait for the transcript
+		var reported time.Time
+		subtitles, err := t.transcribe(ctx, video, language, func(read int64, size int64) {
+			if read < size && time.Since(reported) < time.Second {
+				return
+			}
+			reported = time.Now()
+			send("progress", map[string]any{"stage": "uploading", "bytes": read, "total_bytes": size})
+			if read >= size {
+				send("progress", map[string]any{"stage": "transcribing"})
+			}
+		})
+		if err != nil {
+			return err
+		}
+
+		send("progress", map[string]any{"stage": "saving"})
+		name := video.Key() + ".vtt"
+		writer := t.getBucket().Object(name).NewWriter(ctx)
+		writer.ContentType = "text/vtt"
+		if _, err := writer.Write(subtitles); err != nil {
+			_ = writer.Close()
+			return err
+		}
+		if err := writer.Close(); err != nil {
+			return err
+		}
+		t.audit(actor, "subtitles", map[string]string{
+			"video": video.Key(),
+		})
+
+		// Attach the new sidecar to the video
+		t.videoCache.Delete("videos")
+		send("done", map[string]any{"subtitles": name})
+		return nil
+	})
+	writeJob(w, job)
+}
+
+// isDownloadable is true for galleries listed, or in a category listed, in DOWNLOADABLE_GALLERIES
+func isDownloadable(gallery Gallery) bool {
+	entries := getList("DOWNLOADABLE_GALLERIES")
+	return slices.Contains(entries, gallery.Category) || slices.Contains(entries, gallery.Category+"/"+gallery.Name)
+}
+
+// downloadHandler streams a gallery as a ZIP archive, one file at a time so memory use stays flat
+func (t *Tenant) downloadHandler(w http.ResponseWriter, r *http.Request) {
+	gallery, err := t.getGallery(strings.TrimSuffix(r.URL.Path, "/download.zip"))
+	if err != nil || isCategoryAdminOnly(gallery.Category) || !isDownloadable(gallery) {
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return
+	}
+	if err := t.verifyGallerySignature(gallery, r.URL.Query()); err != nil {
+		errorHandler(w, r, http.StatusForbidden, err.Error())
+		return
+	}
+	galleryKey := gallery.Category + "/" + gallery.Name
+	if _, protected := t.getMetadata().Passwords[galleryKey]; protected && !t.passwordPrompt(w, r, galleryKey, gallery.Name) {
+		return
+	}
+	if !t.gatePrompt(w, r, gallery.Category, gallery.Name, gallery.Name) {
+		return
+	}
+	thumbnails := r.URL.Query().Get("thumbnails") == "true"
+	log.Println("Downloading Gallery: " + galleryKey)
+
+	var names []string
+	videos := withoutHidden(gallery.Videos)
+	sortVideos(videos, "")
+	for _, video := range videos {
+		if !t.canWatch(r, video) {
+			continue
+		}
+		names = append(names, video.VideoPath)
+		if thumbnails && !video.IsImage() && video.ThumbnailPath != "" {
+			names = append(names, video.ThumbnailPath)
+		}
+	}
+
+	w.Header().Set("Content-Type", "application/zip")
+	w.Header().Set("Content-Disposition", mime.FormatMediaType("attachment", map[string]string{"filename": gallery.Name + ".zip"}))
+	archive := zip.NewWriter(w)
+	for _, name := range names {
+		reader, err := t.getBucket().Object(name).NewReader(r.Context())
+		if err != nil {
+			// The response has started, so a broken archive is all that's left to signal it
+			log.Println("Failed to download " + name + ": " + err.Error())
+			return
+		}
+		// Videos and photos are compressed already
+		file, err := archive.CreateHeader(&zip.FileHeader{
+			Name:     path.Base(name),
+			Method:   zip.Store,
+			Modified: reader.Attrs.LastModified,
+		})
+		if err == nil {
+			_, err = io.Copy(file, reader)
+		}
+		_ = reader.Close()
+		if err != nil {
+			log.Println("Failed to download " + name + ": " + err.Error())
+			return
+		}
+	}
+	if err := archive.Close(); err != nil {
+		return
+	}
+}
+
+// videoHandler redirects to a freshly signed url, so the link never expires
+func (t *Tenant) videoHandler(w http.ResponseWriter, r *http.Request) {
+	video, err := t.getVideo(strings.TrimPrefix(r.URL.Path, "/video/"))
+	if err != nil || video.Url == "" || slices.Contains(t.getMetadata().HiddenVideos, video.Key()) {
+		errorHandler(w, r, http.StatusNotFound, "Video not found")
+		return
+	}
+	// Same as the gallery's own pages, the link needs the gallery's guest link signature
+	gallery, err := t.getVideoGallery(video)
+	if err != nil || isCategoryAdminOnly(gallery.Category) {
+		errorHandler(w, r, http.StatusNotFound, "Video not found")
+		return
+	}
+	if err := t.verifyGallerySignature(gallery, r.URL.Query()); err != nil {
+		errorHandler(w, r, http.StatusForbidden, err.Error())
+		return
+	}
+	if key := getProtection(t.getMetadata(), video); key != "" && !t.passwordPrompt(w, r, key, video.Name) {
+		return
+	}
+	if !t.gatePrompt(w, r, video.Category, video.Gallery, video.Name) {
+		return
+	}
+	videos := []Video{video}
+	t.selectRenditions(r, videos)
+	w.Header().Set("Cache-Control", "no-store")
+	http.Redirect(w, r, videos[0].Url, http.StatusFound)
+}
+
+const (
+	renditionHls      = "hls"
+	renditionMp4      = "mp4"
+	renditionOriginal = "original"
+)
+
+// deviceRenditions lists the renditions each kind of client plays best first
+var deviceRenditions = map[string][]string{
+	// Safari only streams HLS natively, and TVs cope better with it than with large files
+	"ios":     {renditionHls, renditionMp4, renditionOriginal},
+	"tv":      {renditionHls, renditionMp4, renditionOriginal},
+	"android": {renditionMp4, renditionHls, renditionOriginal},
+	"desktop": {renditionMp4, renditionOriginal, renditionHls},
+}
+
+// getDevice sorts a client into one of the device profiles by its user agent
+func getDevice(userAgent string) string {
+	contains := func(markers ...string) bool {
+		return slices.ContainsFunc(markers, func(marker string) bool { return strings.Contains(userAgent, marker) })
+	}
+	switch {
+	case contains("SmartTV", "SMART-TV", "Tizen", "Web0S", "WebOS", "BRAVIA", "AppleTV", "CrKey", "; AFT", "Roku"):
+		return "tv"
+	case contains("iPhone", "iPad", "iPod"):
+		return "ios"
+	case contains("Android"):
+		return "android"
+	}
+	return "desktop"
+}
+
+// getRenditionKind tells MP4 files, which play almost everywhere, from other uploads
+func getRenditionKind(extension string) string {
+	if extension == ".mp4" || extension == ".m4v" {
+		return renditionMp4
+	}
+	return renditionOriginal
+}
+
+// getRendition picks the rendition the client plays best and its object name, which is the video itself when it has only one
+func getRendition(r *http.Request, video Video) (string, string) {
+	for _, kind := range deviceRenditions[getDevice(r.UserAgent())] {
+		if name, ok := video.renditions[kind]; ok {
+			return kind, name
+		}
+	}
+	return "", video.VideoPath
+}
+
+func getPlaylistUrl(video Video) string {
+	return "/hls/" + video.Id + ".m3u8"
+}
+
+// selectRenditions hands out the rendition each video plays best in on the client, instead of the last one listed
+func (t *Tenant) selectRenditions(r *http.Request, videos []Video) {
+	objects := t.getLibrary().objects
+	for i, video := range videos {
+		kind, name := getRendition(r, video)
+		switch kind {
+		case "":
+			continue
+		case renditionHls:
+			videos[i].Url = getPlaylistUrl(video)
+		default:
+			videos[i].Url = objects[name].url
+		}
+		videos[i].Rendition = kind
+		if kind != renditionOriginal {
+			videos[i].Playable = true
+		}
+	}
+}
+
+// Playlists keep their signed segment urls for as long as the library keeps its urls
+var playlistCache = cache.New(signedUrlTTL/2, time.Hour)
+
+var playlistUriRegex = regexp.MustCompile(`URI="([^"]+)"`)
+
+// getPlaylist signs the segments, keys and init sections of a playlist, and sends other playlists it names back through the proxy
+func (t *Tenant) getPlaylist(ctx context.Context, video Video, name string) ([]byte, error) {
+	master := video.renditions[renditionHls]
+	cacheKey := fmt.Sprintf("%s/%s/%d", t.BucketName, name, t.getLibrary().objects[master].generation)
+	if playlist, ok := playlistCache.Get(cacheKey); ok {
+		return playlist.([]byte), nil
+	}
+
+	reader, err := t.getBucket().Object(name).NewReader(ctx)
+	if err != nil {
+		return nil, err
+	}
+	defer reader.Close()
+	data, err := io.ReadAll(reader)
+	if err != nil {
+		return nil, err
+	}
+
+	// Relative uris are resolved against the playlist, and have to stay in the gallery folder
+	root := path.Dir(master)
+	lines := strings.Split(string(data), "\n")
+	var uris []string
+	for _, line := range lines {
+		line = strings.TrimSpace(line)
+		if strings.HasPrefix(line, "#") {
+			for _, match := range playlistUriRegex.FindAllStringSubmatch(line, -1) {
+				uris = append(uris, match[1])
+			}
+		} else if line != "" {
+			uris = append(uris, line)
+		}
+	}
+	targets := make(map[string]string)
+	var names []string
+	for _, uri := range uris {
+		if _, ok := targets[uri]; ok || strings.Contains(uri, "://") {
+			continue
+		}
+		target, _, _ := strings.Cut(path.Join(path.Dir(name), uri), "?")
+		if !strings.HasPrefix(target, root+"/") {
+			return nil, fmt.Errorf("%s points outside the gallery", uri)
+		}
+		if path.Ext(target) == ".m3u8" {
+			targets[uri] = getPlaylistUrl(video) + "?playlist=" + url.QueryEscape(strings.TrimPrefix(target, root+"/"))
+			continue
+		}
+		targets[uri] = target
+		names = append(names, target)
+	}
+	signedUrls, err := t.signUrls(names)
+	if err != nil {
+		return nil, err
+	}
+	for uri, target := range targets {
+		if signedUrl, ok := signedUrls[target]; ok {
+			targets[uri] = signedUrl
+		}
+	}
+
+	for i, line := range lines {
+		trimmed := strings.TrimSpace(line)
+		if strings.HasPrefix(trimmed, "#") {
+			lines[i] = playlistUriRegex.ReplaceAllStringFunc(trimmed, func(attribute string) string {
+				if target, ok := targets[playlistUriRegex.FindStringSubmatch(attribute)[1]]; ok {
+					return `URI="` + target + `"`
+				}
+				return attribute
+			})
+		} else if target, ok := targets[trimmed]; ok {
+			lines[i] = target
+		}
+	}
+	playlist := []byte(strings.Join(lines, "\n"))
+	playlistCache.Set(cacheKey, playlist, cache.DefaultExpiration)
+	return playlist, nil
+}
+
+// playlistHandler serves a video's HLS playlist, since the segments in the bucket can only be fetched with signed urls
+func (t *Tenant) playlistHandler(w http.ResponseWriter, r *http.Request) {
+	video, err := t.getVideo(strings.TrimSuffix(strings.TrimPrefix(r.URL.Path, "/hls/"), ".m3u8"))
+	master, ok := video.renditions[renditionHls]
+	if err != nil || !ok || slices.Contains(t.getMetadata().HiddenVideos, video.Key()) || !t.canWatch(r, video) {
+		errorHandler(w, r, http.StatusNotFound, "Playlist not found")
+		return
+	}
+
+	// Variant playlists of a master playlist are named relative to the gallery folder
+	name := master
+	if variant := r.URL.Query().Get("playlist"); variant != "" {
+		name = path.Join(path.Dir(master), variant)
+		if !strings.HasPrefix(name, path.Dir(master)+"/") || path.Ext(name) != ".m3u8" {
+			errorHandler(w, r, http.StatusNotFound, "Playlist not found")
+			return
+		}
+	}
+
+	playlist, err := t.getPlaylist(r.Context(), video, name)
+	if errors.Is(err, storage.ErrObjectNotExist) {
+		errorHandler(w, r, http.StatusNotFound, "Playlist not found")
+		return
+	}
+	if err != nil {
+		log.Println("Failed to read playlist: " + err.Error())
+		errorHandler(w, r, http.StatusBadGateway, "The playlist can't be read right now")
+		return
+	}
+	w.Header().Set("Content-Type", "application/vnd.apple.mpegurl")
+	w.Header().Set("Cache-Control", "private, max-age=3600")
+	_, err = w.Write(playlist)
+	if err != nil {
+		return
+	}
+}
+
+func (t *Tenant) strmExportHandler(w http.ResponseWriter, r *http.Request) {
+	log.Println("Generating STRM Export")
+	baseUrl := getBaseUrl(r)
+
+	w.Header().Set("Content-Type", "application/zip")
+	w.Header().Set("Content-Disposition", `attachment; filename="video-gallery-strm.zip"`)
+	archive := zip.NewWriter(w)
+	// Media centers keep the links, so signed ones last until the gallery key is rotated or a year has passed
+	expires := time.Now().Add(365 * 24 * time.Hour)
+	for _, gallery := range withoutHiddenVideos(t.getPublicGalleries()) {
+		for _, video := range gallery.Videos {
+			if video.IsImage() {
+				continue
+			}
+			name := gallery.Category + "/" + gallery.Name + "/" + video.Name
+
+			// The stream points at the redirect rather than the expiring signed url
+			file, err := archive.Create(name + ".strm")
+			if err != nil {
+				panic(err)
+			}
+			link := baseUrl + "/video/" + video.Id
+			if isSignedGalleryLinks() {
+				link += "?" + t.signGalleryQuery(gallery, expires)
+			}
+			if _, err := io.WriteString(file, link+"\n"); err != nil {
+				return
+			}
+
+			nfo := KodiNfo{
+				Title:     video.Name,
+				Set:       gallery.Name,
+				Genre:     gallery.Category,
+				Tags:      video.Tags,
+				Premiered: video.Created.Format(time.DateOnly),
+			}
+			if video.ThumbnailPath != "" {
+				nfo.Thumb = baseUrl + video.thumbnailUrl(thumbnailWidths[len(thumbnailWidths)-1])
+			}
+			file, err = archive.Create(name + ".nfo")
+			if err != nil {
+				panic(err)
+			}
+			if _, err := io.WriteString(file, xml.Header); err != nil {
+				return
+			}
+			encoder := xml.NewEncoder(file)
+			encoder.Indent("", "  ")
+			if err := encoder.Encode(nfo); err != nil {
+				return
 			}
-		}
-		if !exists {
-			categories = append(categories, Category{
-				Name:      category,
-				Stub:      category,
-				Galleries: []Gallery{gallery},
-			})
 		}
 	}
-	return categories
+	if err := archive.Close(); err != nil {
+		return
+	}
 }
 
-func getGallery(stub string) (Gallery, error) {
-	// Get gallery
-	for _, gallery := range getGalleries() {
-		if gallery.Stub == stub {
-			return gallery, nil
-		}
+func (t *Tenant) refreshUrlsHandler(w http.ResponseWriter, r *http.Request) {
+	if r.Method != http.MethodPost {
+		errorHandler(w, r, http.StatusMethodNotAllowed, "Use POST to refresh urls")
+		return
 	}
-	return Gallery{}, fmt.Errorf("gallery not found")
-}
 
-func getGalleries() []Gallery {
-	videos := getVideos()
-	secretKey := os.Getenv("SECRET_KEY")
+	var request struct {
+		Ids []string `json:"ids"`
+	}
+	if err := json.NewDecoder(http.MaxBytesReader(w, r.Body, 1<<20)).Decode(&request); err != nil {
+		errorHandler(w, r, http.StatusBadRequest, "Expected a JSON body with a list of ids")
+		return
+	}
+	if len(request.Ids) > 100 {
+		errorHandler(w, r, http.StatusBadRequest, "Refresh at most 100 urls at once")
+		return
+	}
+	log.Printf("Refreshing %d Urls\n", len(request.Ids))
 
-	var galleries []Gallery
-	for _, video := range videos {
-		category := video.Category
-		gallery := video.Gallery
-		// Check if gallery already exists
-		exists := false
-		for i, g := range galleries {
-			if g.Name == gallery {
-				galleries[i].Videos = append(galleries[i].Videos, video)
-				exists = true
-				break
-			}
+	// Sign new urls for the requested videos
+	urls := make(map[string]SignedUrls)
+	for _, video := range t.getLibrary().Videos {
+		if !slices.Contains(request.Ids, video.Id) || !t.canWatch(r, video) {
+			continue
 		}
-		if !exists {
-			// Generate Hash
-			hash := sha1.New()
-			hash.Write([]byte(gallery + secretKey))
-			secretKey := base64.URLEncoding.EncodeToString(hash.Sum(nil))[0:4]
-
-			galleries = append(galleries, Gallery{
-				Name:     gallery,
-				Category: category,
-				Stub:     "/gallery/" + secretKey,
-				Videos:   []Video{video},
-			})
+		videoUrl, err := t.signUrl(video.VideoPath)
+		if err != nil {
+			panic(err)
+		}
+		signedUrls := SignedUrls{Url: videoUrl}
+		if video.ThumbnailPath != "" {
+			thumbnailUrl, err := t.signUrl(video.ThumbnailPath)
+			if err != nil {
+				panic(err)
+			}
+			signedUrls.Thumbnail = &thumbnailUrl
 		}
+		urls[video.Id] = signedUrls
+	}
+
+	// Convert to JSON
+	jsonString, err := json.Marshal(urls)
+	if err != nil {
+		panic(err)
+	}
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
 	}
-	return galleries
 }
 
-var videoCache = cache.New(5*time.Minute, 10*time.Minute)
+func (t *Tenant) suggestHandler(w http.ResponseWriter, r *http.Request) {
+	suggestions := t.getSuggestIndex().suggest(r.URL.Query().Get("q"), 10)
+	if isSignedGalleryLinks() {
+		for i, suggestion := range suggestions {
+			gallery := Gallery{Name: suggestion.Gallery, Category: suggestion.Category, Stub: suggestion.Href}
+			suggestions[i].Href = t.signGalleryLink(gallery, time.Now().Add(24*time.Hour))
+		}
+	}
 
-func getVideos() []Video {
-	// Check if Videos are cached
-	if cachedVideos, found := videoCache.Get("videos"); found {
-		log.Println("Using Cached Videos")
-		return cachedVideos.([]Video)
+	// Convert to JSON
+	jsonString, err := json.Marshal(suggestions)
+	if err != nil {
+		panic(err)
 	}
-	log.Println("Getting Videos")
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
+	if err != nil {
+		return
+	}
+}
 
-	// Get Environment Variables
-	bucketName := os.Getenv("BUCKET_NAME")
-	if bucketName == "" {
-		panic("BUCKET_NAME not set")
+func (t *Tenant) galleryApiHandler(w http.ResponseWriter, r *http.Request) {
+	switch path.Base(r.URL.Path) {
+	case "next":
+		t.nextVideosHandler(w, r)
+	case "videos":
+		t.galleryVideosHandler(w, r)
+	default:
+		errorHandler(w, r, http.StatusNotFound, "Page not found")
 	}
+}
 
-	// Initialize Cloud Storage
-	storageClient, err := storage.NewClient(context.Background())
-	if err != nil {
-		log.Fatal(err)
+// getApiGallery finds the gallery of an /api/gallery/{stub}/{action} request, writing the error when it can't
+func (t *Tenant) getApiGallery(w http.ResponseWriter, r *http.Request, action string) (Gallery, bool) {
+	stub, found := strings.CutSuffix(strings.TrimPrefix(r.URL.Path, "/api"), "/"+action)
+	gallery, err := t.getGallery(stub)
+	if !found || err != nil || isCategoryAdminOnly(gallery.Category) {
+		errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+		return Gallery{}, false
 	}
+	if err := t.verifyGallerySignature(gallery, r.URL.Query()); err != nil {
+		errorHandler(w, r, http.StatusForbidden, err.Error())
+		return Gallery{}, false
+	}
+	gallery.Videos = withoutHidden(gallery.Videos)
+	return gallery, true
+}
 
-	bucket := storageClient.Bucket(bucketName)
-	files := bucket.Objects(context.Background(), nil)
-	videosMap := make(map[string]Video)
+const galleryPageSize = 60
 
-	// Allowed Extensions
-	videoExtensions := []string{".mp4", ".m4v", ".webm", ".mov", ".avi"}
-	imageExtensions := []string{".jpg", ".jpeg", ".png"}
-	extensionRegex, _ := regexp.Compile(`\.[a-zA-Z0-9]+$`)
+func (t *Tenant) galleryVideosHandler(w http.ResponseWriter, r *http.Request) {
+	gallery, ok := t.getApiGallery(w, r, "videos")
+	if !ok {
+		return
+	}
+	galleryKey := gallery.Category + "/" + gallery.Name
+	if _, protected := t.getMetadata().Passwords[galleryKey]; protected && !t.hasAccess(r, galleryKey) {
+		errorHandler(w, r, http.StatusForbidden, "This gallery needs a password")
+		return
+	}
+	query := r.URL.Query()
 
-	// Iterate through videos
-	for {
-		file, err := files.Next()
-		if errors.Is(err, iterator.Done) {
-			break
+	limit := galleryPageSize
+	if value := query.Get("limit"); value != "" {
+		var err error
+		limit, err = strconv.Atoi(value)
+		if err != nil || limit <= 0 || limit > 200 {
+			errorHandler(w, r, http.StatusBadRequest, "limit must be between 1 and 200")
+			return
 		}
+	}
+
+	// Start after the cursor's place in the sort order
+	order := query.Get("sort")
+	sortVideos(gallery.Videos, order)
+	start := 0
+	if value := query.Get("cursor"); value != "" {
+		cursor, err := decodeCursor(value)
 		if err != nil {
-			log.Fatal(err)
+			errorHandler(w, r, http.StatusBadRequest, "Invalid cursor")
+			return
 		}
-
-		parts := strings.Split(file.Name, "/")
-		if len(parts) == 3 && parts[2] != "" {
-			category := parts[0]
-			gallery := parts[1]
-			filename := parts[2]
-			// Create Signed 24 Hour URL
-			signedUrl, err := bucket.SignedURL(file.Name, &storage.SignedURLOptions{
-				Expires: time.Now().Add(24 * time.Hour),
-				Method:  "GET",
-			})
-			if err != nil {
-				log.Fatal(err)
-			}
-			// Remove extension from filename
-			fileBase := extensionRegex.ReplaceAll([]byte(filename), []byte(""))
-
-			// If Video doesn't exist
-			if _, ok := videosMap[string(fileBase)]; !ok {
-				videosMap[string(fileBase)] = Video{
-					Name:     string(fileBase),
-					Category: category,
-					Gallery:  gallery,
-				}
-			}
-
-			// Check if video already exists
-			if video, ok := videosMap[string(fileBase)]; ok {
-				for _, extension := range videoExtensions {
-					if strings.HasSuffix(filename, extension) {
-						videosMap[string(fileBase)] = Video{
-							Name:      video.Name,
-							Category:  video.Category,
-							Gallery:   video.Gallery,
-							Url:       signedUrl,
-							Thumbnail: video.Thumbnail,
-						}
-					}
-				}
-				for _, extension := range imageExtensions {
-					if strings.HasSuffix(filename, extension) {
-						videosMap[string(fileBase)] = Video{
-							Name:      video.Name,
-							Category:  video.Category,
-							Gallery:   video.Gallery,
-							Url:       video.Url,
-							Thumbnail: &signedUrl,
-						}
-					}
-				}
+		start = len(gallery.Videos)
+		for i, video := range gallery.Videos {
+			if compareVideos(order, cursor, getVideoCursor(video)) < 0 {
+				start = i
+				break
 			}
 		}
 	}
-	// Convert Map to Array
-	var videos []Video
-	for _, video := range videosMap {
-		videos = append(videos, video)
-	}
+	end := min(start+limit, len(gallery.Videos))
+	videos := gallery.Videos[start:end]
+	t.selectRenditions(r, videos)
+	t.lockVideos(r, videos)
 
-	// Cache Videos
-	videoCache.Set("videos", videos, cache.DefaultExpiration)
-	return videos
-}
+	page := VideoPage{Videos: []PageVideo{}}
+	if end < len(gallery.Videos) {
+		page.NextCursor = encodeCursor(gallery.Videos[end-1])
+	}
+	for _, video := range videos {
+		pageVideo := PageVideo{
+			Id:        video.Id,
+			Name:      video.Name,
+			Url:       video.Url,
+			Thumbnail: video.ThumbnailSmall,
+			MediaType: video.MediaType,
+			Playable:  video.Playable,
+			Locked:    video.Locked,
+			Preview:   video.Preview,
+			Rendition: video.Rendition,
 
-func galleryHandler(w http.ResponseWriter, _ *http.Request) {
-	log.Println("Generating Index")
+			DurationDisplay: video.DurationDisplay,
+			Resolution:      video.Resolution,
+		}
+		for _, chapter := range video.Chapters {
+			pageVideo.Chapters = append(pageVideo.Chapters, PageChapter{Title: chapter.Title, Url: chapter.Url})
+		}
+		page.Videos = append(page.Videos, pageVideo)
+	}
 
-	template, err := pug.CompileFile("./views/index.pug", pug.Options{})
+	// Convert to JSON
+	jsonString, err := json.Marshal(page)
 	if err != nil {
 		panic(err)
 	}
-
-	err = template.Execute(w, Index{
-		Categories: getCategories(),
-	})
-
+	// Write JSON
+	w.Header().Set("Content-Type", "application/json")
+	_, err = w.Write(jsonString)
 	if err != nil {
-		panic(err)
+		return
 	}
 }
 
-func feedHandler(w http.ResponseWriter, _ *http.Request) {
-	log.Println("Generating Feed")
+func (t *Tenant) nextVideosHandler(w http.ResponseWriter, r *http.Request) {
+	gallery, ok := t.getApiGallery(w, r, "next")
+	if !ok {
+		return
+	}
+	query := r.URL.Query()
+
+	var err error
+	count := 3
+	if value := query.Get("count"); value != "" {
+		count, err = strconv.Atoi(value)
+		if err != nil || count <= 0 || count > 20 {
+			errorHandler(w, r, http.StatusBadRequest, "count must be between 1 and 20")
+			return
+		}
+	}
+
+	// Continue after the given video in the order the page shows
+	sortVideos(gallery.Videos, query.Get("sort"))
+	start := 0
+	if after := query.Get("after"); after != "" {
+		index := slices.IndexFunc(gallery.Videos, func(video Video) bool { return video.Id == after })
+		if index < 0 {
+			errorHandler(w, r, http.StatusNotFound, "Video not found")
+			return
+		}
+		start = index + 1
+	}
 
-	galleries := getGalleries()
+	next := []NextVideo{}
+	for _, video := range gallery.Videos[start:] {
+		if len(next) == count {
+			break
+		}
+		if video.IsImage() || !t.canWatch(r, video) {
+			continue
+		}
+		videoUrl := getPlaylistUrl(video)
+		if kind, videoPath := getRendition(r, video); kind != renditionHls {
+			var err error
+			videoUrl, err = t.signUrl(videoPath)
+			if err != nil {
+				panic(err)
+			}
+		}
+		nextVideo := NextVideo{Id: video.Id, Name: video.Name, Url: videoUrl}
+		if video.SubtitlesPath != "" {
+			nextVideo.Subtitles = "/subtitles/" + video.Id
+		}
+		if video.ThumbnailPath != "" {
+			thumbnailUrl, err := t.signUrl(video.ThumbnailPath)
+			if err != nil {
+				panic(err)
+			}
+			nextVideo.Thumbnail = &thumbnailUrl
+		}
+		next = append(next, nextVideo)
+	}
 
 	// Convert to JSON
-	jsonString, err := json.Marshal(galleries)
+	jsonString, err := json.Marshal(next)
 	if err != nil {
 		panic(err)
 	}
@@ -252,42 +9558,337 @@ func feedHandler(w http.ResponseWriter, _ *http.Request) {
 	}
 }
 
-func pageHandler(w http.ResponseWriter, r *http.Request) {
-	// Get path
-	path := r.URL.String()
+// Closed when the server shuts down, so event streams end instead of holding it up
+var shutdownStreams = make(chan struct{})
 
-	gallery, err := getGallery(path)
-	if err != nil {
-		log.Println("Gallery not found: " + path)
-		http.NotFound(w, r)
+// eventsHandler streams library changes as server-sent events, for one gallery or every public one
+func (t *Tenant) eventsHandler(w http.ResponseWriter, r *http.Request) {
+	query := r.URL.Query()
+	var gallery *Gallery
+	if stub := query.Get("gallery"); stub != "" {
+		found, err := t.getGallery("/gallery/" + stub)
+		if err != nil || isCategoryAdminOnly(found.Category) {
+			errorHandler(w, r, http.StatusNotFound, "Gallery not found")
+			return
+		}
+		if err := t.verifyGallerySignature(found, query); err != nil {
+			errorHandler(w, r, http.StatusForbidden, err.Error())
+			return
+		}
+		galleryKey := found.Category + "/" + found.Name
+		if _, protected := t.getMetadata().Passwords[galleryKey]; protected && !t.hasAccess(r, galleryKey) {
+			errorHandler(w, r, http.StatusForbidden, "This gallery needs a password")
+			return
+		}
+		if !t.passesGate(r, t.getMetadata(), found.Category, found.Name) {
+			errorHandler(w, r, http.StatusForbidden, "This gallery needs to be unlocked")
+			return
+		}
+		gallery = &found
+	} else if isSignedGalleryLinks() {
+		errorHandler(w, r, http.StatusForbidden, "Events can only be followed for a gallery")
 		return
 	}
-	log.Println("Generating Gallery Page: " + path)
 
-	template, err := pug.CompileFile("./views/gallery.pug", pug.Options{})
-	if err != nil {
-		panic(err)
+	w.Header().Set("Content-Type", "text/event-stream")
+	w.Header().Set("Cache-Control", "no-cache")
+	flusher, _ := w.(http.Flusher)
+	flush := func() {
+		if flusher != nil {
+			flusher.Flush()
+		}
 	}
+	_, _ = fmt.Fprint(w, ": connected\n\n")
+	flush()
 
-	err = template.Execute(w, gallery)
-	if err != nil {
-		panic(err)
+	subscription := t.subscribeEvents()
+	defer t.unsubscribeEvents(subscription)
+
+	// Comments keep proxies from closing an idle stream
+	keepAlive := time.NewTicker(30 * time.Second)
+	defer keepAlive.Stop()
+	for {
+		select {
+		case <-r.Context().Done():
+			return
+		case <-shutdownStreams:
+			return
+		case <-keepAlive.C:
+			_, _ = fmt.Fprint(w, ": keep-alive\n\n")
+			flush()
+		case events := <-subscription:
+			metadata := t.getMetadata()
+			for _, event := range events {
+				if gallery != nil && (event.Category != gallery.Category || event.Gallery != gallery.Name) {
+					continue
+				}
+				// Hidden videos aren't announced, not even to their own gallery's page
+				video := Video{Category: event.Category, Gallery: event.Gallery, Name: event.Video}
+				if event.Video != "" && slices.Contains(metadata.HiddenVideos, video.Key()) {
+					continue
+				}
+
+				// The stream for every gallery leaves out what the index doesn't show
+				if gallery == nil {
+					if isCategoryHidden(event.Category) || getProtection(metadata, video) != "" || !t.passesGate(r, metadata, event.Category, event.Gallery) {
+						continue
+					}
+				}
+				jsonString, err := json.Marshal(GalleryEvent{
+					Event:    event.Event,
+					Category: event.Category,
+					Gallery:  event.Gallery,
+					Video:    event.Video,
+				})
+				if err != nil {
+					panic(err)
+				}
+				_, _ = fmt.Fprintf(w, "event: %s\ndata: %s\n\n", event.Event, jsonString)
+			}
+			flush()
+		}
+	}
+}
+
+func (t *Tenant) registerRoutes(mux *http.ServeMux) {
+	log.Println("Starting " + t.Host + " with Key: " + t.SecretKey)
+
+	// Hostname patterns take precedence over the default tenant
+	prefix := t.Host + "/" + t.SecretKey
+	// Public routes are taken offline during maintenance, the secret key and token routes keep working
+	mux.HandleFunc(t.Host+"/gallery/", t.unlessMaintenance(t.requireGallerySignature(t.pageHandler)))
+	mux.HandleFunc(t.Host+"/api/views/", t.unlessMaintenance(t.viewsHandler))
+	mux.HandleFunc(t.Host+"/api/analytics", t.unlessMaintenance(t.analyticsHandler))
+	mux.HandleFunc(t.Host+"/thumb/", t.unlessMaintenance(t.thumbnailHandler))
+	mux.HandleFunc(t.Host+"/og/", t.unlessMaintenance(t.previewImageHandler))
+	mux.HandleFunc(t.Host+"/s/", t.unlessMaintenance(t.shortLinkHandler))
+	mux.HandleFunc(t.Host+"/video/", t.unlessMaintenance(t.videoHandler))
+	mux.HandleFunc(t.Host+"/hls/", t.unlessMaintenance(t.playlistHandler))
+	mux.HandleFunc(t.Host+"/stream/transcode/", t.unlessMaintenance(t.transcodeHandler))
+	mux.HandleFunc(t.Host+"/subtitles/", t.unlessMaintenance(t.subtitlesHandler))
+	mux.HandleFunc(t.Host+"/api/urls/refresh", t.unlessMaintenance(t.refreshUrlsHandler))
+	mux.HandleFunc(t.Host+"/api/gallery/", t.unlessMaintenance(t.galleryApiHandler))
+	mux.HandleFunc(t.Host+"/collection/", t.unlessMaintenance(t.collectionHandler))
+	mux.HandleFunc(t.Host+"/api/events", t.unlessMaintenance(t.eventsHandler))
+	mux.HandleFunc(prefix+"/index", t.galleryHandler)
+	mux.HandleFunc(prefix+"/feed", t.feedHandler)
+	mux.HandleFunc(prefix+"/feed/", t.categoryFeedHandler)
+	mux.HandleFunc(prefix+"/feed.json", t.jsonFeedHandler)
+	mux.HandleFunc(prefix+"/export/strm", t.strmExportHandler)
+	mux.HandleFunc(prefix+"/tag/", t.tagHandler)
+	mux.HandleFunc(prefix+"/catalog", t.catalogHandler)
+	mux.HandleFunc(prefix+"/stats", t.statsHandler)
+	mux.HandleFunc(prefix+"/metrics.json", t.metricsHandler)
+	mux.HandleFunc(prefix+"/suggest", t.suggestHandler)
+	mux.HandleFunc(prefix+"/catalog/", t.catalogHandler)
+
+	// Category keys only open the index and feed of their own category
+	for category, key := range getCategoryKeys() {
+		if key == "" || key == t.SecretKey || isCategoryAdminOnly(category) {
+			log.Println("Skipping category key for " + category + " on " + t.Host)
+			continue
+		}
+		mux.HandleFunc(t.Host+"/"+key+"/index", t.unlessMaintenance(t.categoryIndexHandler(category)))
+		mux.HandleFunc(t.Host+"/"+key+"/feed", t.unlessMaintenance(t.categoryKeyFeedHandler(category)))
+	}
+
+	// Token authenticated routes for scripts
+	api := t.Host + "/api"
+	mux.HandleFunc(api+"/feed", t.requireToken(scopeRead, t.feedHandler))
+	mux.HandleFunc(api+"/feed.json", t.requireToken(scopeRead, t.jsonFeedHandler))
+	mux.HandleFunc(api+"/stats", t.requireToken(scopeRead, t.statsHandler))
+	mux.HandleFunc(api+"/metrics.json", t.requireToken(scopeRead, t.metricsHandler))
+	mux.HandleFunc(api+"/suggest", t.requireToken(scopeRead, t.suggestHandler))
+
+	// Admin and mutation routes are left out, so they fall through to not found
+	if isReadOnly() {
+		log.Println("Read only, admin routes disabled for " + t.Host)
+		return
+	}
+	mux.HandleFunc(prefix+"/cover", t.coverHandler)
+	mux.HandleFunc(prefix+"/share", t.shareHandler)
+	mux.HandleFunc(prefix+"/tokens", t.tokensHandler)
+	mux.HandleFunc(prefix+"/shorten", t.shortenHandler)
+	mux.HandleFunc(prefix+"/tags", t.tagsHandler)
+	mux.HandleFunc(prefix+"/password", t.passwordHandler)
+	mux.HandleFunc(prefix+"/upload", t.uploadHandler)
+	mux.HandleFunc(prefix+"/upload/finalize", t.finalizeUploadHandler)
+	mux.HandleFunc(prefix+"/galleries", t.galleriesHandler)
+	mux.HandleFunc(prefix+"/orphans", t.orphansHandler)
+	mux.HandleFunc(prefix+"/verify", t.verifyHandler)
+	mux.HandleFunc(prefix+"/audit", t.auditHandler)
+	mux.HandleFunc(prefix+"/coverage", t.coverageHandler)
+	mux.HandleFunc(prefix+"/analytics", t.analyticsReportHandler)
+	mux.HandleFunc(prefix+"/archive", t.archiveHandler)
+	mux.HandleFunc(prefix+"/collections", t.collectionsHandler)
+	mux.HandleFunc(prefix+"/digest", t.digestHandler)
+	mux.HandleFunc(prefix+"/contact-sheet", t.contactSheetHandler)
+	mux.HandleFunc(prefix+"/order", t.orderHandler)
+	mux.HandleFunc(prefix+"/subtitles", t.subtitlesJobHandler)
+	mux.HandleFunc(prefix+"/previews", t.previewsHandler)
+	mux.HandleFunc(prefix+"/clip", t.clipHandler)
+	mux.HandleFunc(prefix+"/rotate", t.rotateHandler)
+	mux.HandleFunc(prefix+"/thumbnails", t.thumbnailsHandler)
+	mux.HandleFunc(prefix+"/jobs/", t.jobHandler)
+	mux.HandleFunc(prefix+"/hidden", t.hiddenHandler)
+	mux.HandleFunc(prefix+"/versions", t.versionsHandler)
+	mux.HandleFunc(prefix+"/metadata", t.metadataHandler)
+	mux.HandleFunc(prefix+"/ratings", t.ratingsHandler)
+	mux.HandleFunc(prefix+"/config", t.configHandler)
+	mux.HandleFunc(prefix+"/config/reload", t.reloadHandler)
+	mux.HandleFunc(prefix+"/debug/render", t.debugRenderHandler)
+	mux.HandleFunc(prefix+"/maintenance", t.maintenanceHandler)
+	mux.HandleFunc(api+"/cover", t.requireRole(scopeEditor, t.coverHandler))
+	mux.HandleFunc(api+"/tags", t.requireRole(scopeEditor, t.tagsHandler))
+	mux.HandleFunc(api+"/password", t.requireRole(scopeAdmin, t.passwordHandler))
+	mux.HandleFunc(api+"/share", t.requireRole(scopeAdmin, t.shareHandler))
+	mux.HandleFunc(api+"/shorten", t.requireRole(scopeAdmin, t.shortenHandler))
+	mux.HandleFunc(api+"/upload", t.requireRole(scopeAdmin, t.uploadHandler))
+	mux.HandleFunc(api+"/upload/finalize", t.requireRole(scopeAdmin, t.finalizeUploadHandler))
+	mux.HandleFunc(api+"/galleries", t.requireRole(scopeAdmin, t.galleriesHandler))
+	mux.HandleFunc(api+"/orphans", t.requireRole(scopeAdmin, t.orphansHandler))
+	mux.HandleFunc(api+"/audit", t.requireRole(scopeAdmin, t.auditHandler))
+	mux.HandleFunc(api+"/archive", t.requireRole(scopeAdmin, t.archiveHandler))
+	mux.HandleFunc(api+"/collections", t.requireRole(scopeEditor, t.collectionsHandler))
+	mux.HandleFunc(api+"/contact-sheet", t.requireRole(scopeEditor, t.contactSheetHandler))
+	mux.HandleFunc(api+"/order", t.requireRole(scopeEditor, t.orderHandler))
+	mux.HandleFunc(api+"/subtitles", t.requireRole(scopeEditor, t.subtitlesJobHandler))
+	mux.HandleFunc(api+"/previews", t.requireRole(scopeEditor, t.previewsHandler))
+	mux.HandleFunc(api+"/clip", t.requireRole(scopeAdmin, t.clipHandler))
+	mux.HandleFunc(api+"/rotate", t.requireRole(scopeAdmin, t.rotateHandler))
+	mux.HandleFunc(api+"/thumbnails", t.requireRole(scopeEditor, t.thumbnailsHandler))
+	mux.HandleFunc(api+"/jobs/", t.requireRole(scopeEditor, t.jobHandler))
+	mux.HandleFunc(api+"/hidden", t.requireRole(scopeEditor, t.hiddenHandler))
+	mux.HandleFunc(api+"/versions", t.requireRole(scopeAdmin, t.versionsHandler))
+	mux.HandleFunc(api+"/metadata", t.requireRole(scopeEditor, t.metadataHandler))
+	mux.HandleFunc(api+"/ratings", t.requireRole(scopeAdmin, t.ratingsHandler))
+	mux.HandleFunc(api+"/maintenance", t.requireRole(scopeAdmin, t.maintenanceHandler))
+}
+
+const commandUsage = `Usage: video-gallery [-host HOSTNAME] COMMAND
+
+Commands:
+  list categories              List the categories
+  list galleries               List the galleries with their stubs
+  list videos                  List every video with its id
+  stats                        Print the library statistics
+  cover GALLERY_STUB VIDEO     Use a video's thumbnail as the gallery cover
+
+Without a command the web server is started.
+`
+
+func runCommand(tenants []*Tenant, args []string) {
+	flags := flag.NewFlagSet("video-gallery", flag.ExitOnError)
+	flags.Usage = func() {
+		fmt.Fprint(flags.Output(), commandUsage)
+		flags.PrintDefaults()
+	}
+	host := flags.String("host", "", "hostname of the tenant to use, defaults to BUCKET_NAME")
+	_ = flags.Parse(args)
+
+	// Pick the tenant
+	var tenant *Tenant
+	for _, t := range tenants {
+		if t.Host == *host {
+			tenant = t
+		}
+	}
+	if tenant == nil {
+		log.Fatal("No tenant for host: " + *host)
+	}
+
+	command := strings.Join(flags.Args(), " ")
+	switch {
+	case command == "list categories":
+		for _, category := range tenant.getCategories() {
+			fmt.Printf("%s\t%d galleries\n", category.Name, len(category.Galleries))
+		}
+	case command == "list galleries":
+		for _, gallery := range tenant.getGalleries() {
+			fmt.Printf("%s\t%s/%s\t%d videos\n", gallery.Stub, gallery.Category, gallery.Name, len(gallery.Videos))
+		}
+	case command == "list videos":
+		for _, video := range tenant.getLibrary().Videos {
+			fmt.Printf("%s\t%s\n", video.Id, video.Key())
+		}
+	case command == "stats":
+		jsonString, err := json.MarshalIndent(tenant.getStats(), "", "  ")
+		if err != nil {
+			log.Fatal(err)
+		}
+		fmt.Println(string(jsonString))
+	case flags.Arg(0) == "cover" && flags.NArg() == 3:
+		gallery, err := tenant.getGallery("/gallery/" + strings.TrimPrefix(flags.Arg(1), "/gallery/"))
+		if err != nil {
+			log.Fatal(err)
+		}
+		if err := tenant.setCover(context.Background(), gallery, flags.Arg(2), "cli"); err != nil {
+			log.Fatal(err)
+		}
+	default:
+		flags.Usage()
+		os.Exit(2)
 	}
 }
 
 func main() {
-	secretKey := os.Getenv("SECRET_KEY")
-	if secretKey == "" {
-		panic("SECRET_KEY not set")
+	if err := loadConfigFile(); err != nil {
+		log.Fatal("Invalid CONFIG_FILE: " + err.Error())
+	}
+	if problems := validateConfig(); len(problems) > 0 {
+		for _, problem := range problems {
+			log.Println("Invalid configuration: " + problem.Error())
+		}
+		log.Fatalf("Found %d configuration problems", len(problems))
+	}
+	thumbnailSemaphore = make(chan struct{}, getConcurrency("THUMBNAIL_CONCURRENCY", runtime.NumCPU()))
+	if maxWidth := getConcurrency("THUMBNAIL_MAX_WIDTH", 0); maxWidth > 0 {
+		limitThumbnailWidths(maxWidth)
+	}
+
+	// Deployments without the views directory use the templates built into the binary
+	if dir := os.Getenv("VIEWS_DIR"); dir != "" {
+		viewsDir = dir
+	}
+	if _, err := os.Stat(viewsDir); err != nil {
+		dir, err := extractViews()
+		if err != nil {
+			log.Fatal("Unable to extract templates: " + err.Error())
+		}
+		log.Println("Templates not found in " + viewsDir + ", using the built in ones")
+		viewsDir = dir
+	}
+
+	tenants := getTenants()
+	validateStorage(tenants)
+
+	// Run a maintenance command instead of the server when given one
+	if len(os.Args) > 1 {
+		runCommand(tenants, os.Args[1:])
+		return
 	}
-	log.Println("Starting with Key: " + secretKey)
 
 	// Service
-	fileServer := http.FileServer(http.Dir("./public"))
-	http.Handle("/", fileServer)
-	http.HandleFunc("/gallery/", pageHandler)
-	http.HandleFunc("/"+secretKey+"/index", galleryHandler)
-	http.HandleFunc("/"+secretKey+"/feed", feedHandler)
+	sweepJobDirs()
+	logFfmpeg()
+	http.HandleFunc("/", publicHandler())
+	for _, tenant := range tenants {
+		if isManifestCache() {
+			tenant.loadManifest()
+		}
+		tenant.registerRoutes(http.DefaultServeMux)
+		go tenant.flushViews(30 * time.Second)
+		if isRefreshAhead() {
+			go tenant.refreshAhead()
+		}
+		go tenant.sendDigests(time.Hour)
+	}
+	http.HandleFunc("/health", healthHandler)
+	http.HandleFunc("/health/ready", readyHandler(tenants))
+	requestMetrics.started = time.Now()
+	if project := os.Getenv("MONITORING_PROJECT"); project != "" {
+		go pushMetrics(tenants, project, time.Duration(getConcurrency("MONITORING_INTERVAL_SECS", 60))*time.Second)
+	}
 
 	// Read Environment Variables
 	port := os.Getenv("PORT")
@@ -295,8 +9896,38 @@ func main() {
 		port = "8080"
 	}
 
-	fmt.Printf("Starting server at port " + port + "\n")
-	if err := http.ListenAndServe(":"+port, nil); err != nil {
-		log.Fatal(err)
+	server := &http.Server{
+		Addr:    ":" + port,
+		Handler: countRequests(recoverHandler(http.DefaultServeMux)),
+	}
+	server.RegisterOnShutdown(func() {
+		close(shutdownStreams)
+	})
+	go func() {
+		fmt.Printf("Starting server at port " + port + "\n")
+		if err := server.ListenAndServe(); err != nil && !errors.Is(err, http.ErrServerClosed) {
+			log.Fatal(err)
+		}
+	}()
+
+	// SIGHUP reloads CONFIG_FILE
+	reloads := make(chan os.Signal, 1)
+	signal.Notify(reloads, syscall.SIGHUP)
+	go logReloads(reloads)
+
+	// Cloud Run sends SIGTERM before stopping the container
+	ctx, stop := signal.NotifyContext(context.Background(), syscall.SIGTERM, os.Interrupt)
+	defer stop()
+	<-ctx.Done()
+	log.Println("Shutting Down")
+
+	// Finish in-flight requests, then save anything still pending
+	shutdownCtx, cancel := context.WithTimeout(context.Background(), 10*time.Second)
+	defer cancel()
+	if err := server.Shutdown(shutdownCtx); err != nil {
+		log.Println("Failed to shut down cleanly: " + err.Error())
+	}
+	for _, tenant := range tenants {
+		tenant.saveViews(shutdownCtx)
 	}
 }
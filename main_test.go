@@ -0,0 +1,318 @@
+package main
+
+import (
+	"encoding/binary"
+	"hash/crc32"
+	"image"
+	"image/color"
+	"slices"
+	"testing"
+	"time"
+
+	"cloud.google.com/go/storage"
+)
+
+func TestCompareNames(t *testing.T) {
+	t.Setenv("SORT_LOCALE", "")
+
+	tests := []struct {
+		name  string
+		a     string
+		b     string
+		order int
+	}{
+		{"equal", "Holiday", "Holiday", 0},
+		{"case", "apple", "Banana", -1},
+		{"accent after plain letter", "Eclair", "Éclair", -1},
+		{"accent before next letter", "Éclair", "Fig", -1},
+		{"accent before last letter", "Ångström", "Zulu", -1},
+		{"accent inside word", "Café Nights", "Cafeteria", -1},
+		{"greek", "Αθήνα", "Βόλος", -1},
+		{"cyrillic", "Киев", "Москва", -1},
+		{"greek before cyrillic", "Ωμέγα", "Азбука", -1},
+		{"latin before greek", "Zebra", "Αλφα", -1},
+		{"cyrillic before arabic", "Москва", "القاهرة", -1},
+		{"latin before han", "Zürich", "東京", -1},
+		{"digits before han", "Tokyo 2020", "Tokyo 東京", -1},
+		{"digits before letters", "2 Fast", "Alpha", -1},
+		{"numbers by value", "Part 2", "Part 10", -1},
+		{"numbers by value with more digits", "Part 99", "Part 100", -1},
+		{"numbers inside names", "Day 9 Beach", "Day 10 Arrival", -1},
+		{"numbers in other scripts", "Часть 2", "Часть 10", -1},
+	}
+	for _, test := range tests {
+		t.Run(test.name, func(t *testing.T) {
+			if order := sign(compareNames(test.a, test.b)); order != test.order {
+				t.Errorf("compareNames(%q, %q) = %d, want %d", test.a, test.b, order, test.order)
+			}
+			if order := sign(compareNames(test.b, test.a)); order != -test.order {
+				t.Errorf("compareNames(%q, %q) = %d, want %d", test.b, test.a, order, -test.order)
+			}
+		})
+	}
+}
+
+func sign(order int) int {
+	switch {
+	case order < 0:
+		return -1
+	case order > 0:
+		return 1
+	}
+	return 0
+}
+
+func TestParseChapters(t *testing.T) {
+	tests := []struct {
+		name     string
+		content  string
+		chapters []Chapter
+	}{
+		{"empty", "", nil},
+		{"plain lines", "00:00 Intro\n01:30 Ceremony\n1:02:03.5 Party", []Chapter{
+			{Start: 0, Title: "Intro"},
+			{Start: 90, Title: "Ceremony"},
+			{Start: 3723.5, Title: "Party"},
+		}},
+		{"windows line endings", "00:00 Intro\r\n00:10 Cake\r\n", []Chapter{
+			{Start: 0, Title: "Intro"},
+			{Start: 10, Title: "Cake"},
+		}},
+		{"webvtt", "WEBVTT\n\n00:00:00.000 --> 00:01:00.000\nIntro\n\n00:01:00.000 --> 00:02:00.000\nCake\n", []Chapter{
+			{Start: 0, Title: "Intro"},
+			{Start: 60, Title: "Cake"},
+		}},
+		{"webvtt cue without title", "WEBVTT\n\n00:00:00.000 --> 00:01:00.000\n\n00:01:00.000 --> 00:02:00.000\nCake", []Chapter{
+			{Start: 60, Title: "Cake"},
+		}},
+		{"webvtt cue at the end", "WEBVTT\n\n00:00:00.000 --> 00:01:00.000", nil},
+		{"out of order", "02:00 Dance\n00:00 Intro\n01:00 Cake", []Chapter{
+			{Start: 0, Title: "Intro"},
+			{Start: 60, Title: "Cake"},
+			{Start: 120, Title: "Dance"},
+		}},
+		{"equal starts keep their order", "01:00 First\n00:00 Intro\n01:00 Second", []Chapter{
+			{Start: 0, Title: "Intro"},
+			{Start: 60, Title: "First"},
+			{Start: 60, Title: "Second"},
+		}},
+		{"malformed lines", "Intro\n12 Seconds\nab:cd Title\n1:2:3:4 Too long\n00:30\n-01:00 Negative\nNaN:00 Not a number\nInf:00 Infinite\n00:-5 Negative seconds\n\n00:45 Kept", []Chapter{
+			{Start: 45, Title: "Kept"},
+		}},
+		{"binary", "\x00\xff\xfe --> \x00\n\xff", nil},
+	}
+	for _, test := range tests {
+		t.Run(test.name, func(t *testing.T) {
+			if chapters := parseChapters(test.content); !slices.Equal(chapters, test.chapters) {
+				t.Errorf("parseChapters(%q) = %v, want %v", test.content, chapters, test.chapters)
+			}
+		})
+	}
+}
+
+// exifTiff is a TIFF header with an EXIF directory holding a capture date at offset 44
+func exifTiff(order binary.ByteOrder, date string) []byte {
+	tiff := make([]byte, 44)
+	copy(tiff, "MM")
+	if order == binary.LittleEndian {
+		copy(tiff, "II")
+	}
+	order.PutUint16(tiff[2:], 42)
+	order.PutUint32(tiff[4:], 8)
+	// The first directory points to the EXIF directory
+	order.PutUint16(tiff[8:], 1)
+	order.PutUint16(tiff[10:], 0x8769)
+	order.PutUint16(tiff[12:], 4)
+	order.PutUint32(tiff[14:], 1)
+	order.PutUint32(tiff[18:], 26)
+	// The EXIF directory holds the capture date
+	order.PutUint16(tiff[26:], 1)
+	order.PutUint16(tiff[28:], 0x9003)
+	order.PutUint16(tiff[30:], 2)
+	order.PutUint32(tiff[32:], 20)
+	order.PutUint32(tiff[36:], 44)
+	return append(tiff, date+"\x00"...)
+}
+
+// exifJpeg wraps a TIFF header in an APP1 segment
+func exifJpeg(tiff []byte) []byte {
+	data := []byte{0xFF, 0xD8, 0xFF, 0xE1}
+	data = binary.BigEndian.AppendUint16(data, uint16(2+6+len(tiff)))
+	data = append(data, "Exif\x00\x00"...)
+	data = append(data, tiff...)
+	return append(data, 0xFF, 0xDA, 0x00, 0x02, 0xFF, 0xD9)
+}
+
+// patched is a big endian TIFF header with a 16 or 32 bit value replaced
+func patched(offset int, value uint32, bits int) []byte {
+	tiff := exifTiff(binary.BigEndian, "2021:06:15 14:30:00")
+	if bits == 16 {
+		binary.BigEndian.PutUint16(tiff[offset:], uint16(value))
+	} else {
+		binary.BigEndian.PutUint32(tiff[offset:], value)
+	}
+	return exifJpeg(tiff)
+}
+
+func TestReadExifDate(t *testing.T) {
+	taken := time.Date(2021, 6, 15, 14, 30, 0, 0, time.UTC)
+	valid := exifJpeg(exifTiff(binary.BigEndian, "2021:06:15 14:30:00"))
+	tests := []struct {
+		name  string
+		data  []byte
+		taken time.Time
+	}{
+		{"big endian", valid, taken},
+		{"little endian", exifJpeg(exifTiff(binary.LittleEndian, "2021:06:15 14:30:00")), taken},
+		{"after another segment", append([]byte{0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00}, valid[2:]...), taken},
+		{"segment longer than the file", append([]byte{0xFF, 0xD8, 0xFF, 0xE1, 0xFF, 0xFF}, valid[6:]...), taken},
+		{"entry count past the end", patched(8, 0xFFFF, 16), taken},
+		{"empty", nil, time.Time{}},
+		{"not a jpeg", []byte("GIF89a"), time.Time{}},
+		{"only the start of image", []byte{0xFF, 0xD8}, time.Time{}},
+		{"segment length below 2", append([]byte{0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x01}, valid[6:]...), time.Time{}},
+		{"after the start of scan", append([]byte{0xFF, 0xD8, 0xFF, 0xDA, 0x00, 0x02}, valid[2:]...), time.Time{}},
+		{"unknown byte order", exifJpeg(append([]byte("XX"), exifTiff(binary.BigEndian, "2021:06:15 14:30:00")[2:]...)), time.Time{}},
+		{"short header", exifJpeg([]byte("MM\x00\x2a")), time.Time{}},
+		{"first directory past the end", patched(4, 0xFFFFFFFF, 32), time.Time{}},
+		{"no EXIF directory", patched(10, 0x0110, 16), time.Time{}},
+		{"EXIF directory past the end", patched(18, 0xFFFFFFF0, 32), time.Time{}},
+		{"EXIF directory at the last byte", patched(18, 62, 32), time.Time{}},
+		{"date past the end", patched(36, 0xFFFFFFFF, 32), time.Time{}},
+		{"date overlapping the end", patched(36, 60, 32), time.Time{}},
+		{"date too short", patched(32, 4, 32), time.Time{}},
+		{"invalid date", exifJpeg(exifTiff(binary.BigEndian, "2021:13:45 99:99:99")), time.Time{}},
+	}
+	for _, test := range tests {
+		t.Run(test.name, func(t *testing.T) {
+			date, err := readExifDate(test.data)
+			if test.taken.IsZero() {
+				if err == nil {
+					t.Errorf("readExifDate() = %v, want an error", date)
+				}
+				return
+			}
+			if err != nil || !date.Equal(test.taken) {
+				t.Errorf("readExifDate() = %v, %v, want %v", date, err, test.taken)
+			}
+		})
+	}
+}
+
+func TestReadExifDateTruncated(t *testing.T) {
+	data := exifJpeg(exifTiff(binary.BigEndian, "2021:06:15 14:30:00"))
+	// The date ends 19 bytes into the TIFF header's offset 44
+	end := 2 + 4 + 6 + 44 + 19
+	for length := 0; length < end; length++ {
+		if date, err := readExifDate(data[:length]); err == nil {
+			t.Errorf("readExifDate() of the first %d bytes = %v, want an error", length, date)
+		}
+	}
+}
+
+func TestScoreFrame(t *testing.T) {
+	frame := func(shade func(x, y int) uint8) image.Image {
+		frame := image.NewGray(image.Rect(0, 0, 320, 180))
+		for y := 0; y < 180; y++ {
+			for x := 0; x < 320; x++ {
+				frame.SetGray(x, y, color.Gray{Y: shade(x, y)})
+			}
+		}
+		return frame
+	}
+	black := frame(func(x, y int) uint8 { return 0 })
+	dark := frame(func(x, y int) uint8 { return uint8((x / 20 % 2) * 30) })
+	fade := frame(func(x, y int) uint8 { return uint8(64 + x*128/320) })
+	detail := frame(func(x, y int) uint8 { return uint8(40 + (x/10+y/10)%2*160) })
+
+	// Each frame makes a better thumbnail than the one before
+	frames := []image.Image{black, dark, fade, detail}
+	for i := 1; i < len(frames); i++ {
+		if before, after := scoreFrame(frames[i-1]), scoreFrame(frames[i]); before >= after {
+			t.Errorf("frame %d scored %f, not below frame %d with %f", i-1, before, i, after)
+		}
+	}
+	if score := scoreFrame(image.NewGray(image.Rectangle{})); score != 0 {
+		t.Errorf("scoreFrame() of an empty frame = %f, want 0", score)
+	}
+}
+
+// box is an MP4 box with a 32 bit size, or a 64 bit size when size is 1
+func box(boxType string, size uint32, largeSize uint64, payload int) []byte {
+	data := binary.BigEndian.AppendUint32(nil, size)
+	data = append(data, boxType...)
+	if size == 1 {
+		data = binary.BigEndian.AppendUint64(data, largeSize)
+	}
+	return append(data, make([]byte, payload)...)
+}
+
+func TestVerifyBoxes(t *testing.T) {
+	join := func(boxes ...[]byte) []byte {
+		var data []byte
+		for _, box := range boxes {
+			data = append(data, box...)
+		}
+		return data
+	}
+	tests := []struct {
+		name  string
+		data  []byte
+		valid bool
+	}{
+		{"complete", join(box("ftyp", 16, 0, 8), box("moov", 24, 0, 16), box("mdat", 12, 0, 4)), true},
+		{"size 0 runs to the end", join(box("ftyp", 16, 0, 8), box("moov", 0, 0, 100)), true},
+		{"size 0 box after moov", join(box("moov", 8, 0, 0), box("mdat", 0, 0, 100)), true},
+		{"size 1 with a 64 bit size", join(box("ftyp", 16, 0, 8), box("mdat", 1, 40, 24), box("moov", 8, 0, 0)), true},
+		{"no moov", join(box("ftyp", 16, 0, 8), box("mdat", 12, 0, 4)), false},
+		{"empty", nil, false},
+		{"truncated header", join(box("moov", 8, 0, 0), []byte{0, 0, 0}), false},
+		{"truncated box", join(box("ftyp", 16, 0, 8), box("moov", 64, 0, 16)), false},
+		{"size below the header", join(box("moov", 8, 0, 0), box("free", 4, 0, 0)), false},
+		{"size 1 without the 64 bit size", join(box("moov", 8, 0, 0), box("mdat", 1, 0, 0)[:12]), false},
+		{"size 1 below its header", join(box("moov", 8, 0, 0), box("mdat", 1, 8, 0)), false},
+		{"size 1 past the end", join(box("moov", 8, 0, 0), box("mdat", 1, 1<<40, 8)), false},
+		{"size 1 past the int64 range", join(box("moov", 8, 0, 0), box("mdat", 1, 1<<63+8, 8)), false},
+		{"size 1 overflowing the offset", join(box("moov", 8, 0, 0), box("mdat", 1, 1<<63-1, 8)), false},
+		{"oversized", join(box("moov", 8, 0, 0), box("mdat", 0xFFFFFFFF, 0, 8)), false},
+	}
+	for _, test := range tests {
+		t.Run(test.name, func(t *testing.T) {
+			reads := 0
+			err := verifyBoxes(int64(len(test.data)), func(offset int64) ([]byte, error) {
+				reads++
+				if offset < 0 || offset > int64(len(test.data)) {
+					t.Fatalf("read at offset %d of %d bytes", offset, len(test.data))
+				}
+				return test.data[offset:min(offset+16, int64(len(test.data)))], nil
+			})
+			if test.valid != (err == nil) {
+				t.Errorf("verifyBoxes() = %v, want valid %v", err, test.valid)
+			}
+			if reads > 1000 {
+				t.Errorf("verifyBoxes() read %d box headers", reads)
+			}
+		})
+	}
+}
+
+func TestHashedThumbnailUrl(t *testing.T) {
+	t.Setenv("HASHED_THUMBNAILS", "true")
+	if !slices.Contains(listedAttrs, "CRC32C") {
+		t.Fatalf("listedAttrs = %v, want the CRC32C listed", listedAttrs)
+	}
+	thumbnailUrl := func(data string) string {
+		file := &storage.ObjectAttrs{
+			Name:   "Category/Gallery/Video.jpg",
+			CRC32C: crc32.Checksum([]byte(data), crc32.MakeTable(crc32.Castagnoli)),
+		}
+		return Video{Id: "abc", thumbnailHash: getThumbnailHash(file)}.thumbnailUrl(320)
+	}
+	first, second := thumbnailUrl("first thumbnail"), thumbnailUrl("second thumbnail")
+	if first == second {
+		t.Errorf("thumbnailUrl() = %q for both thumbnails", first)
+	}
+	if again := thumbnailUrl("first thumbnail"); again != first {
+		t.Errorf("thumbnailUrl() = %q, then %q for the same thumbnail", first, again)
+	}
+}
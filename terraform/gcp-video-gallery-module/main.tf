@@ -36,6 +36,11 @@ resource "google_cloud_run_service" "video_gallery" {
           value = random_string.gallery_key.result
         }
 
+        env {
+          name  = "TRUSTED_PROXY_HOPS"
+          value = "1"
+        }
+
         resources {
           limits = {
             cpu    = "1000m"